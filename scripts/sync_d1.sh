@@ -8,7 +8,7 @@ sql_path="data/index.sql"
 if [ ! -f "$db_path" ]; then
   echo "Database not found at path $db_path. Create first by running:"
   echo ""
-  ecoh "  cargo run --bin create_index --features=index -- client_portal.dat"
+  ecoh "  cargo run --bin create_index --features=index -- --release retail client_portal.dat client_cell_1.dat"
   echo ""
 
   exit 1
@@ -19,11 +19,21 @@ echo "DROP TABLE IF EXISTS database_types;" > "$sql_path"
 echo "DROP TABLE IF EXISTS file_types;" >> "$sql_path"
 echo "DROP TABLE IF EXISTS file_subtypes;" >> "$sql_path"
 echo "DROP TABLE IF EXISTS files;" >> "$sql_path"
+echo "DROP TABLE IF EXISTS releases;" >> "$sql_path"
+echo "DROP TABLE IF EXISTS release_dats;" >> "$sql_path"
+echo "DROP TABLE IF EXISTS files_search;" >> "$sql_path"
 echo "...done."
 
 echo "Using database $db_path."
 echo "Dumping database to $sql_path..."
-sqlite3 "$db_path" ".dump" | grep -v "^PRAGMA" | grep -v "BEGIN TRANSACTION" | grep -v "COMMIT" >> "$sql_path"
+sqlite3 "$db_path" ".dump database_types file_types file_subtypes files releases release_dats" | grep -v "^PRAGMA" | grep -v "BEGIN TRANSACTION" | grep -v "COMMIT" >> "$sql_path"
+echo "...done."
+
+# .dump writes FTS5 tables as their shadow tables via writable_schema, which
+# D1 doesn't allow, so recreate the search table and insert its rows instead
+echo "Dumping search table to $sql_path..."
+sqlite3 "$db_path" "SELECT sql || ';' FROM sqlite_master WHERE name = 'files_search'" >> "$sql_path"
+sqlite3 -cmd ".mode insert files_search" "$db_path" "SELECT id, database_type, release_id, file_type, file_subtype, names FROM files_search" >> "$sql_path"
 echo "...done."
 
 echo "Executing on CloudFlare.."
@@ -1,5 +1,9 @@
 #![cfg(feature = "index")]
 
+use acdatservice::{
+    db::file_type_for,
+    generators::{spells, strings},
+};
 use acprotocol::dat::{
     file_types::{dat_file::DatFile, texture::Texture},
     reader::{
@@ -8,11 +12,14 @@ use acprotocol::dat::{
     },
     DatDatabaseType, DatFileSubtype, DatFileType,
 };
+use byteorder::{LittleEndian, ReadBytesExt};
+use sha2::{Digest, Sha256};
 use sqlite::{self, Connection};
 use std::{
+    collections::HashMap,
     env,
     fs::{self, File},
-    io::Cursor,
+    io::{Cursor, Seek, SeekFrom},
     path::Path,
 };
 use strum::IntoEnumIterator;
@@ -21,12 +28,34 @@ use strum::IntoEnumIterator;
 type DbType = DatDatabaseType;
 type FileType = DatFileType;
 
+// The DAT header starts at 0x140 and its fourth field (DataSet) records which
+// database (portal, cell, etc.) the file holds
+const DAT_HEADER_DATA_SET_OFFSET: u64 = 0x140 + 12;
+
 fn setup() -> Result<(), Box<dyn std::error::Error>> {
     fs::create_dir_all("./data")?;
 
     Ok(())
 }
 
+fn has_column(
+    connection: &Connection,
+    table: &str,
+    column: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut statement = connection.prepare("SELECT name FROM pragma_table_info(?)")?;
+    statement.bind((1, table))?;
+
+    while let sqlite::State::Row = statement.next()? {
+        let name: String = statement.read(0)?;
+        if name == column {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
 fn migrate(connection: &Connection) -> Result<(), Box<dyn std::error::Error>> {
     connection.execute("DROP TABLE IF EXISTS database_types;")?;
     connection.execute(
@@ -53,7 +82,31 @@ fn migrate(connection: &Connection) -> Result<(), Box<dyn std::error::Error>> {
         )",
     )?;
 
-    connection.execute("DROP TABLE IF EXISTS files;")?;
+    // Releases and their files accumulate across runs so that multiple DAT
+    // versions can be served side by side
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS releases (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE,
+            created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )",
+    )?;
+
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS release_dats (
+            release_id INTEGER NOT NULL,
+            database_type INTEGER NOT NULL,
+            object_key TEXT NOT NULL,
+            block_size INTEGER NOT NULL
+        )",
+    )?;
+
+    // Indexes from before releases existed can't say which release their
+    // files belong to, so they're rebuilt rather than migrated
+    if has_column(connection, "files", "id")? && !has_column(connection, "files", "release_id")? {
+        connection.execute("DROP TABLE files;")?;
+    }
+
     connection.execute(
         "CREATE TABLE IF NOT EXISTS files (
             id INTEGER NOT NULL,
@@ -62,10 +115,37 @@ fn migrate(connection: &Connection) -> Result<(), Box<dyn std::error::Error>> {
             file_subtype INTEGER,
             file_offset INTEGER NOT NULL,
             file_size INTEGER NOT NULL,
+            release_id INTEGER NOT NULL,
+            content_hash TEXT,
             extra_info JSON
         )",
     )?;
 
+    if !has_column(connection, "files", "content_hash")? {
+        connection.execute("ALTER TABLE files ADD COLUMN content_hash TEXT;")?;
+    }
+
+    // Every route looks files up within a release, and /files pages through
+    // them by ID or by size
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS files_release_id ON files (release_id, id, database_type)",
+    )?;
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS files_release_size ON files (release_id, file_size, id, database_type)",
+    )?;
+
+    // Names to search files by, rebuilt on D1 by sync_d1.sh
+    connection.execute(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS files_search USING fts5(
+            id UNINDEXED,
+            database_type UNINDEXED,
+            release_id UNINDEXED,
+            file_type,
+            file_subtype,
+            names
+        )",
+    )?;
+
     Ok(())
 }
 
@@ -96,38 +176,165 @@ fn seed(connection: &Connection) -> Result<(), Box<dyn std::error::Error>> {
     statement.bind((3, DatFileSubtype::Icon.to_string().as_str()))?;
     statement.next()?; // Is this really how we execute a prepared statement?
 
+    // Every file that isn't an icon has the None subtype, whatever its type,
+    // so ?subtype=None can be looked up by name too
+    let mut statement = connection.prepare("INSERT INTO file_subtypes VALUES(?, NULL, ?);")?;
+    statement.bind((1, DatFileSubtype::None.as_u32() as i64))?;
+    statement.bind((2, DatFileSubtype::None.to_string().as_str()))?;
+    statement.next()?;
+
     Ok(())
 }
 
+/// Create the named release if needed and clear out anything a previous
+/// import of it left behind. Returns the release's ID.
+fn create_release(connection: &Connection, name: &str) -> Result<i64, Box<dyn std::error::Error>> {
+    let mut statement = connection.prepare("INSERT OR IGNORE INTO releases (name) VALUES (?)")?;
+    statement.bind((1, name))?;
+    statement.next()?;
+
+    let mut statement = connection.prepare("SELECT id FROM releases WHERE name = ?")?;
+    statement.bind((1, name))?;
+    statement.next()?;
+    let release_id: i64 = statement.read(0)?;
+
+    for table in ["files", "release_dats", "files_search"] {
+        let mut statement =
+            connection.prepare(format!("DELETE FROM {} WHERE release_id = ?", table))?;
+        statement.bind((1, release_id))?;
+        statement.next()?;
+    }
+
+    Ok(release_id)
+}
+
 fn show_data(connection: &Connection) -> Result<(), Box<dyn std::error::Error>> {
-    let mut statement = connection.prepare("SELECT count(1) FROM files;")?;
+    let mut statement = connection.prepare(
+        "SELECT releases.name, count(files.id) FROM releases
+        LEFT JOIN files ON files.release_id = releases.id
+        GROUP BY releases.id ORDER BY releases.id;",
+    )?;
 
     while let sqlite::State::Row = statement.next()? {
-        let count: i64 = statement.read(0)?;
-        println!("Count: {}", count);
+        let name: String = statement.read(0)?;
+        let count: i64 = statement.read(1)?;
+        println!("Release {}: {} files", name, count);
     }
 
     Ok(())
 }
 
-fn create_index(connection: &Connection, dat_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+/// Names decoded from a file for search, each with the ID of the file it
+/// names. Spell names go on their icons and DualDidMapper names on the files
+/// they map to, so searching for a spell finds its icon. Files we can't
+/// decode are still searchable by type, so decoding errors only get logged.
+fn decoded_names(file_id: u32, file_type: &DatFileType, buf: &[u8]) -> Vec<(u32, String)> {
+    // The SpellTable is a singleton found by ID rather than by file type
+    let names = if file_id == spells::SPELL_TABLE_ID {
+        spells::parse_spell_table(buf).map(|spells| {
+            spells
+                .into_values()
+                .map(|spell| (spell.icon, spell.name))
+                .collect()
+        })
+    } else {
+        match file_type {
+            DatFileType::StringTable => strings::parse_string_table(buf).map(|table| {
+                table
+                    .entries
+                    .into_iter()
+                    .flat_map(|entry| entry.strings)
+                    .map(|name| (file_id, name))
+                    .collect()
+            }),
+            DatFileType::EnumMapper => strings::parse_enum_mapper(buf).map(|mapper| {
+                mapper
+                    .names
+                    .into_values()
+                    .map(|name| (file_id, name))
+                    .collect()
+            }),
+            DatFileType::DualDidMapper => strings::parse_dual_did_mapper(buf).map(|mapper| {
+                let client = mapper
+                    .client_enum_to_name
+                    .into_iter()
+                    .map(|(key, name)| (mapper.client_enum_to_id.get(&key).copied(), name));
+                let server = mapper
+                    .server_enum_to_name
+                    .into_iter()
+                    .map(|(key, name)| (mapper.server_enum_to_id.get(&key).copied(), name));
+                // Names without a DID stay on the mapper
+                client
+                    .chain(server)
+                    .map(|(did, name)| (did.unwrap_or(file_id), name))
+                    .collect()
+            }),
+            _ => Ok(Vec::new()),
+        }
+    };
+
+    names.unwrap_or_else(|err| {
+        println!(
+            "Failed to decode names from file 0x{:08X}: {}",
+            file_id, err
+        );
+        Vec::new()
+    })
+}
+
+fn read_database_type(dat_path: &str) -> Result<DatDatabaseType, Box<dyn std::error::Error>> {
+    let mut dat_file = File::open(dat_path)?;
+    dat_file.seek(SeekFrom::Start(DAT_HEADER_DATA_SET_OFFSET))?;
+    let data_set = dat_file.read_u32::<LittleEndian>()?;
+
+    DatDatabaseType::from_u32(data_set).ok_or_else(|| {
+        Box::from(format!(
+            "Unknown database type {} in header of dat file: {}",
+            data_set, dat_path
+        ))
+    })
+}
+
+fn create_index(
+    connection: &Connection,
+    release_id: i64,
+    object_key: &str,
+    dat_path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let database_type = read_database_type(dat_path)?;
+    println!("Indexing {} as {} database", dat_path, database_type);
+
     let mut db_file = File::open(dat_path)?;
     let db: DatDatabase = DatDatabase::read(&mut db_file)?;
     let mut db_file_reader = SyncFileRangeReader::new(db_file);
 
+    let mut statement = connection.prepare(
+        "INSERT INTO release_dats (release_id, database_type, object_key, block_size) VALUES (?, ?, ?, ?)",
+    )?;
+    statement.bind((1, release_id))?;
+    statement.bind((2, database_type.as_u32() as i64))?;
+    statement.bind((3, object_key))?;
+    statement.bind((4, db.header.block_size as i64))?;
+    statement.next()?;
+
     let files = db.list_files(true)?;
 
+    // Search rows are written once every file has been read, since a file's
+    // names can come from files listed after it
+    let mut search_rows = Vec::new();
+    let mut names_by_id: HashMap<u32, Vec<String>> = HashMap::new();
+
     for file in files {
         println!("Processing file: {:?}", file);
 
-        let dat_file_type = DatFileType::from_object_id(file.object_id);
+        let dat_file_type = file_type_for(&database_type, file.object_id);
 
         let mut statement = connection.prepare(
-            "INSERT INTO files (id, database_type, file_type, file_subtype, file_offset, file_size) VALUES (?, ?, ?, ?, ?, ?)",
+            "INSERT INTO files (id, database_type, file_type, file_subtype, file_offset, file_size, release_id, content_hash) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
         )?;
 
         statement.bind((1, file.object_id as i64))?;
-        statement.bind((2, DatDatabaseType::Portal.as_u32() as i64))?;
+        statement.bind((2, database_type.as_u32() as i64))?;
         statement.bind((3, dat_file_type.as_u32() as i64))?;
 
         // Read the entire file so we can find out its subtype, if anye
@@ -135,55 +342,170 @@ fn create_index(connection: &Connection, dat_path: &str) -> Result<(), Box<dyn s
         let mut reader =
             SyncDatFileReader::new(file.file_size as usize, db.header.block_size as usize)?;
         let buf = reader.read_file(&mut db_file_reader, file.file_offset)?;
+        let content_hash = format!("{:x}", Sha256::digest(&buf));
+        for (named_id, name) in decoded_names(file.object_id, &dat_file_type, &buf) {
+            names_by_id.entry(named_id).or_default().push(name);
+        }
         let mut buf_reader = Cursor::new(buf);
 
-        match dat_file_type {
-            DatFileType::Texture => {
-                let outer_file: DatFile<Texture> = DatFile::read(&mut buf_reader)?;
-                let icon = outer_file.inner;
-                if icon.width == 32 && icon.height == 32 {
-                    statement.bind((subtype_col_index, DatFileSubtype::Icon.as_u32() as i64))?;
-                } else {
-                    statement.bind((subtype_col_index, DatFileSubtype::None.as_u32() as i64))?;
+        let file_subtype = match dat_file_type {
+            // A texture we can't read is still indexed, just without a subtype
+            DatFileType::Texture => match DatFile::<Texture>::read(&mut buf_reader) {
+                Ok(outer_file) if outer_file.inner.width == 32 && outer_file.inner.height == 32 => {
+                    DatFileSubtype::Icon
                 }
-            }
-            _ => {
-                statement.bind((subtype_col_index, DatFileSubtype::None.as_u32() as i64))?;
-            }
-        }
+                Ok(_) => DatFileSubtype::None,
+                Err(err) => {
+                    println!("Failed to read texture 0x{:08X}: {}", file.object_id, err);
+                    DatFileSubtype::None
+                }
+            },
+            _ => DatFileSubtype::None,
+        };
+        search_rows.push((
+            file.object_id,
+            dat_file_type.to_string(),
+            file_subtype.to_string(),
+        ));
+        statement.bind((subtype_col_index, file_subtype.as_u32() as i64))?;
 
         statement.bind((5, file.file_offset as i64))?;
         statement.bind((6, file.file_size as i64))?;
+        statement.bind((7, release_id))?;
+        statement.bind((8, content_hash.as_str()))?;
         statement.next()?;
     }
 
+    for (file_id, file_type_name, file_subtype_name) in search_rows {
+        let names = names_by_id.remove(&file_id).unwrap_or_default();
+
+        let mut statement = connection.prepare(
+            "INSERT INTO files_search (id, database_type, release_id, file_type, file_subtype, names) VALUES (?, ?, ?, ?, ?, ?)",
+        )?;
+        statement.bind((1, file_id as i64))?;
+        statement.bind((2, database_type.as_u32() as i64))?;
+        statement.bind((3, release_id))?;
+        statement.bind((4, file_type_name.as_str()))?;
+        statement.bind((5, file_subtype_name.as_str()))?;
+        // Not joined with newlines, which sqlite3 dumps using unistr()
+        statement.bind((6, names.join("; ").as_str()))?;
+        statement.next()?;
+    }
+
+    for (file_id, names) in names_by_id {
+        println!(
+            "Skipping {} names for 0x{:08X}, which isn't in this DAT",
+            names.len(),
+            file_id
+        );
+    }
+
     Ok(())
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() != 2 {
-        return Err(Box::from("Must specify path to dat file to index."));
+    let usage = "Usage: create_index --release <name> <dat file>...";
+
+    if args.len() < 4 || args[1] != "--release" {
+        return Err(Box::from(format!(
+            "Must specify a release name and path to one or more dat files to index.\n{}",
+            usage
+        )));
     }
 
-    let dat_path = &args[1];
+    let release_name = &args[2];
+    let dat_paths = &args[3..];
 
-    if !Path::new(dat_path).exists() {
+    // Release names end up in URLs (/v/:release) and R2 keys, so keep them to
+    // characters that need no escaping in either
+    let url_safe = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.');
+    if !release_name.chars().all(url_safe) || release_name.chars().all(|c| c == '.') {
         return Err(Box::from(format!(
-            "Provided dat file path doesn't exist: {}",
-            dat_path
+            "Invalid release name: {}. Use letters, digits, -, _ and . only.\n{}",
+            release_name, usage
         )));
     }
 
+    for dat_path in dat_paths {
+        if !Path::new(dat_path).exists() {
+            return Err(Box::from(format!(
+                "Provided dat file path doesn't exist: {}",
+                dat_path
+            )));
+        }
+    }
+
     let db_path = "./data/index.sqlite";
     let connection = sqlite::open(db_path)?;
 
     setup()?;
     migrate(&connection)?;
     seed(&connection)?;
-    create_index(&connection, dat_path)?;
+    let release_id = create_release(&connection, release_name)?;
+    for dat_path in dat_paths {
+        // DATs are uploaded to R2 under a prefix named after their release
+        let file_name = Path::new(dat_path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| format!("Invalid dat file path: {}", dat_path))?;
+        let object_key = format!("{}/{}", release_name, file_name);
+
+        create_index(&connection, release_id, &object_key, dat_path)?;
+    }
     show_data(&connection)?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes every EnumMapper and DualDidMapper in a real client_portal.dat,
+    /// which the hand-built buffers in the generators' tests can't vouch for.
+    /// Run with PORTAL_DAT=/path/to/client_portal.dat cargo test --features
+    /// index -- --ignored
+    #[test]
+    #[ignore = "needs a client_portal.dat, set PORTAL_DAT to its path"]
+    fn test_decode_mappers_from_portal_dat() {
+        let dat_path = env::var("PORTAL_DAT").expect("PORTAL_DAT isn't set");
+        assert!(matches!(
+            read_database_type(&dat_path).unwrap(),
+            DatDatabaseType::Portal
+        ));
+
+        let mut db_file = File::open(&dat_path).unwrap();
+        let db = DatDatabase::read(&mut db_file).unwrap();
+        let mut db_file_reader = SyncFileRangeReader::new(db_file);
+
+        let mut mappers = 0;
+        for file in db.list_files(true).unwrap() {
+            let file_type = file_type_for(&DatDatabaseType::Portal, file.object_id);
+            if !matches!(
+                file_type,
+                DatFileType::EnumMapper | DatFileType::DualDidMapper
+            ) {
+                continue;
+            }
+
+            let mut reader =
+                SyncDatFileReader::new(file.file_size as usize, db.header.block_size as usize)
+                    .unwrap();
+            let buf = reader
+                .read_file(&mut db_file_reader, file.file_offset)
+                .unwrap();
+
+            // Every map has to decode and the ID read back has to match
+            let id = match file_type {
+                DatFileType::EnumMapper => strings::parse_enum_mapper(&buf).map(|mapper| mapper.id),
+                _ => strings::parse_dual_did_mapper(&buf).map(|mapper| mapper.id),
+            };
+            assert_eq!(id, Ok(file.object_id));
+            mappers += 1;
+        }
+
+        assert!(mappers > 0);
+    }
+}
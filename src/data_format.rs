@@ -0,0 +1,161 @@
+use serde::Serialize;
+use serde_json::Value;
+
+/// Formats we can serve exported files as. Everything is serialized from the
+/// same serde structures, so the formats only differ in encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataFormat {
+    Json,
+    Cbor,
+    MessagePack,
+    Yaml,
+}
+
+impl DataFormat {
+    pub const ALL: [DataFormat; 4] = [
+        DataFormat::Json,
+        DataFormat::Cbor,
+        DataFormat::MessagePack,
+        DataFormat::Yaml,
+    ];
+
+    /// Parse a `?format=` value
+    pub fn from_name(name: &str) -> Option<DataFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "json" => Some(DataFormat::Json),
+            "cbor" => Some(DataFormat::Cbor),
+            "msgpack" | "messagepack" => Some(DataFormat::MessagePack),
+            "yaml" | "yml" => Some(DataFormat::Yaml),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DataFormat::Json => "json",
+            DataFormat::Cbor => "cbor",
+            DataFormat::MessagePack => "msgpack",
+            DataFormat::Yaml => "yaml",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            DataFormat::Json => "application/json",
+            DataFormat::Cbor => "application/cbor",
+            DataFormat::MessagePack => "application/msgpack",
+            DataFormat::Yaml => "application/yaml",
+        }
+    }
+
+    /// Serialize a value in this format. JSON is pretty-printed to match the
+    /// rest of the API.
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            DataFormat::Json => serde_json::to_vec_pretty(value).map_err(|err| err.to_string()),
+            DataFormat::Cbor => {
+                let mut buf = Vec::new();
+                ciborium::into_writer(value, &mut buf).map_err(|err| err.to_string())?;
+                Ok(buf)
+            }
+            DataFormat::MessagePack => {
+                rmp_serde::to_vec_named(value).map_err(|err| err.to_string())
+            }
+            DataFormat::Yaml => serde_yaml::to_string(value)
+                .map(String::into_bytes)
+                .map_err(|err| err.to_string()),
+        }
+    }
+}
+
+/// A parsed file that can still be encoded in any format. CBOR, MessagePack
+/// and YAML are encoded from the typed value so integer map keys stay
+/// integers; JSON goes through a Value so `?fields=` and `?filter=` can trim
+/// it first.
+pub trait Exported {
+    fn encode(&self, format: DataFormat) -> Result<Vec<u8>, String>;
+
+    fn to_json(&self) -> Result<Value, String>;
+}
+
+impl<T: Serialize> Exported for T {
+    fn encode(&self, format: DataFormat) -> Result<Vec<u8>, String> {
+        format.encode(self)
+    }
+
+    fn to_json(&self) -> Result<Value, String> {
+        serde_json::to_value(self).map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    fn value() -> Value {
+        json!({
+            "id": 0x0E00000E_u32,
+            "spells": {"1": {"name": "Strength Other I", "duration": 1800.5}},
+            "formula": [1, 63, 45],
+        })
+    }
+
+    #[test]
+    fn test_from_name() {
+        for format in DataFormat::ALL {
+            assert_eq!(DataFormat::from_name(format.name()), Some(format));
+        }
+        assert_eq!(DataFormat::from_name("YML"), Some(DataFormat::Yaml));
+        assert_eq!(DataFormat::from_name("xml"), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let value = value();
+
+        let json = DataFormat::Json.encode(&value).unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&json).unwrap(), value);
+
+        let cbor = DataFormat::Cbor.encode(&value).unwrap();
+        let decoded: Value = ciborium::from_reader(cbor.as_slice()).unwrap();
+        assert_eq!(decoded, value);
+
+        let msgpack = DataFormat::MessagePack.encode(&value).unwrap();
+        assert_eq!(rmp_serde::from_slice::<Value>(&msgpack).unwrap(), value);
+
+        let yaml = DataFormat::Yaml.encode(&value).unwrap();
+        let text = String::from_utf8(yaml).unwrap();
+        assert!(text.contains("name: Strength Other I"));
+        assert_eq!(serde_yaml::from_str::<Value>(&text).unwrap(), value);
+    }
+
+    #[test]
+    fn test_binary_formats_keep_integer_keys() {
+        let names = BTreeMap::from([(1u32, "Strength".to_string()), (2, "Endurance".to_string())]);
+        let exported: Box<dyn Exported> = Box::new(names.clone());
+
+        let cbor = exported.encode(DataFormat::Cbor).unwrap();
+        let decoded: ciborium::Value = ciborium::from_reader(cbor.as_slice()).unwrap();
+        let keys: Vec<_> = decoded
+            .as_map()
+            .unwrap()
+            .iter()
+            .map(|(key, _)| key.as_integer().map(i128::from))
+            .collect();
+        assert_eq!(keys, vec![Some(1), Some(2)]);
+
+        let msgpack = exported.encode(DataFormat::MessagePack).unwrap();
+        assert_eq!(
+            rmp_serde::from_slice::<BTreeMap<u32, String>>(&msgpack).unwrap(),
+            names
+        );
+
+        // JSON can only have string keys
+        assert_eq!(
+            exported.to_json().unwrap(),
+            json!({"1": "Strength", "2": "Endurance"})
+        );
+    }
+}
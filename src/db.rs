@@ -10,11 +10,43 @@ pub struct File {
     pub file_subtype: i64,
     pub file_offset: i64,
     pub file_size: i64,
+    pub release_id: i64,
+    pub content_hash: Option<String>,
+}
+
+/// The type of a file from its ID. Cell DAT IDs are a landblock in the high
+/// word and a cell in the low word, so they can't be matched against the
+/// portal ID ranges.
+pub fn file_type_for(database_type: &DatDatabaseType, object_id: u32) -> DatFileType {
+    match database_type {
+        DatDatabaseType::Cell => match object_id & 0xFFFF {
+            0xFFFF => DatFileType::LandBlock,
+            0xFFFE => DatFileType::LandBlockInfo,
+            _ => DatFileType::EnvCell,
+        },
+        _ => DatFileType::from_object_id(object_id),
+    }
+}
+
+/// Files are identified by their database as well as their ID, since IDs
+/// repeat across databases, e.g. cell landblock 0x0E00FFFF and portal tables
+pub type FileKey = (u32, u32);
+
+/// The key of a file in the portal DAT
+pub fn portal_key(file_id: u32) -> FileKey {
+    (DatDatabaseType::Portal.as_u32(), file_id)
 }
 
 impl File {
+    pub fn key(&self) -> FileKey {
+        (self.database_type as u32, self.id as u32)
+    }
+
     pub fn resolved_file_type(&self) -> DatFileType {
-        let file_type = DatFileType::from_object_id(self.id as u32);
+        let file_type = match DatDatabaseType::from_u32(self.database_type as u32) {
+            Some(database_type) => file_type_for(&database_type, self.id as u32),
+            None => DatFileType::from_object_id(self.id as u32),
+        };
         if file_type != DatFileType::Unknown {
             file_type
         } else {
@@ -23,6 +55,38 @@ impl File {
     }
 }
 
+/// A named import of one or more DATs, e.g. "retail" or "emu-2024-06"
+#[derive(Deserialize, Serialize)]
+pub struct Release {
+    pub id: i64,
+    pub name: String,
+    pub created_at: String,
+}
+
+/// Where a release's DAT for one database type lives in R2
+#[derive(Deserialize, Serialize)]
+pub struct ReleaseDat {
+    pub release_id: i64,
+    pub database_type: i64,
+    pub object_key: String,
+    pub block_size: i64,
+}
+
+/// A release along with the DATs imported into it
+pub struct DatRelease {
+    pub release: Release,
+    pub dats: Vec<ReleaseDat>,
+}
+
+impl DatRelease {
+    /// The DAT the given file was indexed from.
+    pub fn dat_for(&self, file: &File) -> Option<&ReleaseDat> {
+        self.dats.iter().find(|dat| {
+            dat.release_id == file.release_id && dat.database_type == file.database_type
+        })
+    }
+}
+
 /// Response struct with string representations for enum fields
 #[derive(Serialize)]
 pub struct FileResponse {
@@ -32,6 +96,7 @@ pub struct FileResponse {
     pub file_subtype: String,
     pub file_offset: i64,
     pub file_size: i64,
+    pub content_hash: Option<String>,
 }
 
 impl From<&File> for FileResponse {
@@ -47,6 +112,55 @@ impl From<&File> for FileResponse {
                 .unwrap_or_else(|| format!("Unknown({})", file.file_subtype)),
             file_offset: file.file_offset,
             file_size: file.file_size,
+            content_hash: file.content_hash.clone(),
+        }
+    }
+}
+
+/// A file that was added, removed, or changed between two releases
+#[derive(Deserialize)]
+pub struct FileChange {
+    #[serde(flatten)]
+    pub file: File,
+    pub change: String,
+}
+
+#[derive(Serialize)]
+pub struct FileChangeResponse {
+    #[serde(flatten)]
+    pub file: FileResponse,
+    pub change: String,
+}
+
+impl From<&FileChange> for FileChangeResponse {
+    fn from(file_change: &FileChange) -> Self {
+        FileChangeResponse {
+            file: (&file_change.file).into(),
+            change: file_change.change.clone(),
+        }
+    }
+}
+
+/// A file matched by /search along with the text that matched
+#[derive(Deserialize)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub file: File,
+    pub snippet: String,
+}
+
+#[derive(Serialize)]
+pub struct SearchResultResponse {
+    #[serde(flatten)]
+    pub file: FileResponse,
+    pub snippet: String,
+}
+
+impl From<&SearchResult> for SearchResultResponse {
+    fn from(result: &SearchResult) -> Self {
+        SearchResultResponse {
+            file: (&result.file).into(),
+            snippet: result.snippet.clone(),
         }
     }
 }
@@ -0,0 +1,374 @@
+use std::{fmt::Display, io::Cursor};
+
+use acprotocol::dat::{
+    file_types::{
+        dat_file::DatFile, texture::Texture, Animation, CharGen, EnvCell, Environment, GfxObj,
+        LandBlock, LandBlockInfo, MotionTable, Region, Scene, Setup, SoundTable, SpellTable,
+        Surface, SurfaceTexture,
+    },
+    DatFileType,
+};
+use serde::Serialize;
+
+use crate::data_format::Exported;
+use crate::generators::{clothing, palette, sound, spells, strings, tables, texture};
+
+/// Turns one kind of file into JSON for `?format=json`
+pub struct Exporter {
+    /// The name clients know the file by, e.g. GfxObj or SkillTable
+    pub name: &'static str,
+    /// What the JSON holds, for the OpenAPI doc
+    pub description: &'static str,
+    handles: fn(&DatFileType, u32) -> bool,
+    export: fn(u32, &[u8]) -> Result<Box<dyn Exported>, String>,
+}
+
+impl Exporter {
+    pub fn export(&self, file_id: u32, buf: &[u8]) -> Result<Box<dyn Exported>, String> {
+        (self.export)(file_id, buf)
+    }
+}
+
+/// A Texture without its pixels, which are better fetched from /textures
+#[derive(Serialize)]
+struct TextureInfo {
+    id: u32,
+    format: u32,
+    width: u32,
+    height: u32,
+    default_palette_id: Option<u32>,
+    data_size: usize,
+}
+
+/// A Wave without its samples, which are better fetched from /sounds
+#[derive(Serialize)]
+struct WaveInfo {
+    id: u32,
+    format_tag: Option<u16>,
+    header_size: usize,
+    data_size: usize,
+}
+
+fn exported<T: Serialize + 'static, E: Display>(
+    parsed: Result<T, E>,
+) -> Result<Box<dyn Exported>, String> {
+    let value = parsed.map_err(|err| err.to_string())?;
+    Ok(Box::new(value))
+}
+
+/// Every file we can export, checked in order. Singleton tables come first
+/// and are matched by ID since they don't have file types of their own.
+pub static EXPORTERS: &[Exporter] = &[
+    Exporter {
+        name: "VitalTable",
+        description: "health, stamina and mana formulas",
+        handles: |_, file_id| file_id == tables::VITAL_TABLE_ID,
+        export: |_, buf| exported(tables::parse_vital_table(buf)),
+    },
+    Exporter {
+        name: "SkillTable",
+        description: "skill names, costs and formulas",
+        handles: |_, file_id| file_id == tables::SKILL_TABLE_ID,
+        export: |_, buf| exported(tables::parse_skill_table(buf)),
+    },
+    Exporter {
+        name: "SpellComponentTable",
+        description: "spell component names, icons and gestures",
+        handles: |_, file_id| file_id == spells::SPELL_COMPONENT_TABLE_ID,
+        export: |_, buf| exported(spells::parse_spell_component_table(buf)),
+    },
+    Exporter {
+        name: "ExperienceTable",
+        description: "XP costs per rank and per character level",
+        handles: |_, file_id| file_id == tables::EXPERIENCE_TABLE_ID,
+        export: |_, buf| exported(tables::parse_experience_table(buf)),
+    },
+    Exporter {
+        name: "CharGen",
+        description: "character creation options",
+        handles: |file_type, _| {
+            matches!(
+                file_type,
+                DatFileType::CharGen | DatFileType::CharacterGenerator
+            )
+        },
+        export: |_, buf| {
+            let mut reader = Cursor::new(buf);
+            reader.set_position(4);
+            exported(CharGen::read(&mut reader))
+        },
+    },
+    Exporter {
+        name: "SpellTable",
+        description: "every spell and spell set",
+        handles: |file_type, _| matches!(file_type, DatFileType::SpellTable),
+        export: |_, buf| exported(SpellTable::read(&mut Cursor::new(buf))),
+    },
+    Exporter {
+        name: "GfxObj",
+        description: "vertices, polygons, surfaces",
+        handles: |file_type, _| matches!(file_type, DatFileType::GfxObj),
+        export: |_, buf| {
+            exported(DatFile::<GfxObj>::read(&mut Cursor::new(buf)).map(|file| file.inner))
+        },
+    },
+    Exporter {
+        name: "Setup",
+        description: "parts, placement frames, cylinder spheres",
+        handles: |file_type, _| matches!(file_type, DatFileType::Setup),
+        export: |_, buf| {
+            exported(DatFile::<Setup>::read(&mut Cursor::new(buf)).map(|file| file.inner))
+        },
+    },
+    Exporter {
+        name: "Animation",
+        description: "frame counts and per-part frames",
+        handles: |file_type, _| matches!(file_type, DatFileType::Animation),
+        export: |_, buf| {
+            exported(DatFile::<Animation>::read(&mut Cursor::new(buf)).map(|file| file.inner))
+        },
+    },
+    Exporter {
+        name: "MotionTable",
+        description: "style defaults, cycles, modifiers and links between substates",
+        handles: |file_type, _| matches!(file_type, DatFileType::MotionTable),
+        export: |_, buf| {
+            exported(DatFile::<MotionTable>::read(&mut Cursor::new(buf)).map(|file| file.inner))
+        },
+    },
+    Exporter {
+        name: "SoundTable",
+        description: "sound types mapped to Wave IDs",
+        handles: |file_type, _| matches!(file_type, DatFileType::SoundTable),
+        export: |_, buf| {
+            exported(DatFile::<SoundTable>::read(&mut Cursor::new(buf)).map(|file| file.inner))
+        },
+    },
+    Exporter {
+        name: "Surface",
+        description: "surface type, color or SurfaceTexture, translucency and luminosity",
+        handles: |file_type, _| matches!(file_type, DatFileType::Surface),
+        export: |_, buf| {
+            exported(DatFile::<Surface>::read(&mut Cursor::new(buf)).map(|file| file.inner))
+        },
+    },
+    Exporter {
+        name: "SurfaceTexture",
+        description: "Texture IDs by level of detail",
+        handles: |file_type, _| matches!(file_type, DatFileType::SurfaceTexture),
+        export: |_, buf| {
+            exported(DatFile::<SurfaceTexture>::read(&mut Cursor::new(buf)).map(|file| file.inner))
+        },
+    },
+    Exporter {
+        name: "Texture",
+        description: "format, size and default palette, without pixel data",
+        handles: |file_type, _| matches!(file_type, DatFileType::Texture),
+        export: |file_id, buf| {
+            exported(
+                DatFile::<Texture>::read(&mut Cursor::new(buf)).map(|file| TextureInfo {
+                    id: file_id,
+                    format: file.inner.format as u32,
+                    width: file.inner.width as u32,
+                    height: file.inner.height as u32,
+                    default_palette_id: file.inner.default_palette_id,
+                    data_size: file.inner.source_data.len(),
+                }),
+            )
+        },
+    },
+    Exporter {
+        name: "Wave",
+        description: "format tag and sizes, without sample data",
+        handles: |file_type, _| matches!(file_type, DatFileType::Wave),
+        export: |file_id, buf| {
+            exported(sound::parse_wave(buf).map(|wave| WaveInfo {
+                id: file_id,
+                format_tag: wave.format_tag(),
+                header_size: wave.header.len(),
+                data_size: wave.data.len(),
+            }))
+        },
+    },
+    Exporter {
+        name: "Environment",
+        description: "cell structures with their vertices, polygons and portals",
+        handles: |file_type, _| matches!(file_type, DatFileType::Environment),
+        export: |_, buf| {
+            exported(DatFile::<Environment>::read(&mut Cursor::new(buf)).map(|file| file.inner))
+        },
+    },
+    Exporter {
+        name: "Scene",
+        description: "objects scattered over terrain",
+        handles: |file_type, _| matches!(file_type, DatFileType::Scene),
+        export: |_, buf| {
+            exported(DatFile::<Scene>::read(&mut Cursor::new(buf)).map(|file| file.inner))
+        },
+    },
+    Exporter {
+        name: "Region",
+        description: "world settings, sky, sound, scene and terrain tables",
+        handles: |file_type, _| matches!(file_type, DatFileType::Region),
+        export: |_, buf| {
+            exported(DatFile::<Region>::read(&mut Cursor::new(buf)).map(|file| file.inner))
+        },
+    },
+    Exporter {
+        name: "LandBlock",
+        description: "terrain types and heights of a landblock's 9x9 vertices",
+        handles: |file_type, _| matches!(file_type, DatFileType::LandBlock),
+        export: |_, buf| {
+            exported(DatFile::<LandBlock>::read(&mut Cursor::new(buf)).map(|file| file.inner))
+        },
+    },
+    Exporter {
+        name: "LandBlockInfo",
+        description: "a landblock's cell count, objects and buildings",
+        handles: |file_type, _| matches!(file_type, DatFileType::LandBlockInfo),
+        export: |_, buf| {
+            exported(DatFile::<LandBlockInfo>::read(&mut Cursor::new(buf)).map(|file| file.inner))
+        },
+    },
+    Exporter {
+        name: "EnvCell",
+        description: "an indoor cell's surfaces, environment, portals and objects",
+        handles: |file_type, _| matches!(file_type, DatFileType::EnvCell),
+        export: |_, buf| {
+            exported(DatFile::<EnvCell>::read(&mut Cursor::new(buf)).map(|file| file.inner))
+        },
+    },
+    Exporter {
+        name: "Palette",
+        description: "colors as [r, g, b, a] arrays",
+        handles: |file_type, _| matches!(file_type, DatFileType::Palette),
+        export: |file_id, buf| {
+            exported(
+                texture::parse_palette(buf)
+                    .map(|colors| palette::PaletteColors::new(file_id, &colors)),
+            )
+        },
+    },
+    Exporter {
+        name: "PaletteSet",
+        description: "Palette IDs",
+        handles: |file_type, _| matches!(file_type, DatFileType::PaletteSet),
+        export: |file_id, buf| {
+            exported(
+                palette::parse_palette_set(buf).map(|palettes| palette::PaletteSetPalettes {
+                    id: file_id,
+                    palettes,
+                }),
+            )
+        },
+    },
+    Exporter {
+        name: "ClothingTable",
+        description: "base effects per Setup and sub palette effects per palette template",
+        handles: |file_type, _| matches!(file_type, DatFileType::ClothingTable),
+        export: |_, buf| exported(clothing::parse_clothing_table(buf)),
+    },
+    Exporter {
+        name: "StringTable",
+        description: "strings by name hash",
+        handles: |file_type, _| matches!(file_type, DatFileType::StringTable),
+        export: |_, buf| exported(strings::parse_string_table(buf)),
+    },
+    Exporter {
+        name: "EnumMapper",
+        description: "names by enum value",
+        handles: |file_type, _| matches!(file_type, DatFileType::EnumMapper),
+        export: |_, buf| exported(strings::parse_enum_mapper(buf)),
+    },
+    Exporter {
+        name: "DualDidMapper",
+        description: "IDs and names by client and server enum value",
+        handles: |file_type, _| matches!(file_type, DatFileType::DualDidMapper),
+        export: |_, buf| exported(strings::parse_dual_did_mapper(buf)),
+    },
+];
+
+/// The exporter for a file, or None when its type has no JSON export
+pub fn find(file_type: &DatFileType, file_id: u32) -> Option<&'static Exporter> {
+    EXPORTERS
+        .iter()
+        .find(|exporter| (exporter.handles)(file_type, file_id))
+}
+
+/// Names of every exportable file type
+pub fn names() -> Vec<&'static str> {
+    EXPORTERS.iter().map(|exporter| exporter.name).collect()
+}
+
+/// Every exportable file type and what its JSON holds, for the OpenAPI doc
+pub fn describe() -> String {
+    let types: Vec<String> = EXPORTERS
+        .iter()
+        .map(|exporter| format!("{} ({})", exporter.name, exporter.description))
+        .collect();
+    types.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use acprotocol::dat::DatDatabaseType;
+
+    use super::*;
+    use crate::db::file_type_for;
+
+    #[test]
+    fn test_exporters_find() {
+        let name = |file_type: DatFileType, file_id: u32| {
+            find(&file_type, file_id).map(|exporter| exporter.name)
+        };
+
+        assert_eq!(name(DatFileType::GfxObj, 0x01000001), Some("GfxObj"));
+        assert_eq!(
+            name(DatFileType::CharacterGenerator, 0x0E000002),
+            Some("CharGen")
+        );
+        // Singleton tables are found by ID whatever their file type
+        assert_eq!(
+            name(DatFileType::Unknown, tables::SKILL_TABLE_ID),
+            Some("SkillTable")
+        );
+        assert_eq!(name(DatFileType::Unknown, 0x0E000007), None);
+
+        // Cell DAT files, typed by the low word of their ID
+        let cell = |file_id: u32| {
+            let file_type = file_type_for(&DatDatabaseType::Cell, file_id);
+            find(&file_type, file_id).map(|exporter| exporter.name)
+        };
+        assert_eq!(cell(0xA9B4FFFF), Some("LandBlock"));
+        assert_eq!(cell(0xA9B4FFFE), Some("LandBlockInfo"));
+        assert_eq!(cell(0xA9B40100), Some("EnvCell"));
+        assert_eq!(name(DatFileType::Region, 0x13000000), Some("Region"));
+
+        let mut names = names();
+        let count = names.len();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), count);
+        assert!(describe().contains("Palette (colors as [r, g, b, a] arrays)"));
+    }
+
+    #[test]
+    fn test_exporters_export() {
+        let mut buf = Vec::new();
+        for value in [0x0A000001u32, 2, 3] {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        buf.extend_from_slice(&[0x55, 0x00, 1, 2, 3]);
+
+        let exporter = find(&DatFileType::Wave, 0x0A000001).unwrap();
+        let json = exporter
+            .export(0x0A000001, &buf)
+            .unwrap()
+            .to_json()
+            .unwrap();
+        assert_eq!(json["format_tag"], 0x55);
+        assert_eq!(json["data_size"], 3);
+
+        assert!(exporter.export(0x0A000001, &buf[..4]).is_err());
+    }
+}
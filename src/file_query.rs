@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+
+use crate::{db::File, parse_file_id};
+
+/// A value bound to one of the `?N` parameters of a query
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryValue {
+    Number(f64),
+    Text(String),
+}
+
+/// How /files is sorted with `?order=`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileOrder {
+    Id,
+    IdDesc,
+    Size,
+    SizeDesc,
+}
+
+impl FileOrder {
+    pub fn from_name(name: &str) -> Option<FileOrder> {
+        match name {
+            "id" => Some(FileOrder::Id),
+            "-id" => Some(FileOrder::IdDesc),
+            "size" => Some(FileOrder::Size),
+            "-size" => Some(FileOrder::SizeDesc),
+            _ => None,
+        }
+    }
+
+    /// The columns rows are sorted by. IDs repeat across databases (e.g.
+    /// portal and highres textures), so the database breaks ties.
+    fn columns(&self) -> &'static [&'static str] {
+        match self {
+            FileOrder::Id | FileOrder::IdDesc => &["id", "database_type"],
+            FileOrder::Size | FileOrder::SizeDesc => &["file_size", "id", "database_type"],
+        }
+    }
+
+    fn descending(&self) -> bool {
+        matches!(self, FileOrder::IdDesc | FileOrder::SizeDesc)
+    }
+
+    fn key(&self, file: &File) -> Vec<i64> {
+        match self {
+            FileOrder::Id | FileOrder::IdDesc => vec![file.id, file.database_type],
+            FileOrder::Size | FileOrder::SizeDesc => {
+                vec![file.file_size, file.id, file.database_type]
+            }
+        }
+    }
+}
+
+pub const DEFAULT_PAGE_LIMIT: usize = 100;
+pub const MAX_PAGE_LIMIT: usize = 1000;
+
+/// Read `?limit=` for paginated lists
+pub fn page_limit(params: &HashMap<String, String>) -> Result<usize, String> {
+    match params.get("limit").map(|value| value.parse::<usize>()) {
+        None => Ok(DEFAULT_PAGE_LIMIT),
+        Some(Ok(val)) if (1..=MAX_PAGE_LIMIT).contains(&val) => Ok(val),
+        Some(Ok(_)) => Err(format!("Choose a limit between 1 and {}", MAX_PAGE_LIMIT)),
+        Some(Err(err)) => Err(format!(
+            "Failed to parse query parameter: limit. Error: {}",
+            err
+        )),
+    }
+}
+
+/// The filters, order and page position of a /files request
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileQuery {
+    file_type: Option<QueryValue>,
+    file_subtype: Option<QueryValue>,
+    database: Option<QueryValue>,
+    min_id: Option<u32>,
+    max_id: Option<u32>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    order: FileOrder,
+    after: Option<Vec<i64>>,
+    limit: Option<usize>,
+}
+
+/// A type, subtype or database by number or by name, e.g. `6` or `Texture`
+fn enum_param(params: &HashMap<String, String>, name: &str) -> Option<QueryValue> {
+    params.get(name).map(|value| match parse_file_id(value) {
+        Ok(number) => QueryValue::Number(number as f64),
+        Err(_) => QueryValue::Text(value.clone()),
+    })
+}
+
+fn id_param(params: &HashMap<String, String>, name: &str) -> Result<Option<u32>, String> {
+    params
+        .get(name)
+        .map(|value| {
+            parse_file_id(value)
+                .map_err(|err| format!("Failed to parse query parameter: {}. Error: {}", name, err))
+        })
+        .transpose()
+}
+
+fn size_param(params: &HashMap<String, String>, name: &str) -> Result<Option<u64>, String> {
+    params
+        .get(name)
+        .map(|value| {
+            value
+                .parse::<u64>()
+                .map_err(|err| format!("Failed to parse query parameter: {}. Error: {}", name, err))
+        })
+        .transpose()
+}
+
+impl FileQuery {
+    /// Read `?type=`, `?subtype=`, `?database=`, `?min_id=`, `?max_id=`,
+    /// `?min_size=`, `?max_size=`, `?order=`, `?after=` and `?limit=`
+    pub fn from_params(params: &HashMap<String, String>) -> Result<FileQuery, String> {
+        let order = match params.get("order") {
+            None => FileOrder::Id,
+            Some(value) => FileOrder::from_name(value)
+                .ok_or_else(|| "Choose an order of id, -id, size or -size".to_string())?,
+        };
+
+        let after = params
+            .get("after")
+            .map(|value| {
+                let key = value
+                    .split('.')
+                    .map(|part| part.parse::<i64>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .ok()
+                    .filter(|key| key.len() == order.columns().len());
+                key.ok_or_else(|| {
+                    "Failed to parse query parameter: after. Use the Link header rather than setting it by hand.".to_string()
+                })
+            })
+            .transpose()?;
+
+        // Without limit or after every file is listed at once, as /files did
+        // before it was paginated
+        let limit = if params.contains_key("limit") || after.is_some() {
+            Some(page_limit(params)?)
+        } else {
+            None
+        };
+
+        Ok(FileQuery {
+            file_type: enum_param(params, "type"),
+            file_subtype: enum_param(params, "subtype"),
+            database: enum_param(params, "database"),
+            min_id: id_param(params, "min_id")?,
+            max_id: id_param(params, "max_id")?,
+            min_size: size_param(params, "min_size")?,
+            max_size: size_param(params, "max_size")?,
+            order,
+            after,
+            limit,
+        })
+    }
+
+    /// Files per page, or None to list every file
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Every file with one ID, narrowed to the database named by
+    /// `?database=` if given
+    pub fn for_id(params: &HashMap<String, String>, file_id: u32) -> FileQuery {
+        FileQuery {
+            file_type: None,
+            file_subtype: None,
+            database: enum_param(params, "database"),
+            min_id: Some(file_id),
+            max_id: Some(file_id),
+            min_size: None,
+            max_size: None,
+            order: FileOrder::Id,
+            after: None,
+            limit: None,
+        }
+    }
+
+    /// The SQL and parameters for up to `limit` files of a release, or all of
+    /// them when `limit` is None
+    pub fn sql(&self, release_id: i64, limit: Option<usize>) -> (String, Vec<QueryValue>) {
+        let mut values = vec![QueryValue::Number(release_id as f64)];
+        let mut clauses = vec!["release_id = ?1".to_string()];
+
+        let mut bind = |value: QueryValue| {
+            values.push(value);
+            format!("?{}", values.len())
+        };
+
+        // Names are looked up in the tables create_index seeds
+        for (column, table, value) in [
+            ("file_type", "file_types", &self.file_type),
+            ("file_subtype", "file_subtypes", &self.file_subtype),
+            ("database_type", "database_types", &self.database),
+        ] {
+            match value {
+                None => {}
+                Some(QueryValue::Text(name)) => clauses.push(format!(
+                    "{} IN (SELECT id FROM {} WHERE name = {} COLLATE NOCASE)",
+                    column,
+                    table,
+                    bind(QueryValue::Text(name.clone()))
+                )),
+                Some(number) => clauses.push(format!("{} = {}", column, bind(number.clone()))),
+            }
+        }
+
+        for (condition, value) in [
+            ("id >=", self.min_id.map(|id| id as f64)),
+            ("id <=", self.max_id.map(|id| id as f64)),
+            ("file_size >=", self.min_size.map(|size| size as f64)),
+            ("file_size <=", self.max_size.map(|size| size as f64)),
+        ] {
+            if let Some(value) = value {
+                clauses.push(format!("{} {}", condition, bind(QueryValue::Number(value))));
+            }
+        }
+
+        let columns = self.order.columns().join(", ");
+        if let Some(after) = &self.after {
+            let placeholders: Vec<String> = after
+                .iter()
+                .map(|value| bind(QueryValue::Number(*value as f64)))
+                .collect();
+            clauses.push(format!(
+                "({}) {} ({})",
+                columns,
+                if self.order.descending() { "<" } else { ">" },
+                placeholders.join(", ")
+            ));
+        }
+
+        let direction = if self.order.descending() {
+            "DESC"
+        } else {
+            "ASC"
+        };
+        let order_by: Vec<String> = self
+            .order
+            .columns()
+            .iter()
+            .map(|column| format!("{} {}", column, direction))
+            .collect();
+        let mut sql = format!(
+            "SELECT * FROM files WHERE {} ORDER BY {}",
+            clauses.join(" AND "),
+            order_by.join(", ")
+        );
+        if let Some(limit) = limit {
+            sql.push_str(&format!(
+                " LIMIT {}",
+                bind(QueryValue::Number(limit as f64))
+            ));
+        }
+
+        (sql, values)
+    }
+
+    /// The `?after=` value for the page following `file`
+    pub fn cursor(&self, file: &File) -> String {
+        let key: Vec<String> = self
+            .order
+            .key(file)
+            .iter()
+            .map(|value| value.to_string())
+            .collect();
+        key.join(".")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_default_query() {
+        // A plain /files lists everything, as it did before pagination
+        let query = FileQuery::from_params(&HashMap::new()).unwrap();
+        assert_eq!(query.limit(), None);
+        let (sql, values) = query.sql(3, query.limit());
+        assert_eq!(
+            sql,
+            "SELECT * FROM files WHERE release_id = ?1 ORDER BY id ASC, database_type ASC"
+        );
+        assert_eq!(values, vec![QueryValue::Number(3.0)]);
+
+        let (sql, values) = query.sql(3, Some(101));
+        assert!(sql.ends_with(" LIMIT ?2"));
+        assert_eq!(values[1], QueryValue::Number(101.0));
+    }
+
+    #[test]
+    fn test_limit() {
+        let limit =
+            |pairs: &[(&str, &str)]| FileQuery::from_params(&params(pairs)).unwrap().limit();
+
+        assert_eq!(limit(&[("limit", "5")]), Some(5));
+        // Following a Link header keeps paging by the default
+        assert_eq!(limit(&[("after", "5.1")]), Some(DEFAULT_PAGE_LIMIT));
+        assert_eq!(limit(&[("type", "Wave")]), None);
+        assert!(FileQuery::from_params(&params(&[("limit", "0")])).is_err());
+    }
+
+    #[test]
+    fn test_filters() {
+        let query = FileQuery::from_params(&params(&[
+            ("type", "Wave"),
+            ("database", "1"),
+            ("min_id", "0x0A000000"),
+            ("max_size", "4096"),
+            ("order", "-size"),
+            ("after", "2048.167772161.1"),
+        ]))
+        .unwrap();
+        let (sql, values) = query.sql(1, Some(10));
+
+        assert_eq!(
+            sql,
+            "SELECT * FROM files WHERE release_id = ?1 \
+             AND file_type IN (SELECT id FROM file_types WHERE name = ?2 COLLATE NOCASE) \
+             AND database_type = ?3 AND id >= ?4 AND file_size <= ?5 \
+             AND (file_size, id, database_type) < (?6, ?7, ?8) \
+             ORDER BY file_size DESC, id DESC, database_type DESC LIMIT ?9"
+        );
+        assert_eq!(values[1], QueryValue::Text("Wave".to_string()));
+        assert_eq!(values[3], QueryValue::Number(0x0A000000 as f64));
+        assert_eq!(values[6], QueryValue::Number(167772161.0));
+        assert_eq!(values.len(), 9);
+    }
+
+    #[test]
+    fn test_for_id() {
+        let query = FileQuery::for_id(&params(&[("database", "Cell")]), 0x0E00FFFF);
+        let (sql, values) = query.sql(2, Some(10));
+
+        assert_eq!(
+            sql,
+            "SELECT * FROM files WHERE release_id = ?1 \
+             AND database_type IN (SELECT id FROM database_types WHERE name = ?2 COLLATE NOCASE) \
+             AND id >= ?3 AND id <= ?4 ORDER BY id ASC, database_type ASC LIMIT ?5"
+        );
+        assert_eq!(values[2], QueryValue::Number(0x0E00FFFF as f64));
+        assert_eq!(values[3], QueryValue::Number(0x0E00FFFF as f64));
+    }
+
+    #[test]
+    fn test_invalid_params() {
+        assert!(FileQuery::from_params(&params(&[("order", "name")])).is_err());
+        assert!(FileQuery::from_params(&params(&[("min_id", "0xZZ")])).is_err());
+        assert!(FileQuery::from_params(&params(&[("min_size", "-1")])).is_err());
+        // A cursor from id order can't be used with size order
+        assert!(FileQuery::from_params(&params(&[("order", "size"), ("after", "5.1")])).is_err());
+    }
+
+    #[test]
+    fn test_cursor() {
+        let file = File {
+            id: 0x0A000001,
+            database_type: 1,
+            file_type: 10,
+            file_subtype: 0,
+            file_offset: 1024,
+            file_size: 2048,
+            release_id: 1,
+            content_hash: None,
+        };
+
+        let query = FileQuery::from_params(&params(&[("order", "size")])).unwrap();
+        let cursor = query.cursor(&file);
+        assert_eq!(cursor, "2048.167772161.1");
+
+        let next =
+            FileQuery::from_params(&params(&[("order", "size"), ("after", &cursor)])).unwrap();
+        assert_eq!(next.after, Some(vec![2048, 0x0A000001, 1]));
+    }
+}
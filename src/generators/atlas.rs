@@ -0,0 +1,153 @@
+use std::io::Cursor;
+
+use image::{imageops, ImageFormat, RgbaImage};
+use serde::Serialize;
+
+/// Where one image sits within an atlas
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct AtlasTile {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+/// Lay out `count` square tiles of `tile_size` pixels left to right, top to
+/// bottom, in rows of `columns`. Returns the atlas width, height, and tiles.
+pub fn layout(count: usize, columns: u32, tile_size: u32) -> (u32, u32, Vec<AtlasTile>) {
+    let columns = columns.max(1);
+    let rows = (count as u32).div_ceil(columns);
+    let width = columns.min(count as u32) * tile_size;
+    let height = rows * tile_size;
+
+    let tiles = (0..count as u32)
+        .map(|index| AtlasTile {
+            x: (index % columns) * tile_size,
+            y: (index / columns) * tile_size,
+            w: tile_size,
+            h: tile_size,
+        })
+        .collect();
+
+    (width, height, tiles)
+}
+
+/// Default number of columns for `count` tiles, roughly square.
+pub fn default_columns(count: usize) -> u32 {
+    ((count as f64).sqrt().ceil() as u32).max(1)
+}
+
+/// An atlas filled in one tile at a time, so only the canvas and the current
+/// tile are held in memory.
+pub struct Atlas {
+    image: RgbaImage,
+}
+
+impl Atlas {
+    pub fn new(width: u32, height: u32) -> Atlas {
+        Atlas {
+            image: RgbaImage::new(width, height),
+        }
+    }
+
+    /// Decode a PNG and paste it into its tile.
+    pub fn paste_png(&mut self, tile: &AtlasTile, png: &[u8]) -> Result<(), image::ImageError> {
+        let image = image::load_from_memory_with_format(png, ImageFormat::Png)?.to_rgba8();
+        imageops::replace(&mut self.image, &image, tile.x as i64, tile.y as i64);
+
+        Ok(())
+    }
+
+    /// Encode the atlas as a PNG.
+    pub fn to_png(&self) -> Result<Vec<u8>, image::ImageError> {
+        let mut buf = Vec::new();
+        self.image
+            .write_to(&mut Cursor::new(&mut buf), ImageFormat::Png)?;
+
+        Ok(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_fills_rows_left_to_right() {
+        let (width, height, tiles) = layout(5, 2, 32);
+
+        assert_eq!((width, height), (64, 96));
+        assert_eq!(
+            tiles[0],
+            AtlasTile {
+                x: 0,
+                y: 0,
+                w: 32,
+                h: 32
+            }
+        );
+        assert_eq!(
+            tiles[1],
+            AtlasTile {
+                x: 32,
+                y: 0,
+                w: 32,
+                h: 32
+            }
+        );
+        assert_eq!(
+            tiles[2],
+            AtlasTile {
+                x: 0,
+                y: 32,
+                w: 32,
+                h: 32
+            }
+        );
+        assert_eq!(
+            tiles[4],
+            AtlasTile {
+                x: 0,
+                y: 64,
+                w: 32,
+                h: 32
+            }
+        );
+    }
+
+    #[test]
+    fn test_layout_narrows_to_count() {
+        let (width, height, tiles) = layout(3, 10, 64);
+
+        assert_eq!((width, height), (192, 64));
+        assert_eq!(tiles.len(), 3);
+    }
+
+    #[test]
+    fn test_default_columns() {
+        assert_eq!(default_columns(1), 1);
+        assert_eq!(default_columns(4), 2);
+        assert_eq!(default_columns(5), 3);
+        assert_eq!(default_columns(100), 10);
+    }
+
+    #[test]
+    fn test_atlas_places_tiles() {
+        let mut red = RgbaImage::new(2, 2);
+        red.pixels_mut()
+            .for_each(|pixel| *pixel = image::Rgba([255, 0, 0, 255]));
+        let mut png = Vec::new();
+        red.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+
+        let (width, height, tiles) = layout(2, 2, 2);
+        let mut atlas = Atlas::new(width, height);
+        atlas.paste_png(&tiles[1], &png).unwrap();
+        let buf = atlas.to_png().unwrap();
+        let atlas = image::load_from_memory(&buf).unwrap().to_rgba8();
+
+        assert_eq!(atlas.dimensions(), (4, 2));
+        assert_eq!(atlas.get_pixel(0, 0).0, [0, 0, 0, 0]);
+        assert_eq!(atlas.get_pixel(3, 1).0, [255, 0, 0, 255]);
+    }
+}
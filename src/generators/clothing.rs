@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use super::reader::Reader;
+
+/// Swap one SurfaceTexture for another on a part
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TextureEffect {
+    pub old_texture: u32,
+    pub new_texture: u32,
+}
+
+/// Replace the GfxObj of one part of a Setup and retexture it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ObjectEffect {
+    pub index: u32,
+    pub model_id: u32,
+    pub texture_effects: Vec<TextureEffect>,
+}
+
+/// A run of colors in the wearer's palette, in colors rather than the
+/// multiples of 8 the client sends over the wire
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SubPaletteRange {
+    pub offset: u32,
+    pub num_colors: u32,
+}
+
+/// Colors to take from a PaletteSet, picked by shade
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SubPalette {
+    pub ranges: Vec<SubPaletteRange>,
+    pub palette_set: u32,
+}
+
+/// The icon and palette substitutions for one palette template
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SubPalEffect {
+    pub icon: u32,
+    pub sub_palettes: Vec<SubPalette>,
+}
+
+/// A ClothingTable (0x10) file. Base effects are keyed by the wearer's Setup
+/// ID and sub palette effects by palette template.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClothingTable {
+    pub id: u32,
+    pub base_effects: BTreeMap<u32, Vec<ObjectEffect>>,
+    pub sub_pal_effects: BTreeMap<u32, SubPalEffect>,
+}
+
+/// A sub palette with its PaletteSet resolved to a Palette
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ResolvedSubPalette {
+    pub palette_set: u32,
+    pub palette_id: u32,
+    pub ranges: Vec<SubPaletteRange>,
+}
+
+/// The concrete model, texture, and palette substitutions for wearing a
+/// clothing item in one palette template and shade
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ResolvedClothing {
+    pub clothing_id: u32,
+    pub setup_id: u32,
+    pub palette_template: Option<u32>,
+    pub shade: f64,
+    pub icon: Option<u32>,
+    pub parts: Vec<ObjectEffect>,
+    pub palettes: Vec<ResolvedSubPalette>,
+}
+
+fn texture_effect(reader: &mut Reader) -> Result<TextureEffect, String> {
+    Ok(TextureEffect {
+        old_texture: reader.u32()?,
+        new_texture: reader.u32()?,
+    })
+}
+
+fn object_effect(reader: &mut Reader) -> Result<ObjectEffect, String> {
+    Ok(ObjectEffect {
+        index: reader.u32()?,
+        model_id: reader.u32()?,
+        texture_effects: reader.list(texture_effect)?,
+    })
+}
+
+fn base_effect(reader: &mut Reader) -> Result<Vec<ObjectEffect>, String> {
+    reader.list(object_effect)
+}
+
+fn sub_palette_range(reader: &mut Reader) -> Result<SubPaletteRange, String> {
+    Ok(SubPaletteRange {
+        offset: reader.u32()?,
+        num_colors: reader.u32()?,
+    })
+}
+
+fn sub_palette(reader: &mut Reader) -> Result<SubPalette, String> {
+    Ok(SubPalette {
+        ranges: reader.list(sub_palette_range)?,
+        palette_set: reader.u32()?,
+    })
+}
+
+fn sub_pal_effect(reader: &mut Reader) -> Result<SubPalEffect, String> {
+    Ok(SubPalEffect {
+        icon: reader.u32()?,
+        sub_palettes: reader.list(sub_palette)?,
+    })
+}
+
+/// Parse a ClothingTable (0x10) file.
+pub fn parse_clothing_table(buf: &[u8]) -> Result<ClothingTable, String> {
+    let mut reader = Reader::new(buf, "clothing table");
+
+    Ok(ClothingTable {
+        id: reader.u32()?,
+        base_effects: reader.hash_table(base_effect)?,
+        sub_pal_effects: reader.hash_table(sub_pal_effect)?,
+    })
+}
+
+impl ClothingTable {
+    /// The part changes for a wearer's Setup. The Setup can be left out when
+    /// the table only covers one.
+    pub fn parts_for(&self, setup_id: Option<u32>) -> Result<(u32, &[ObjectEffect]), String> {
+        let setup_id = match setup_id {
+            Some(val) => val,
+            None if self.base_effects.len() == 1 => *self.base_effects.keys().next().unwrap(),
+            None => {
+                return Err(format!(
+                    "Clothing table covers several setups, choose one of: {}",
+                    self.setup_ids()
+                ))
+            }
+        };
+
+        match self.base_effects.get(&setup_id) {
+            Some(parts) => Ok((setup_id, parts)),
+            None => Err(format!(
+                "Clothing table has no effects for setup 0x{:08X}, choose one of: {}",
+                setup_id,
+                self.setup_ids()
+            )),
+        }
+    }
+
+    pub fn sub_pal_effect(&self, palette_template: u32) -> Result<&SubPalEffect, String> {
+        self.sub_pal_effects.get(&palette_template).ok_or_else(|| {
+            let templates: Vec<String> = self
+                .sub_pal_effects
+                .keys()
+                .map(|key| key.to_string())
+                .collect();
+            format!(
+                "Clothing table has no palette template {}, choose one of: {}",
+                palette_template,
+                templates.join(", ")
+            )
+        })
+    }
+
+    fn setup_ids(&self) -> String {
+        let ids: Vec<String> = self
+            .base_effects
+            .keys()
+            .map(|key| format!("0x{:08X}", key))
+            .collect();
+        ids.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clothing_table_file() -> Vec<u8> {
+        let mut buf = Vec::new();
+        let mut put = |value: u32| buf.extend_from_slice(&value.to_le_bytes());
+        put(0x10000001);
+
+        // One base effect for setup 0x02000001: part 9 becomes 0x01000002
+        // with one texture swap
+        put(1 | (16 << 16));
+        put(0x02000001);
+        put(1);
+        put(9);
+        put(0x01000002);
+        put(1);
+        put(0x05000001);
+        put(0x05000002);
+
+        // Palette template 4 with one sub palette over colors 24-47
+        put(1 | (16 << 16));
+        put(4);
+        put(0x06001234);
+        put(1);
+        put(1);
+        put(24);
+        put(24);
+        put(0x0F000001);
+
+        buf
+    }
+
+    #[test]
+    fn test_parse_clothing_table() {
+        let table = parse_clothing_table(&clothing_table_file()).unwrap();
+
+        assert_eq!(table.id, 0x10000001);
+        assert_eq!(
+            table.base_effects[&0x02000001],
+            vec![ObjectEffect {
+                index: 9,
+                model_id: 0x01000002,
+                texture_effects: vec![TextureEffect {
+                    old_texture: 0x05000001,
+                    new_texture: 0x05000002,
+                }],
+            }]
+        );
+        assert_eq!(
+            table.sub_pal_effects[&4],
+            SubPalEffect {
+                icon: 0x06001234,
+                sub_palettes: vec![SubPalette {
+                    ranges: vec![SubPaletteRange {
+                        offset: 24,
+                        num_colors: 24,
+                    }],
+                    palette_set: 0x0F000001,
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_clothing_table_rejects_truncated() {
+        let buf = clothing_table_file();
+        assert!(parse_clothing_table(&buf[..buf.len() - 2]).is_err());
+    }
+
+    #[test]
+    fn test_parts_for() {
+        let mut table = parse_clothing_table(&clothing_table_file()).unwrap();
+
+        let (setup_id, parts) = table.parts_for(None).unwrap();
+        assert_eq!(setup_id, 0x02000001);
+        assert_eq!(parts.len(), 1);
+        assert!(table.parts_for(Some(0x02000002)).is_err());
+
+        table.base_effects.insert(0x02000002, vec![]);
+        assert!(table.parts_for(None).is_err());
+        assert_eq!(table.parts_for(Some(0x02000002)).unwrap().1.len(), 0);
+    }
+
+    #[test]
+    fn test_sub_pal_effect() {
+        let table = parse_clothing_table(&clothing_table_file()).unwrap();
+        assert_eq!(table.sub_pal_effect(4).unwrap().icon, 0x06001234);
+        assert!(table.sub_pal_effect(5).is_err());
+    }
+}
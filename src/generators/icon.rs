@@ -1,15 +1,483 @@
-use acprotocol::dat::Icon;
+use std::{collections::HashMap, io::Cursor};
+
+use acprotocol::dat::{
+    file_types::{dat_file::DatFile, texture::Texture},
+    Icon,
+};
+use sha2::{Digest, Sha256};
 use worker::*;
 
-pub async fn generate_icon(icon: &Icon) -> Result<Response> {
-    let buf = icon.export()?;
+use super::{palette::PaletteChoice, texture};
+use crate::{
+    db::{portal_key, File, FileKey},
+    parse_decimal_or_hex_string,
+};
+
+/// Transparent UI effect used when none is requested
+pub const DEFAULT_UI_EFFECT_ID: u32 = 0x060011C5;
+
+/// Most icons a single batch request can render
+pub const MAX_BATCH_ICONS: usize = 200;
+
+/// Most icons a single atlas can hold
+pub const MAX_ATLAS_ICONS: usize = 256;
+
+/// Widest an atlas can be, in icons
+pub const MAX_ATLAS_COLUMNS: u32 = 64;
+
+/// Most pixels (width times height) an atlas can have, e.g. 4096x4096
+pub const MAX_ATLAS_PIXELS: u64 = 4096 * 4096;
+
+/// Prefix in R2 under which rendered icons are persisted
+pub const RENDER_CACHE_PREFIX: &str = "renders/";
+
+/// Rendered icons are keyed on their release and inputs so they never change
+pub const ICON_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// Icons requested against the latest release change when a new release is
+/// imported so clients should revalidate them now and then
+pub const LATEST_ICON_CACHE_CONTROL: &str = "public, max-age=3600";
+
+/// The fully resolved set of textures and options that make up an icon.
+/// Names like background=armor or ui_effect=fire are resolved to texture IDs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IconSpec {
+    pub icon_id: u32,
+    pub scale: u32,
+    pub background: Option<u32>,
+    pub underlay: Option<u32>,
+    pub overlay: Option<u32>,
+    pub ui_effect: u32,
+    /// Substitute palette for a paletted icon texture. PaletteSet choices must
+    /// be resolved to a Palette before rendering.
+    pub palette: Option<PaletteChoice>,
+}
+
+impl IconSpec {
+    /// Parse an icon ID and its query parameters (scale, background, underlay,
+    /// overlay, ui_effect, palette, palette_set, shade). Errors are messages suitable for a 400 response.
+    pub fn from_params(
+        icon_id: &str,
+        params: &HashMap<String, String>,
+    ) -> std::result::Result<IconSpec, String> {
+        let icon_id = parse_decimal_or_hex_string(icon_id).map_err(|err| err.to_string())?;
+
+        // scale
+        let scale = params
+            .get("scale")
+            .map(|value| value.parse::<u32>())
+            .unwrap_or_else(|| Ok(1))
+            .map_err(|err| format!("Failed to parse query parameter: scale.{}", err))?;
+
+        // Error for unreasonable scale values
+        if !(1..=8).contains(&scale) {
+            return Err("Choose a scale value between 1 and 8".to_string());
+        }
+
+        // background - accepts ID or ItemType name
+        let background = match params.get("background") {
+            Some(value) => Some(match parse_decimal_or_hex_string(value) {
+                Ok(id) => id as u32,
+                Err(_) => match acprotocol::dat::icon::parse_item_type(value) {
+                    Ok(item_type_value) => {
+                        acprotocol::dat::icon::get_background_from_item_type(item_type_value)
+                    }
+                    Err(e) => return Err(format!("Error parsing background: {}", e)),
+                },
+            }),
+            None => None,
+        };
+
+        // underlay - accepts ID only
+        let underlay = match params.get("underlay") {
+            Some(value) => Some(parse_decimal_or_hex_string(value).map_err(|err| {
+                format!("Failed to parse query parameter: underlay. Error: {}", err)
+            })? as u32),
+            None => None,
+        };
+
+        // overlay - accepts ID only
+        let overlay = match params.get("overlay") {
+            Some(value) => Some(parse_decimal_or_hex_string(value).map_err(|err| {
+                format!("Failed to parse query parameter: overlay. Error: {}", err)
+            })? as u32),
+            None => None,
+        };
+
+        // ui_effect - accepts ID or UiEffects name, defaults to transparent
+        let ui_effect = match params.get("ui_effect") {
+            Some(value) => match parse_decimal_or_hex_string(value) {
+                Ok(id) => id as u32,
+                Err(_) => match acprotocol::dat::icon::parse_ui_effect(value) {
+                    Ok(ui_effect_flags) => {
+                        acprotocol::dat::icon::get_ui_effect_texture_id(ui_effect_flags)
+                    }
+                    Err(e) => return Err(format!("Error parsing ui_effect: {}", e)),
+                },
+            },
+            None => DEFAULT_UI_EFFECT_ID,
+        };
+
+        // palette, or palette_set and shade
+        let palette = PaletteChoice::from_params(params)?;
+
+        Ok(IconSpec {
+            icon_id: icon_id as u32,
+            scale,
+            background,
+            underlay,
+            overlay,
+            ui_effect,
+            palette,
+        })
+    }
+
+    /// Every texture ID this icon is composited from, bottom layer first.
+    pub fn texture_ids(&self) -> Vec<u32> {
+        let mut ids = Vec::new();
+        ids.extend(self.background);
+        ids.extend(self.underlay);
+        ids.push(self.icon_id);
+        ids.extend(self.overlay);
+        ids.push(self.ui_effect);
+        ids
+    }
+
+    /// Every file this icon reads: its textures plus any palette.
+    pub fn file_ids(&self) -> Vec<u32> {
+        let mut ids = self.texture_ids();
+        ids.extend(self.palette.map(|palette| palette.file_id()));
+        ids
+    }
+
+    /// Path and query uniquely identifying this icon within a release, for use
+    /// as a cache key. Every ID is absolute so equivalent requests share a key.
+    pub fn cache_key(&self, release_id: i64) -> String {
+        let mut key = format!(
+            "/v/{}/icons/0x{:08X}?scale={}",
+            release_id, self.icon_id, self.scale
+        );
+        let layers = [
+            ("background", self.background),
+            ("underlay", self.underlay),
+            ("overlay", self.overlay),
+            ("ui_effect", Some(self.ui_effect)),
+        ];
+        for (name, texture_id) in layers {
+            if let Some(texture_id) = texture_id {
+                key.push_str(&format!("&{}=0x{:08X}", name, texture_id));
+            }
+        }
+        if let Some(palette) = &self.palette {
+            key.push_str(&palette.cache_param());
+        }
+        key
+    }
+
+    /// Hash of the content of every input texture plus the rendering options.
+    /// None if any input was indexed without a content hash.
+    pub fn content_hash(&self, files: &HashMap<FileKey, File>) -> Option<String> {
+        let mut hasher = Sha256::new();
+        hasher.update(format!("scale={};", self.scale));
+
+        let layers = [
+            ("background", self.background),
+            ("underlay", self.underlay),
+            ("icon", Some(self.icon_id)),
+            ("overlay", self.overlay),
+            ("ui_effect", Some(self.ui_effect)),
+        ];
+        for (name, texture_id) in layers {
+            let hash = match texture_id {
+                Some(texture_id) => files
+                    .get(&portal_key(texture_id))?
+                    .content_hash
+                    .as_deref()?,
+                None => "",
+            };
+            hasher.update(format!("{}={};", name, hash));
+        }
+
+        if let Some(palette) = &self.palette {
+            let hash = files
+                .get(&portal_key(palette.file_id()))?
+                .content_hash
+                .as_deref()?;
+            hasher.update(format!("palette={};", hash));
+            if let PaletteChoice::PaletteSet { shade, .. } = palette {
+                hasher.update(format!("shade={};", shade));
+            }
+        }
+
+        Some(format!("{:x}", hasher.finalize()))
+    }
+
+    /// R2 key of this icon in the render cache. Keyed on the content hash so
+    /// a new import that changes any input texture gets a new render.
+    pub fn render_key(&self, files: &HashMap<FileKey, File>) -> Option<String> {
+        self.content_hash(files)
+            .map(|hash| format!("{}{}.png", RENDER_CACHE_PREFIX, hash))
+    }
+
+    /// Build the Icon from the raw DAT buffers of every input file. A paletted
+    /// icon texture is recolored with the substitute palette, if any.
+    pub fn to_icon(&self, bufs: &HashMap<u32, Vec<u8>>) -> std::result::Result<Icon, String> {
+        let parse_texture = |texture_id: u32| -> std::result::Result<Texture, String> {
+            let buf = bufs
+                .get(&texture_id)
+                .ok_or_else(|| format!("Failed to read texture file for ID {:X}", texture_id))?;
+            let mut buf_reader = Cursor::new(buf.as_slice());
+            let texture_file: DatFile<Texture> = DatFile::read(&mut buf_reader)
+                .map_err(|_| format!("Failed to parse texture file for ID {:X}", texture_id))?;
+            Ok(texture_file.inner)
+        };
+
+        Ok(Icon {
+            width: 32,
+            height: 32,
+            scale: self.scale,
+            background: self.background.map(parse_texture).transpose()?,
+            underlay: self.underlay.map(parse_texture).transpose()?,
+            icon: self.recolor(parse_texture(self.icon_id)?, bufs)?,
+            overlay: self.overlay.map(parse_texture).transpose()?,
+            effect: Some(parse_texture(self.ui_effect)?),
+        })
+    }
+
+    fn recolor(
+        &self,
+        icon: Texture,
+        bufs: &HashMap<u32, Vec<u8>>,
+    ) -> std::result::Result<Texture, String> {
+        let palette_id = match self.palette {
+            Some(PaletteChoice::Palette(id)) => id,
+            Some(PaletteChoice::PaletteSet { .. }) => {
+                return Err("Palette set must be resolved before rendering".to_string())
+            }
+            None => return Ok(icon),
+        };
+
+        if !texture::is_paletted(icon.format as u32) {
+            return Ok(icon);
+        }
+
+        let buf = bufs
+            .get(&palette_id)
+            .ok_or_else(|| format!("Failed to read palette file for ID {:X}", palette_id))?;
+        let colors = texture::parse_palette(buf)?;
+        let image = texture::decode_texture(&icon, Some(&colors))?;
+
+        Ok(texture::with_argb_pixels(icon, &image))
+    }
+}
+
+/// One entry in a batch request: an icon and the name it's returned under
+pub struct BatchIcon {
+    pub name: String,
+    pub spec: IconSpec,
+}
+
+impl BatchIcon {
+    /// Parse a JSON object with the same fields as the /icons/:id path and
+    /// query parameters, plus an optional name that defaults to the id.
+    pub fn from_json(value: &serde_json::Value) -> std::result::Result<BatchIcon, String> {
+        let object = value
+            .as_object()
+            .ok_or_else(|| "Icon spec must be a JSON object".to_string())?;
+
+        let mut params = HashMap::new();
+        for (key, value) in object {
+            let value = match value {
+                serde_json::Value::String(text) => text.clone(),
+                serde_json::Value::Number(number) => number.to_string(),
+                _ => return Err(format!("Field {} must be a string or number", key)),
+            };
+            params.insert(key.clone(), value);
+        }
+
+        let id = params
+            .remove("id")
+            .ok_or_else(|| "Icon spec must have an id".to_string())?;
+        let spec = IconSpec::from_params(&id, &params)?;
+        let name = params.remove("name").unwrap_or(id);
+
+        // Names become ZIP entry paths, so they can't leave the archive root
+        if name.is_empty() || name.contains(['/', '\\']) || name.contains("..") {
+            return Err(format!(
+                "Invalid name {}. Names can't be empty or contain /, \\ or ..",
+                name
+            ));
+        }
+
+        Ok(BatchIcon { name, spec })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use acprotocol::dat::DatDatabaseType;
+
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn file(id: u32, content_hash: Option<&str>) -> File {
+        File {
+            id: id as i64,
+            database_type: DatDatabaseType::Portal.as_u32() as i64,
+            file_type: 6,
+            file_subtype: 1,
+            file_offset: 0,
+            file_size: 0,
+            release_id: 1,
+            content_hash: content_hash.map(|hash| hash.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_from_params_defaults() {
+        let spec = IconSpec::from_params("0x6957", &params(&[])).unwrap();
+
+        assert_eq!(spec.icon_id, 0x06006957);
+        assert_eq!(spec.scale, 1);
+        assert_eq!(spec.background, None);
+        assert_eq!(spec.ui_effect, DEFAULT_UI_EFFECT_ID);
+        assert_eq!(spec.texture_ids(), vec![0x06006957, DEFAULT_UI_EFFECT_ID]);
+    }
+
+    #[test]
+    fn test_from_params_rejects_bad_values() {
+        assert!(IconSpec::from_params("text", &params(&[])).is_err());
+        assert!(IconSpec::from_params("0x6957", &params(&[("scale", "0")])).is_err());
+        assert!(IconSpec::from_params("0x6957", &params(&[("scale", "9")])).is_err());
+        assert!(IconSpec::from_params("0x6957", &params(&[("underlay", "text")])).is_err());
+    }
+
+    #[test]
+    fn test_texture_ids_are_in_layer_order() {
+        let spec = IconSpec::from_params(
+            "0x6957",
+            &params(&[("underlay", "0x1000"), ("overlay", "0x2000")]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            spec.texture_ids(),
+            vec![0x06001000, 0x06006957, 0x06002000, DEFAULT_UI_EFFECT_ID]
+        );
+    }
+
+    #[test]
+    fn test_batch_icon_from_json() {
+        let icon = BatchIcon::from_json(&serde_json::json!({
+            "id": 26967,
+            "scale": 2,
+            "overlay": "0x2000",
+        }))
+        .unwrap();
+        assert_eq!(icon.name, "26967");
+        assert_eq!(icon.spec.icon_id, 0x06006957);
+        assert_eq!(icon.spec.scale, 2);
+        assert_eq!(icon.spec.overlay, Some(0x06002000));
+
+        let named =
+            BatchIcon::from_json(&serde_json::json!({"id": "0x6957", "name": "sword"})).unwrap();
+        assert_eq!(named.name, "sword");
+
+        assert!(BatchIcon::from_json(&serde_json::json!({"scale": 2})).is_err());
+        assert!(BatchIcon::from_json(&serde_json::json!({"id": [1]})).is_err());
+        assert!(BatchIcon::from_json(&serde_json::json!("0x6957")).is_err());
+
+        for name in ["../../x", "/etc/x", "a\\b", "", ".."] {
+            assert!(
+                BatchIcon::from_json(&serde_json::json!({"id": "0x6957", "name": name})).is_err(),
+                "accepted {:?}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_cache_key_normalizes_ids() {
+        let relative =
+            IconSpec::from_params("26967", &params(&[("scale", "2"), ("overlay", "0x2000")]))
+                .unwrap();
+        let absolute = IconSpec::from_params(
+            "0x06006957",
+            &params(&[("scale", "2"), ("overlay", "100671488")]),
+        )
+        .unwrap();
+
+        assert_eq!(relative.cache_key(3), absolute.cache_key(3));
+        assert_eq!(
+            relative.cache_key(3),
+            "/v/3/icons/0x06006957?scale=2&overlay=0x06002000&ui_effect=0x060011C5"
+        );
+        assert_ne!(relative.cache_key(3), relative.cache_key(4));
+
+        let recolored = IconSpec::from_params(
+            "26967",
+            &params(&[("palette_set", "0x0F000001"), ("shade", "0.5")]),
+        )
+        .unwrap();
+        assert_eq!(
+            recolored.cache_key(3),
+            "/v/3/icons/0x06006957?scale=1&ui_effect=0x060011C5&palette_set=0x0F000001&shade=0.5"
+        );
+    }
+
+    #[test]
+    fn test_palette_is_an_input() {
+        let spec = IconSpec::from_params("0x6957", &params(&[("palette", "0x04000001")])).unwrap();
+        assert_eq!(
+            spec.file_ids(),
+            vec![0x06006957, DEFAULT_UI_EFFECT_ID, 0x04000001]
+        );
+
+        let mut files = HashMap::new();
+        files.insert(portal_key(0x06006957), file(0x06006957, Some("aaaa")));
+        files.insert(
+            portal_key(DEFAULT_UI_EFFECT_ID),
+            file(DEFAULT_UI_EFFECT_ID, Some("bbbb")),
+        );
+        assert!(spec.content_hash(&files).is_none());
+
+        files.insert(portal_key(0x04000001), file(0x04000001, Some("cccc")));
+        let hash = spec.content_hash(&files).unwrap();
+        let plain = IconSpec {
+            palette: None,
+            ..spec.clone()
+        };
+        assert_ne!(plain.content_hash(&files).unwrap(), hash);
+    }
+
+    #[test]
+    fn test_content_hash_tracks_inputs_and_options() {
+        let spec = IconSpec::from_params("0x6957", &params(&[])).unwrap();
+        let mut files = HashMap::new();
+        files.insert(portal_key(0x06006957), file(0x06006957, Some("aaaa")));
+        files.insert(
+            portal_key(DEFAULT_UI_EFFECT_ID),
+            file(DEFAULT_UI_EFFECT_ID, Some("bbbb")),
+        );
+
+        let hash = spec.content_hash(&files).unwrap();
 
-    let mut response = Response::from_body(worker::ResponseBody::Body(buf))?;
+        let scaled = IconSpec {
+            scale: 2,
+            ..spec.clone()
+        };
+        assert_ne!(scaled.content_hash(&files).unwrap(), hash);
 
-    response.headers_mut().set("Content-Type", "image/png")?;
-    response
-        .headers_mut()
-        .set("Content-Disposition", "inline")?;
+        files.insert(portal_key(0x06006957), file(0x06006957, Some("cccc")));
+        assert_ne!(spec.content_hash(&files).unwrap(), hash);
 
-    Ok(response)
+        files.insert(portal_key(0x06006957), file(0x06006957, None));
+        assert!(spec.content_hash(&files).is_none());
+    }
 }
@@ -1 +1,12 @@
+pub mod atlas;
+pub mod clothing;
 pub mod icon;
+pub mod model;
+pub mod output;
+pub mod palette;
+pub mod reader;
+pub mod sound;
+pub mod spells;
+pub mod strings;
+pub mod tables;
+pub mod texture;
@@ -0,0 +1,775 @@
+use std::collections::HashMap;
+
+use acprotocol::dat::file_types::{GfxObj, Setup, Surface};
+use base64::prelude::*;
+use serde_json::{json, Map, Value};
+
+// Surface::surface_type flags
+pub const SURFACE_BASE1_SOLID: u32 = 0x1;
+pub const SURFACE_BASE1_CLIPMAP: u32 = 0x4;
+pub const SURFACE_TRANSLUCENT: u32 = 0x10;
+pub const SURFACE_ALPHA: u32 = 0x100;
+pub const SURFACE_ADDITIVE: u32 = 0x10000;
+
+// Polygon::sides_type values
+const SIDES_SINGLE: u32 = 0;
+const SIDES_DOUBLE: u32 = 1;
+
+// Placement frame keys in Setup::placement_frames
+const PLACEMENT_DEFAULT: u32 = 0x0;
+const PLACEMENT_RESTING: u32 = 0x65;
+
+pub const MODEL_FORMATS: [&str; 3] = ["glb", "gltf", "obj"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+}
+
+/// Triangles that share a material
+#[derive(Debug, Default, PartialEq)]
+pub struct Primitive {
+    pub material: usize,
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+#[derive(Debug, Default)]
+pub struct Mesh {
+    pub name: String,
+    pub primitives: Vec<Primitive>,
+}
+
+/// An instance of a mesh. Translation and rotation (x, y, z, w) are in the
+/// game's Z-up space like everything else in a Model.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Node {
+    pub name: String,
+    pub mesh: usize,
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlphaMode {
+    Opaque,
+    Mask,
+    Blend,
+}
+
+#[derive(Clone, Debug)]
+pub struct Material {
+    pub name: String,
+    pub color: [f32; 4],
+    /// PNG
+    pub texture: Option<Vec<u8>>,
+    pub alpha_mode: AlphaMode,
+}
+
+/// A model in the game's coordinate space. Positions are converted to glTF's
+/// Y-up space on export.
+#[derive(Debug, Default)]
+pub struct Model {
+    pub meshes: Vec<Mesh>,
+    pub nodes: Vec<Node>,
+    pub materials: Vec<Material>,
+}
+
+/// A GfxObj vertex before it's been split by UV
+#[derive(Clone, Debug)]
+pub struct SourceVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uvs: Vec<[f32; 2]>,
+}
+
+/// One side of a GfxObj polygon. `surface` indexes into the GfxObj's surface
+/// list and each corner is a (vertex ID, UV index) pair.
+#[derive(Clone, Debug)]
+pub struct Face {
+    pub surface: usize,
+    pub corners: Vec<(u16, u8)>,
+    pub back: bool,
+}
+
+/// The game is Z-up, glTF is Y-up. This is a rotation about X so winding and
+/// handedness are preserved.
+pub fn to_y_up(v: [f32; 3]) -> [f32; 3] {
+    [v[0], v[2], -v[1]]
+}
+
+/// Same as to_y_up for an (x, y, z, w) quaternion
+pub fn rotation_to_y_up(q: [f32; 4]) -> [f32; 4] {
+    [q[0], q[2], -q[1], q[3]]
+}
+
+fn rotate(q: [f32; 4], v: [f32; 3]) -> [f32; 3] {
+    let [qx, qy, qz, qw] = q;
+    // t = 2 * cross(q.xyz, v)
+    let t = [
+        2.0 * (qy * v[2] - qz * v[1]),
+        2.0 * (qz * v[0] - qx * v[2]),
+        2.0 * (qx * v[1] - qy * v[0]),
+    ];
+    [
+        v[0] + qw * t[0] + (qy * t[2] - qz * t[1]),
+        v[1] + qw * t[1] + (qz * t[0] - qx * t[2]),
+        v[2] + qw * t[2] + (qx * t[1] - qy * t[0]),
+    ]
+}
+
+/// Triangulate faces into one primitive per surface. `materials` maps the
+/// GfxObj's surface indices to Model materials.
+///
+/// Polygons are stored clockwise (Direct3D's front face) so corners are
+/// reversed to get glTF's counter-clockwise winding. Back faces keep the
+/// stored order and flip their normals instead.
+pub fn build_mesh(
+    name: String,
+    vertices: &HashMap<u16, SourceVertex>,
+    faces: &[Face],
+    materials: &[usize],
+) -> Result<Mesh, String> {
+    let mut primitives: Vec<Primitive> = Vec::new();
+    let mut lookups: Vec<HashMap<(u16, u8, bool), u32>> = Vec::new();
+
+    for face in faces {
+        let material = *materials.get(face.surface).ok_or_else(|| {
+            format!(
+                "Polygon references surface {} but there are only {}",
+                face.surface,
+                materials.len()
+            )
+        })?;
+
+        let index = match primitives.iter().position(|p| p.material == material) {
+            Some(val) => val,
+            None => {
+                primitives.push(Primitive {
+                    material,
+                    ..Default::default()
+                });
+                lookups.push(HashMap::new());
+                primitives.len() - 1
+            }
+        };
+        let primitive = &mut primitives[index];
+        let lookup = &mut lookups[index];
+
+        let mut corner_indices = Vec::with_capacity(face.corners.len());
+        for &(vertex_id, uv_index) in &face.corners {
+            let key = (vertex_id, uv_index, face.back);
+            let vertex_index = match lookup.get(&key) {
+                Some(val) => *val,
+                None => {
+                    let source = vertices.get(&vertex_id).ok_or_else(|| {
+                        format!("Polygon references missing vertex {}", vertex_id)
+                    })?;
+                    let normal = if face.back {
+                        source.normal.map(|n| -n)
+                    } else {
+                        source.normal
+                    };
+                    primitive.vertices.push(Vertex {
+                        position: source.position,
+                        normal,
+                        uv: source
+                            .uvs
+                            .get(uv_index as usize)
+                            .copied()
+                            .unwrap_or_default(),
+                    });
+                    let vertex_index = (primitive.vertices.len() - 1) as u32;
+                    lookup.insert(key, vertex_index);
+                    vertex_index
+                }
+            };
+            corner_indices.push(vertex_index);
+        }
+
+        if !face.back {
+            corner_indices.reverse();
+        }
+
+        for i in 1..corner_indices.len().saturating_sub(1) {
+            primitive.indices.extend_from_slice(&[
+                corner_indices[0],
+                corner_indices[i],
+                corner_indices[i + 1],
+            ]);
+        }
+    }
+
+    Ok(Mesh { name, primitives })
+}
+
+/// Pull the vertices and faces out of a GfxObj for build_mesh
+pub fn gfx_obj_geometry(gfx_obj: &GfxObj) -> (HashMap<u16, SourceVertex>, Vec<Face>) {
+    let vertices = gfx_obj
+        .vertex_array
+        .vertices
+        .iter()
+        .map(|(id, vertex)| {
+            (
+                *id,
+                SourceVertex {
+                    position: [vertex.origin.x, vertex.origin.y, vertex.origin.z],
+                    normal: [vertex.normal.x, vertex.normal.y, vertex.normal.z],
+                    uvs: vertex.uvs.iter().map(|uv| [uv.u, uv.v]).collect(),
+                },
+            )
+        })
+        .collect();
+
+    // Sort by polygon ID so output is stable
+    let mut polygons: Vec<_> = gfx_obj.polygons.iter().collect();
+    polygons.sort_by_key(|(id, _)| **id);
+
+    let mut faces = Vec::new();
+    for (_, polygon) in polygons {
+        let corners = |uv_indices: &[u8]| -> Vec<(u16, u8)> {
+            polygon
+                .vertex_ids
+                .iter()
+                .enumerate()
+                .map(|(i, id)| (*id as u16, uv_indices.get(i).copied().unwrap_or_default()))
+                .collect()
+        };
+
+        faces.push(Face {
+            surface: polygon.pos_surface as usize,
+            corners: corners(&polygon.pos_uv_indices),
+            back: false,
+        });
+
+        match polygon.sides_type as u32 {
+            SIDES_SINGLE => {}
+            // Double sided polygons reuse the front surface on the back
+            SIDES_DOUBLE => faces.push(Face {
+                surface: polygon.pos_surface as usize,
+                corners: corners(&polygon.pos_uv_indices),
+                back: true,
+            }),
+            _ => faces.push(Face {
+                surface: polygon.neg_surface as usize,
+                corners: corners(&polygon.neg_uv_indices),
+                back: true,
+            }),
+        }
+    }
+
+    (vertices, faces)
+}
+
+/// Where each part of a Setup goes: (GfxObj ID, translation, rotation, scale).
+/// Uses the resting placement, falling back to the default one.
+pub fn setup_parts(setup: &Setup) -> Vec<(u32, [f32; 3], [f32; 4], [f32; 3])> {
+    let frames = setup
+        .placement_frames
+        .get(&PLACEMENT_RESTING)
+        .or_else(|| setup.placement_frames.get(&PLACEMENT_DEFAULT))
+        .map(|placement| placement.frames.as_slice())
+        .unwrap_or_default();
+
+    setup
+        .parts
+        .iter()
+        .enumerate()
+        .map(|(i, part_id)| {
+            let (translation, rotation) = match frames.get(i) {
+                Some(frame) => (
+                    [frame.origin.x, frame.origin.y, frame.origin.z],
+                    [
+                        frame.orientation.x,
+                        frame.orientation.y,
+                        frame.orientation.z,
+                        frame.orientation.w,
+                    ],
+                ),
+                None => ([0.0; 3], [0.0, 0.0, 0.0, 1.0]),
+            };
+            let scale = setup
+                .default_scale
+                .get(i)
+                .map(|scale| [scale.x, scale.y, scale.z])
+                .unwrap_or([1.0; 3]);
+            (*part_id, translation, rotation, scale)
+        })
+        .collect()
+}
+
+/// Build a material for a Surface. `texture` is the decoded PNG of its
+/// texture, if it has one.
+pub fn surface_material(surface_id: u32, surface: &Surface, texture: Option<Vec<u8>>) -> Material {
+    let flags = surface.surface_type as u32;
+
+    let color = if flags & SURFACE_BASE1_SOLID != 0 {
+        let argb = surface.color_value;
+        [
+            ((argb >> 16) & 0xFF) as f32 / 255.0,
+            ((argb >> 8) & 0xFF) as f32 / 255.0,
+            (argb & 0xFF) as f32 / 255.0,
+            1.0,
+        ]
+    } else {
+        [1.0; 4]
+    };
+
+    let alpha_mode = if flags & (SURFACE_TRANSLUCENT | SURFACE_ALPHA | SURFACE_ADDITIVE) != 0 {
+        AlphaMode::Blend
+    } else if flags & SURFACE_BASE1_CLIPMAP != 0 {
+        AlphaMode::Mask
+    } else {
+        AlphaMode::Opaque
+    };
+
+    // Translucency is stored as how see-through the surface is
+    let mut color = color;
+    if flags & SURFACE_TRANSLUCENT != 0 {
+        color[3] = (1.0 - surface.translucency).clamp(0.0, 1.0);
+    }
+
+    Material {
+        name: format!("0x{:08X}", surface_id),
+        color,
+        texture,
+        alpha_mode,
+    }
+}
+
+fn pad_to_four(buf: &mut Vec<u8>, byte: u8) {
+    while !buf.len().is_multiple_of(4) {
+        buf.push(byte);
+    }
+}
+
+fn f32_bytes<const N: usize>(values: impl Iterator<Item = [f32; N]>) -> Vec<u8> {
+    values
+        .flat_map(|value| value.into_iter().flat_map(f32::to_le_bytes))
+        .collect()
+}
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+
+impl Model {
+    /// The glTF JSON document and its binary buffer
+    fn gltf_document(&self) -> (Map<String, Value>, Vec<u8>) {
+        let mut bin = Vec::new();
+        let mut views = Vec::new();
+        let mut accessors = Vec::new();
+
+        let mut push_view = |bin: &mut Vec<u8>, data: &[u8], target: Option<u32>| -> usize {
+            pad_to_four(bin, 0);
+            let mut view = json!({
+                "buffer": 0,
+                "byteOffset": bin.len(),
+                "byteLength": data.len(),
+            });
+            if let Some(target) = target {
+                view["target"] = json!(target);
+            }
+            bin.extend_from_slice(data);
+            views.push(view);
+            views.len() - 1
+        };
+
+        let mut images = Vec::new();
+        let mut textures = Vec::new();
+        let mut materials = Vec::new();
+        for material in &self.materials {
+            let mut pbr = json!({
+                "baseColorFactor": material.color,
+                "metallicFactor": 0.0,
+                "roughnessFactor": 1.0,
+            });
+            if let Some(png) = &material.texture {
+                let view = push_view(&mut bin, png, None);
+                images.push(json!({ "bufferView": view, "mimeType": "image/png" }));
+                textures.push(json!({ "source": images.len() - 1, "sampler": 0 }));
+                pbr["baseColorTexture"] = json!({ "index": textures.len() - 1 });
+            }
+            let alpha_mode = match material.alpha_mode {
+                AlphaMode::Opaque => "OPAQUE",
+                AlphaMode::Mask => "MASK",
+                AlphaMode::Blend => "BLEND",
+            };
+            materials.push(json!({
+                "name": material.name,
+                "pbrMetallicRoughness": pbr,
+                "alphaMode": alpha_mode,
+            }));
+        }
+
+        let mut meshes = Vec::new();
+        for mesh in &self.meshes {
+            let mut primitives = Vec::new();
+            for primitive in &mesh.primitives {
+                let positions: Vec<[f32; 3]> = primitive
+                    .vertices
+                    .iter()
+                    .map(|v| to_y_up(v.position))
+                    .collect();
+                let mut min = [f32::MAX; 3];
+                let mut max = [f32::MIN; 3];
+                for position in &positions {
+                    for axis in 0..3 {
+                        min[axis] = min[axis].min(position[axis]);
+                        max[axis] = max[axis].max(position[axis]);
+                    }
+                }
+
+                let count = primitive.vertices.len();
+                let view = push_view(
+                    &mut bin,
+                    &f32_bytes(positions.into_iter()),
+                    Some(ARRAY_BUFFER),
+                );
+                accessors.push(json!({
+                    "bufferView": view, "componentType": FLOAT, "count": count,
+                    "type": "VEC3", "min": min, "max": max,
+                }));
+                let position = accessors.len() - 1;
+
+                let normals = primitive.vertices.iter().map(|v| to_y_up(v.normal));
+                let view = push_view(&mut bin, &f32_bytes(normals), Some(ARRAY_BUFFER));
+                accessors.push(json!({
+                    "bufferView": view, "componentType": FLOAT, "count": count, "type": "VEC3",
+                }));
+                let normal = accessors.len() - 1;
+
+                let uvs = primitive.vertices.iter().map(|v| v.uv);
+                let view = push_view(&mut bin, &f32_bytes(uvs), Some(ARRAY_BUFFER));
+                accessors.push(json!({
+                    "bufferView": view, "componentType": FLOAT, "count": count, "type": "VEC2",
+                }));
+                let uv = accessors.len() - 1;
+
+                let indices: Vec<u8> = primitive
+                    .indices
+                    .iter()
+                    .flat_map(|i| i.to_le_bytes())
+                    .collect();
+                let view = push_view(&mut bin, &indices, Some(ELEMENT_ARRAY_BUFFER));
+                accessors.push(json!({
+                    "bufferView": view, "componentType": UNSIGNED_INT,
+                    "count": primitive.indices.len(), "type": "SCALAR",
+                }));
+                let indices = accessors.len() - 1;
+
+                primitives.push(json!({
+                    "attributes": { "POSITION": position, "NORMAL": normal, "TEXCOORD_0": uv },
+                    "indices": indices,
+                    "material": primitive.material,
+                }));
+            }
+            meshes.push(json!({ "name": mesh.name, "primitives": primitives }));
+        }
+
+        let nodes: Vec<Value> = self
+            .nodes
+            .iter()
+            .map(|node| {
+                json!({
+                    "name": node.name,
+                    "mesh": node.mesh,
+                    "translation": to_y_up(node.translation),
+                    "rotation": rotation_to_y_up(node.rotation),
+                    "scale": [node.scale[0], node.scale[2], node.scale[1]],
+                })
+            })
+            .collect();
+        let scene_nodes: Vec<usize> = (0..nodes.len()).collect();
+
+        let mut doc = Map::new();
+        doc.insert(
+            "asset".to_string(),
+            json!({ "version": "2.0", "generator": "dats.treestats.net" }),
+        );
+        doc.insert("scene".to_string(), json!(0));
+        doc.insert("scenes".to_string(), json!([{ "nodes": scene_nodes }]));
+
+        // glTF doesn't allow empty arrays
+        let arrays = [
+            ("nodes", nodes),
+            ("meshes", meshes),
+            ("materials", materials),
+            ("textures", textures),
+            ("images", images),
+            ("accessors", accessors),
+            ("bufferViews", views),
+        ];
+        for (name, values) in arrays {
+            if !values.is_empty() {
+                doc.insert(name.to_string(), Value::Array(values));
+            }
+        }
+        if doc.contains_key("textures") {
+            // Linear filtering with mipmaps, repeat wrapping
+            doc.insert(
+                "samplers".to_string(),
+                json!([{ "magFilter": 9729, "minFilter": 9987, "wrapS": 10497, "wrapT": 10497 }]),
+            );
+        }
+        if !bin.is_empty() {
+            pad_to_four(&mut bin, 0);
+            doc.insert("buffers".to_string(), json!([{ "byteLength": bin.len() }]));
+        }
+
+        (doc, bin)
+    }
+
+    /// glTF JSON with the buffer embedded as a data URI
+    pub fn to_gltf(&self) -> Result<Vec<u8>, serde_json::Error> {
+        let (mut doc, bin) = self.gltf_document();
+        if let Some(Value::Array(buffers)) = doc.get_mut("buffers") {
+            buffers[0]["uri"] = json!(format!(
+                "data:application/octet-stream;base64,{}",
+                BASE64_STANDARD.encode(&bin)
+            ));
+        }
+
+        serde_json::to_vec(&doc)
+    }
+
+    /// Binary glTF
+    pub fn to_glb(&self) -> Result<Vec<u8>, serde_json::Error> {
+        let (doc, bin) = self.gltf_document();
+        let mut json = serde_json::to_vec(&doc)?;
+        pad_to_four(&mut json, b' ');
+
+        let mut length = 12 + 8 + json.len();
+        if !bin.is_empty() {
+            length += 8 + bin.len();
+        }
+
+        let mut buf = Vec::with_capacity(length);
+        buf.extend_from_slice(b"glTF");
+        buf.extend_from_slice(&2u32.to_le_bytes());
+        buf.extend_from_slice(&(length as u32).to_le_bytes());
+        buf.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        buf.extend_from_slice(b"JSON");
+        buf.extend_from_slice(&json);
+        if !bin.is_empty() {
+            buf.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+            buf.extend_from_slice(b"BIN\0");
+            buf.extend_from_slice(&bin);
+        }
+
+        Ok(buf)
+    }
+
+    /// Wavefront OBJ. Node transforms are baked into the vertices and
+    /// materials are referenced by name only since OBJ can't embed textures.
+    pub fn to_obj(&self) -> String {
+        let mut obj = String::from("# Exported by dats.treestats.net\n");
+        let mut offset = 1;
+
+        for node in &self.nodes {
+            let mesh = match self.meshes.get(node.mesh) {
+                Some(val) => val,
+                None => continue,
+            };
+            obj.push_str(&format!("o {}\n", node.name));
+
+            for primitive in &mesh.primitives {
+                for vertex in &primitive.vertices {
+                    let scaled = [
+                        vertex.position[0] * node.scale[0],
+                        vertex.position[1] * node.scale[1],
+                        vertex.position[2] * node.scale[2],
+                    ];
+                    let rotated = rotate(node.rotation, scaled);
+                    let [x, y, z] = to_y_up([
+                        rotated[0] + node.translation[0],
+                        rotated[1] + node.translation[1],
+                        rotated[2] + node.translation[2],
+                    ]);
+                    let [nx, ny, nz] = to_y_up(rotate(node.rotation, vertex.normal));
+                    obj.push_str(&format!("v {} {} {}\n", x, y, z));
+                    obj.push_str(&format!("vt {} {}\n", vertex.uv[0], 1.0 - vertex.uv[1]));
+                    obj.push_str(&format!("vn {} {} {}\n", nx, ny, nz));
+                }
+
+                if let Some(material) = self.materials.get(primitive.material) {
+                    obj.push_str(&format!("usemtl {}\n", material.name));
+                }
+                for triangle in primitive.indices.chunks(3) {
+                    obj.push('f');
+                    for index in triangle {
+                        let i = offset + *index as usize;
+                        obj.push_str(&format!(" {}/{}/{}", i, i, i));
+                    }
+                    obj.push('\n');
+                }
+                offset += primitive.vertices.len();
+            }
+        }
+
+        obj
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(position: [f32; 3]) -> SourceVertex {
+        SourceVertex {
+            position,
+            normal: [0.0, 0.0, 1.0],
+            uvs: vec![[0.0, 0.0], [1.0, 1.0]],
+        }
+    }
+
+    fn quad() -> HashMap<u16, SourceVertex> {
+        HashMap::from([
+            (0, vertex([0.0, 0.0, 0.0])),
+            (1, vertex([1.0, 0.0, 0.0])),
+            (2, vertex([1.0, 1.0, 0.0])),
+            (3, vertex([0.0, 1.0, 0.0])),
+        ])
+    }
+
+    fn quad_face(surface: usize, back: bool) -> Face {
+        Face {
+            surface,
+            corners: vec![(0, 0), (1, 0), (2, 1), (3, 1)],
+            back,
+        }
+    }
+
+    fn model() -> Model {
+        let mesh = build_mesh(
+            "0x01000001".to_string(),
+            &quad(),
+            &[quad_face(0, false)],
+            &[0],
+        )
+        .unwrap();
+        Model {
+            meshes: vec![mesh],
+            nodes: vec![Node {
+                name: "0x01000001".to_string(),
+                mesh: 0,
+                translation: [0.0, 0.0, 2.0],
+                rotation: [0.0, 0.0, 0.0, 1.0],
+                scale: [1.0; 3],
+            }],
+            materials: vec![Material {
+                name: "0x08000001".to_string(),
+                color: [1.0; 4],
+                texture: Some(vec![0x89, b'P', b'N', b'G']),
+                alpha_mode: AlphaMode::Opaque,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_to_y_up() {
+        assert_eq!(to_y_up([1.0, 2.0, 3.0]), [1.0, 3.0, -2.0]);
+    }
+
+    #[test]
+    fn test_build_mesh_fans_and_reverses_winding() {
+        let mesh = build_mesh("m".to_string(), &quad(), &[quad_face(0, false)], &[0]).unwrap();
+
+        assert_eq!(mesh.primitives.len(), 1);
+        let primitive = &mesh.primitives[0];
+        assert_eq!(primitive.vertices.len(), 4);
+        assert_eq!(primitive.indices, vec![3, 2, 1, 3, 1, 0]);
+        assert_eq!(primitive.vertices[2].uv, [1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_build_mesh_back_faces() {
+        let faces = [quad_face(0, false), quad_face(1, true)];
+        let mesh = build_mesh("m".to_string(), &quad(), &faces, &[0, 1]).unwrap();
+
+        assert_eq!(mesh.primitives.len(), 2);
+        let back = &mesh.primitives[1];
+        assert_eq!(back.material, 1);
+        assert_eq!(back.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(back.vertices[0].normal, [0.0, 0.0, -1.0]);
+    }
+
+    #[test]
+    fn test_build_mesh_shares_primitives_by_material() {
+        let faces = [quad_face(0, false), quad_face(1, false)];
+        let mesh = build_mesh("m".to_string(), &quad(), &faces, &[0, 0]).unwrap();
+
+        assert_eq!(mesh.primitives.len(), 1);
+        assert_eq!(mesh.primitives[0].indices.len(), 12);
+        assert_eq!(mesh.primitives[0].vertices.len(), 4);
+    }
+
+    #[test]
+    fn test_build_mesh_rejects_bad_references() {
+        assert!(build_mesh("m".to_string(), &quad(), &[quad_face(1, false)], &[0]).is_err());
+
+        let face = Face {
+            surface: 0,
+            corners: vec![(0, 0), (1, 0), (9, 0)],
+            back: false,
+        };
+        assert!(build_mesh("m".to_string(), &quad(), &[face], &[0]).is_err());
+    }
+
+    #[test]
+    fn test_to_glb() {
+        let glb = model().to_glb().unwrap();
+
+        assert_eq!(&glb[0..4], b"glTF");
+        assert_eq!(u32::from_le_bytes(glb[4..8].try_into().unwrap()), 2);
+        assert_eq!(
+            u32::from_le_bytes(glb[8..12].try_into().unwrap()) as usize,
+            glb.len()
+        );
+
+        let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+        assert_eq!(&glb[16..20], b"JSON");
+        assert_eq!(json_length % 4, 0);
+        let doc: Value = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+
+        assert_eq!(doc["nodes"][0]["translation"], json!([0.0, 2.0, -0.0]));
+        assert_eq!(doc["images"][0]["mimeType"], "image/png");
+        assert_eq!(doc["accessors"][0]["max"], json!([1.0, 0.0, 0.0]));
+        assert_eq!(doc["accessors"][0]["min"], json!([0.0, 0.0, -1.0]));
+
+        let bin_header = 20 + json_length;
+        assert_eq!(&glb[bin_header + 4..bin_header + 8], b"BIN\0");
+        let bin_length =
+            u32::from_le_bytes(glb[bin_header..bin_header + 4].try_into().unwrap()) as usize;
+        assert_eq!(doc["buffers"][0]["byteLength"], json!(bin_length));
+    }
+
+    #[test]
+    fn test_to_gltf_embeds_buffer() {
+        let doc: Value = serde_json::from_slice(&model().to_gltf().unwrap()).unwrap();
+        let uri = doc["buffers"][0]["uri"].as_str().unwrap();
+        assert!(uri.starts_with("data:application/octet-stream;base64,"));
+    }
+
+    #[test]
+    fn test_empty_model_has_no_empty_arrays() {
+        let doc: Value = serde_json::from_slice(&Model::default().to_gltf().unwrap()).unwrap();
+        assert!(doc.get("meshes").is_none());
+        assert!(doc.get("buffers").is_none());
+    }
+
+    #[test]
+    fn test_to_obj_bakes_transforms() {
+        let obj = model().to_obj();
+
+        assert!(obj.contains("o 0x01000001\n"));
+        assert!(obj.contains("v 1 2 -0\n"));
+        assert!(obj.contains("vt 1 0\n"));
+        assert!(obj.contains("usemtl 0x08000001\n"));
+        assert!(obj.contains("f 4/4/4 3/3/3 2/2/2\n"));
+    }
+}
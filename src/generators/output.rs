@@ -0,0 +1,246 @@
+use std::io::Cursor;
+
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage, RgbaImage};
+use worker::{Response, ResponseBody};
+
+/// Image formats we can serve textures and icons as. PNG is the canonical
+/// format: it's what we render and persist, and everything else is encoded
+/// from it on the way out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Png,
+    Webp,
+    Jpeg,
+    Ico,
+    Bmp,
+}
+
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 5] = [
+        OutputFormat::Png,
+        OutputFormat::Webp,
+        OutputFormat::Jpeg,
+        OutputFormat::Ico,
+        OutputFormat::Bmp,
+    ];
+
+    /// Parse a `?format=` value
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "png" => Some(OutputFormat::Png),
+            "webp" => Some(OutputFormat::Webp),
+            "jpeg" | "jpg" => Some(OutputFormat::Jpeg),
+            "ico" => Some(OutputFormat::Ico),
+            "bmp" => Some(OutputFormat::Bmp),
+            _ => None,
+        }
+    }
+
+    /// Parse a media type from an Accept header
+    pub fn from_media_type(media_type: &str) -> Option<OutputFormat> {
+        match media_type.to_ascii_lowercase().as_str() {
+            "image/png" => Some(OutputFormat::Png),
+            "image/webp" => Some(OutputFormat::Webp),
+            "image/jpeg" | "image/jpg" => Some(OutputFormat::Jpeg),
+            "image/x-icon" | "image/vnd.microsoft.icon" => Some(OutputFormat::Ico),
+            "image/bmp" => Some(OutputFormat::Bmp),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Webp => "webp",
+            OutputFormat::Jpeg => "jpeg",
+            OutputFormat::Ico => "ico",
+            OutputFormat::Bmp => "bmp",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "jpg",
+            _ => self.name(),
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::Webp => "image/webp",
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Ico => "image/x-icon",
+            OutputFormat::Bmp => "image/bmp",
+        }
+    }
+
+    /// Pick a format from `?format=`, falling back to the Accept header and
+    /// then PNG. An explicit but unknown format is an error; unknown media
+    /// types in Accept are ignored.
+    pub fn negotiate(format: Option<&str>, accept: Option<&str>) -> Result<OutputFormat, String> {
+        if let Some(name) = format {
+            return OutputFormat::from_name(name).ok_or_else(|| {
+                let names: Vec<&str> = OutputFormat::ALL.iter().map(|f| f.name()).collect();
+                format!(
+                    "Unsupported image format: {}. Use one of {}.",
+                    name,
+                    names.join(", ")
+                )
+            });
+        }
+
+        let mut best: Option<(f32, OutputFormat)> = None;
+        for entry in accept.unwrap_or_default().split(',') {
+            let mut parts = entry.split(';');
+            let media_type = parts.next().unwrap_or_default().trim();
+            let quality = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|value| value.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            if quality <= 0.0 {
+                continue;
+            }
+
+            // Earlier entries win ties
+            if let Some(format) = OutputFormat::from_media_type(media_type) {
+                if best.is_none_or(|(best_quality, _)| quality > best_quality) {
+                    best = Some((quality, format));
+                }
+            }
+        }
+
+        Ok(best.map(|(_, format)| format).unwrap_or(OutputFormat::Png))
+    }
+
+    /// Encode an image in this format
+    pub fn encode(&self, image: &RgbaImage) -> Result<Vec<u8>, String> {
+        let mut buf = Cursor::new(Vec::new());
+        let result = match self {
+            OutputFormat::Png => image.write_to(&mut buf, ImageFormat::Png),
+            OutputFormat::Webp => image.write_to(&mut buf, ImageFormat::WebP),
+            OutputFormat::Jpeg => flatten(image).write_to(&mut buf, ImageFormat::Jpeg),
+            OutputFormat::Ico => {
+                if image.width() > 256 || image.height() > 256 {
+                    return Err(format!(
+                        "ICO images can be at most 256x256, this one is {}x{}",
+                        image.width(),
+                        image.height()
+                    ));
+                }
+                image.write_to(&mut buf, ImageFormat::Ico)
+            }
+            OutputFormat::Bmp => image.write_to(&mut buf, ImageFormat::Bmp),
+        };
+        result.map_err(|err| format!("Failed to encode {}: {}", self.name(), err))?;
+        Ok(buf.into_inner())
+    }
+
+    /// Re-encode a PNG in this format. PNGs are passed through untouched.
+    pub fn transcode(&self, png: Vec<u8>) -> Result<Vec<u8>, String> {
+        if *self == OutputFormat::Png {
+            return Ok(png);
+        }
+
+        let image = image::load_from_memory_with_format(&png, ImageFormat::Png)
+            .map_err(|err| format!("Failed to decode PNG: {}", err))?;
+        self.encode(&image.to_rgba8())
+    }
+}
+
+/// Wrap an encoded image in a response. `name` is used for the
+/// Content-Disposition filename, e.g. `0x06006957` becomes `0x06006957.png`.
+pub fn generate_image(buf: Vec<u8>, format: OutputFormat, name: &str) -> worker::Result<Response> {
+    let mut response = Response::from_body(ResponseBody::Body(buf))?;
+
+    response
+        .headers_mut()
+        .set("Content-Type", format.content_type())?;
+    response.headers_mut().set(
+        "Content-Disposition",
+        &format!("inline; filename=\"{}.{}\"", name, format.extension()),
+    )?;
+
+    Ok(response)
+}
+
+/// JPEG has no alpha channel so transparent pixels are composited onto black
+fn flatten(image: &RgbaImage) -> DynamicImage {
+    let mut flat = RgbImage::new(image.width(), image.height());
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let [r, g, b, a] = pixel.0;
+        let blend = |channel: u8| (channel as u16 * a as u16 / 255) as u8;
+        flat.put_pixel(x, y, Rgb([blend(r), blend(g), blend(b)]));
+    }
+    DynamicImage::ImageRgb8(flat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_negotiate_prefers_format_param() {
+        assert_eq!(
+            OutputFormat::negotiate(Some("webp"), Some("image/png")),
+            Ok(OutputFormat::Webp)
+        );
+        assert_eq!(
+            OutputFormat::negotiate(Some("JPG"), None),
+            Ok(OutputFormat::Jpeg)
+        );
+        assert!(OutputFormat::negotiate(Some("gif"), None).is_err());
+    }
+
+    #[test]
+    fn test_negotiate_accept() {
+        assert_eq!(OutputFormat::negotiate(None, None), Ok(OutputFormat::Png));
+        assert_eq!(
+            OutputFormat::negotiate(None, Some("*/*")),
+            Ok(OutputFormat::Png)
+        );
+        // What browsers send for <img>
+        assert_eq!(
+            OutputFormat::negotiate(
+                None,
+                Some("image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8")
+            ),
+            Ok(OutputFormat::Webp)
+        );
+        assert_eq!(
+            OutputFormat::negotiate(None, Some("image/png;q=0.5, image/x-icon")),
+            Ok(OutputFormat::Ico)
+        );
+        assert_eq!(
+            OutputFormat::negotiate(None, Some("image/webp;q=0, image/bmp;q=0.1")),
+            Ok(OutputFormat::Bmp)
+        );
+    }
+
+    #[test]
+    fn test_encode_all_formats() {
+        let image = RgbaImage::from_pixel(32, 32, Rgba([255, 0, 0, 128]));
+        let png = OutputFormat::Png.encode(&image).unwrap();
+
+        for format in OutputFormat::ALL {
+            let buf = format.transcode(png.clone()).unwrap();
+            let detected = image::guess_format(&buf).unwrap();
+            assert_eq!(detected.to_mime_type(), format.content_type());
+        }
+    }
+
+    #[test]
+    fn test_ico_size_limit() {
+        let image = RgbaImage::new(512, 512);
+        assert!(OutputFormat::Ico.encode(&image).is_err());
+    }
+
+    #[test]
+    fn test_jpeg_flattens_alpha() {
+        let image = RgbaImage::from_pixel(8, 8, Rgba([255, 255, 255, 0]));
+        let flat = flatten(&image);
+        assert_eq!(flat.to_rgb8().get_pixel(0, 0), &Rgb([0, 0, 0]));
+    }
+}
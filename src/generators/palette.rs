@@ -0,0 +1,289 @@
+use std::{collections::HashMap, io::Cursor};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use image::RgbaImage;
+use serde::Serialize;
+
+use super::texture::argb_to_rgba;
+use crate::parse_file_id;
+
+pub const SWATCH_SIZE: u32 = 16;
+pub const SWATCH_COLUMNS: u32 = 64;
+
+/// JSON form of a Palette with each color as [r, g, b, a]
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PaletteColors {
+    pub id: u32,
+    pub colors: Vec<[u8; 4]>,
+}
+
+impl PaletteColors {
+    pub fn new(id: u32, argb: &[u32]) -> Self {
+        PaletteColors {
+            id,
+            colors: argb.iter().map(|color| argb_to_rgba(*color).0).collect(),
+        }
+    }
+}
+
+/// JSON form of a PaletteSet
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PaletteSetPalettes {
+    pub id: u32,
+    pub palettes: Vec<u32>,
+}
+
+/// Parse a PaletteSet (0x0F) file into its Palette IDs.
+pub fn parse_palette_set(buf: &[u8]) -> Result<Vec<u32>, String> {
+    let mut reader = Cursor::new(buf);
+    // Skip the file ID
+    reader.set_position(4);
+
+    let read_error = |err: std::io::Error| format!("Failed to read palette set: {}", err);
+    let count = reader.read_u32::<LittleEndian>().map_err(read_error)?;
+    (0..count)
+        .map(|_| reader.read_u32::<LittleEndian>().map_err(read_error))
+        .collect()
+}
+
+/// Pick a Palette from a PaletteSet's IDs. Shades run from 0 to 1 across the
+/// set, matching how the client picks dye colors.
+pub fn palette_from_set(palette_ids: &[u32], shade: f64) -> Option<u32> {
+    if palette_ids.is_empty() || !(0.0..=1.0).contains(&shade) {
+        return None;
+    }
+
+    let index = ((palette_ids.len() as f64 - 0.000001) * shade) as usize;
+    palette_ids.get(index).copied()
+}
+
+/// Parse the shade query parameter, a number from 0 to 1. Errors are
+/// messages suitable for a 400 response.
+pub fn parse_shade(params: &HashMap<String, String>) -> Result<Option<f64>, String> {
+    let shade = params
+        .get("shade")
+        .map(|value| value.parse::<f64>())
+        .transpose()
+        .map_err(|err| format!("Failed to parse query parameter: shade. Error: {}", err))?;
+
+    match shade {
+        Some(shade) if !(0.0..=1.0).contains(&shade) => {
+            Err("Choose a shade value between 0 and 1".to_string())
+        }
+        _ => Ok(shade),
+    }
+}
+
+/// A substitute palette for paletted textures, either given directly or
+/// picked from a PaletteSet by shade
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PaletteChoice {
+    Palette(u32),
+    PaletteSet { id: u32, shade: f64 },
+}
+
+impl PaletteChoice {
+    /// Parse the palette, palette_set, and shade query parameters. Errors are
+    /// messages suitable for a 400 response.
+    pub fn from_params(params: &HashMap<String, String>) -> Result<Option<PaletteChoice>, String> {
+        let parse_id = |name: &str| -> Result<Option<u32>, String> {
+            params
+                .get(name)
+                .map(|value| {
+                    parse_file_id(value).map_err(|err| {
+                        format!("Failed to parse query parameter: {}. Error: {}", name, err)
+                    })
+                })
+                .transpose()
+        };
+
+        match (
+            parse_id("palette")?,
+            parse_id("palette_set")?,
+            parse_shade(params)?,
+        ) {
+            (None, None, None) => Ok(None),
+            (Some(id), None, None) => Ok(Some(PaletteChoice::Palette(id))),
+            (None, Some(id), shade) => Ok(Some(PaletteChoice::PaletteSet {
+                id,
+                shade: shade.unwrap_or(0.0),
+            })),
+            (Some(_), Some(_), _) => {
+                Err("Specify either palette or palette_set, not both".to_string())
+            }
+            (_, None, Some(_)) => Err("shade can only be used with palette_set".to_string()),
+        }
+    }
+
+    /// The Palette or PaletteSet file this choice reads
+    pub fn file_id(&self) -> u32 {
+        match self {
+            PaletteChoice::Palette(id) => *id,
+            PaletteChoice::PaletteSet { id, .. } => *id,
+        }
+    }
+
+    /// Query string fragment for cache keys, e.g. `&palette=0x04000001`
+    pub fn cache_param(&self) -> String {
+        match self {
+            PaletteChoice::Palette(id) => format!("&palette=0x{:08X}", id),
+            PaletteChoice::PaletteSet { id, shade } => {
+                format!("&palette_set=0x{:08X}&shade={}", id, shade)
+            }
+        }
+    }
+}
+
+/// Render palette colors as square swatches, left to right and wrapping every
+/// SWATCH_COLUMNS colors.
+pub fn render_swatches(argb: &[u32]) -> Result<RgbaImage, String> {
+    if argb.is_empty() {
+        return Err("Palette has no colors".to_string());
+    }
+
+    let count = argb.len() as u32;
+    let columns = count.min(SWATCH_COLUMNS);
+    let rows = count.div_ceil(columns);
+    let mut image = RgbaImage::new(columns * SWATCH_SIZE, rows * SWATCH_SIZE);
+
+    for (i, color) in argb.iter().enumerate() {
+        let left = (i as u32 % columns) * SWATCH_SIZE;
+        let top = (i as u32 / columns) * SWATCH_SIZE;
+        let rgba = argb_to_rgba(*color);
+        for y in top..top + SWATCH_SIZE {
+            for x in left..left + SWATCH_SIZE {
+                image.put_pixel(x, y, rgba);
+            }
+        }
+    }
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_palette_colors() {
+        let palette = PaletteColors::new(0x04000001, &[0xFF102030, 0x80405060]);
+        assert_eq!(
+            palette.colors,
+            vec![[0x10, 0x20, 0x30, 0xFF], [0x40, 0x50, 0x60, 0x80]]
+        );
+        assert_eq!(
+            serde_json::to_value(&palette).unwrap(),
+            serde_json::json!({ "id": 0x04000001, "colors": [[16, 32, 48, 255], [64, 80, 96, 128]] })
+        );
+    }
+
+    #[test]
+    fn test_parse_palette_set() {
+        let mut buf = Vec::new();
+        for value in [0x0F000001u32, 2, 0x04000001, 0x04000002] {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        assert_eq!(
+            parse_palette_set(&buf).unwrap(),
+            vec![0x04000001, 0x04000002]
+        );
+        assert!(parse_palette_set(&buf[..12]).is_err());
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_palette_from_set() {
+        let ids = [0x04000001, 0x04000002, 0x04000003, 0x04000004];
+        assert_eq!(palette_from_set(&ids, 0.0), Some(0x04000001));
+        assert_eq!(palette_from_set(&ids, 0.25), Some(0x04000001));
+        assert_eq!(palette_from_set(&ids, 0.26), Some(0x04000002));
+        assert_eq!(palette_from_set(&ids, 1.0), Some(0x04000004));
+        assert_eq!(palette_from_set(&ids, 1.5), None);
+        assert_eq!(palette_from_set(&[], 0.5), None);
+    }
+
+    #[test]
+    fn test_parse_shade() {
+        assert_eq!(parse_shade(&params(&[])), Ok(None));
+        assert_eq!(parse_shade(&params(&[("shade", "0.25")])), Ok(Some(0.25)));
+        assert!(parse_shade(&params(&[("shade", "-0.1")])).is_err());
+        assert!(parse_shade(&params(&[("shade", "dark")])).is_err());
+    }
+
+    #[test]
+    fn test_palette_choice_from_params() {
+        assert_eq!(PaletteChoice::from_params(&params(&[])), Ok(None));
+        assert_eq!(
+            PaletteChoice::from_params(&params(&[("palette", "0x04000001")])),
+            Ok(Some(PaletteChoice::Palette(0x04000001)))
+        );
+        assert_eq!(
+            PaletteChoice::from_params(&params(&[("palette_set", "0x0F000001"), ("shade", "0.5")])),
+            Ok(Some(PaletteChoice::PaletteSet {
+                id: 0x0F000001,
+                shade: 0.5
+            }))
+        );
+        assert_eq!(
+            PaletteChoice::from_params(&params(&[("palette_set", "0x0F000001")])),
+            Ok(Some(PaletteChoice::PaletteSet {
+                id: 0x0F000001,
+                shade: 0.0
+            }))
+        );
+
+        assert!(PaletteChoice::from_params(&params(&[("shade", "0.5")])).is_err());
+        assert!(PaletteChoice::from_params(&params(&[
+            ("palette", "0x04000001"),
+            ("palette_set", "0x0F000001")
+        ]))
+        .is_err());
+        assert!(PaletteChoice::from_params(&params(&[
+            ("palette_set", "0x0F000001"),
+            ("shade", "2")
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_palette_choice_cache_param() {
+        assert_eq!(
+            PaletteChoice::Palette(0x04000001).cache_param(),
+            "&palette=0x04000001"
+        );
+        assert_eq!(
+            PaletteChoice::PaletteSet {
+                id: 0x0F000001,
+                shade: 0.5
+            }
+            .cache_param(),
+            "&palette_set=0x0F000001&shade=0.5"
+        );
+    }
+
+    #[test]
+    fn test_render_swatches() {
+        let image = render_swatches(&[0xFFFF0000, 0xFF00FF00]).unwrap();
+        assert_eq!(image.dimensions(), (2 * SWATCH_SIZE, SWATCH_SIZE));
+        assert_eq!(image.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(
+            image.get_pixel(SWATCH_SIZE, SWATCH_SIZE - 1),
+            &Rgba([0, 255, 0, 255])
+        );
+
+        let image = render_swatches(&vec![0xFF000000; 100]).unwrap();
+        assert_eq!(
+            image.dimensions(),
+            (SWATCH_COLUMNS * SWATCH_SIZE, 2 * SWATCH_SIZE)
+        );
+
+        assert!(render_swatches(&[]).is_err());
+    }
+}
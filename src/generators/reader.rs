@@ -0,0 +1,209 @@
+use std::{
+    collections::BTreeMap,
+    io::{Cursor, Read},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+/// Reads the little-endian fields of DAT files acprotocol doesn't parse.
+/// Errors name the kind of file being read, e.g. "Failed to read string
+/// table: ...".
+pub struct Reader<'a> {
+    cursor: Cursor<&'a [u8]>,
+    what: &'static str,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8], what: &'static str) -> Reader<'a> {
+        Reader {
+            cursor: Cursor::new(buf),
+            what,
+        }
+    }
+
+    fn error(&self, err: impl std::fmt::Display) -> String {
+        format!("Failed to read {}: {}", self.what, err)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        self.cursor.read_u8().map_err(|err| self.error(err))
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        self.cursor
+            .read_u16::<LittleEndian>()
+            .map_err(|err| self.error(err))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        self.cursor
+            .read_u32::<LittleEndian>()
+            .map_err(|err| self.error(err))
+    }
+
+    pub fn i32(&mut self) -> Result<i32, String> {
+        self.cursor
+            .read_i32::<LittleEndian>()
+            .map_err(|err| self.error(err))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        self.cursor
+            .read_u64::<LittleEndian>()
+            .map_err(|err| self.error(err))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, String> {
+        self.cursor
+            .read_f32::<LittleEndian>()
+            .map_err(|err| self.error(err))
+    }
+
+    pub fn f64(&mut self) -> Result<f64, String> {
+        self.cursor
+            .read_f64::<LittleEndian>()
+            .map_err(|err| self.error(err))
+    }
+
+    /// One, two or four bytes depending on the high bits of the first
+    pub fn compressed_u32(&mut self) -> Result<u32, String> {
+        let b0 = self.u8()? as u32;
+        if b0 & 0x80 == 0 {
+            return Ok(b0);
+        }
+
+        let b1 = self.u8()? as u32;
+        if b0 & 0x40 == 0 {
+            return Ok(((b0 & 0x7F) << 8) | b1);
+        }
+
+        let low = self.u16()? as u32;
+        Ok(((((b0 & 0x3F) << 8) | b1) << 16) | low)
+    }
+
+    /// Skip to the next multiple of four bytes from the start of the file
+    pub fn align(&mut self) {
+        let position = self.cursor.position();
+        self.cursor.set_position((position + 3) & !3);
+    }
+
+    /// `len` bytes, checked against what's left so a bad length can't
+    /// allocate more than the file holds
+    pub fn bytes(&mut self, len: usize) -> Result<Vec<u8>, String> {
+        let remaining = self.cursor.get_ref().len() - self.cursor.position() as usize;
+        if len > remaining {
+            return Err(self.error(format!(
+                "{} bytes needed but only {} remain",
+                len, remaining
+            )));
+        }
+
+        let mut buf = vec![0; len];
+        self.cursor
+            .read_exact(&mut buf)
+            .map_err(|err| self.error(err))?;
+        Ok(buf)
+    }
+
+    /// A u16 length followed by that many single-byte characters, padded to
+    /// four bytes
+    pub fn pstring(&mut self) -> Result<String, String> {
+        let len = self.u16()? as usize;
+        let buf = self.bytes(len)?;
+        self.align();
+
+        Ok(buf.into_iter().map(char::from).collect())
+    }
+
+    /// A compressed length followed by that many single-byte (Windows-1252)
+    /// characters
+    pub fn compressed_pstring(&mut self) -> Result<String, String> {
+        let len = self.compressed_u32()? as usize;
+        Ok(self.bytes(len)?.into_iter().map(char::from).collect())
+    }
+
+    /// A compressed length followed by that many UTF-16 code units
+    pub fn unicode_string(&mut self) -> Result<String, String> {
+        let len = self.compressed_u32()? as usize;
+        let units: Vec<u16> = self
+            .bytes(len * 2)?
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        Ok(String::from_utf16_lossy(&units))
+    }
+
+    /// A u16 length followed by that many bytes with their nibbles swapped,
+    /// padded to four bytes
+    pub fn obfuscated_string(&mut self) -> Result<Vec<u8>, String> {
+        let len = self.u16()? as usize;
+        let buf = self.bytes(len)?;
+        self.align();
+
+        Ok(buf.into_iter().map(|byte| byte.rotate_left(4)).collect())
+    }
+
+    /// A u32 count followed by that many items
+    pub fn list<T>(
+        &mut self,
+        read: impl Fn(&mut Self) -> Result<T, String>,
+    ) -> Result<Vec<T>, String> {
+        let count = self.u32()?;
+        (0..count).map(|_| read(self)).collect()
+    }
+
+    /// A u16 count and u16 bucket size followed by that many u32 keyed items
+    pub fn hash_table<T>(
+        &mut self,
+        read: impl Fn(&mut Self) -> Result<T, String>,
+    ) -> Result<BTreeMap<u32, T>, String> {
+        let count = self.u16()?;
+        let _buckets = self.u16()?;
+        (0..count).map(|_| Ok((self.u32()?, read(self)?))).collect()
+    }
+
+    /// A compressed count followed by that many u32 keyed items
+    pub fn smart_map<T>(
+        &mut self,
+        read: impl Fn(&mut Self) -> Result<T, String>,
+    ) -> Result<BTreeMap<u32, T>, String> {
+        let count = self.compressed_u32()?;
+        (0..count).map(|_| Ok((self.u32()?, read(self)?))).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compressed_u32() {
+        let buf = [0x7F, 0x81, 0x02, 0xC1, 0x02, 0x04, 0x03];
+        let mut reader = Reader::new(&buf, "test");
+
+        assert_eq!(reader.compressed_u32(), Ok(0x7F));
+        assert_eq!(reader.compressed_u32(), Ok(0x0102));
+        assert_eq!(reader.compressed_u32(), Ok(0x0102_0304));
+        assert!(reader.compressed_u32().is_err());
+    }
+
+    #[test]
+    fn test_pstring_aligns() {
+        let buf = [
+            0x03, 0x00, b'a', b'b', b'c', 0x00, 0x00, 0x00, 0x2A, 0, 0, 0,
+        ];
+        let mut reader = Reader::new(&buf, "test");
+
+        assert_eq!(reader.pstring(), Ok("abc".to_string()));
+        assert_eq!(reader.u32(), Ok(42));
+    }
+
+    #[test]
+    fn test_bytes_rejects_overlong_lengths() {
+        let mut reader = Reader::new(&[0xFF, 0xFF, b'a'], "string table");
+        assert_eq!(
+            reader.pstring(),
+            Err("Failed to read string table: 65535 bytes needed but only 1 remain".to_string())
+        );
+    }
+}
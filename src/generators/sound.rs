@@ -0,0 +1,160 @@
+use std::io::{Cursor, Read};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+// WAVEFORMATEX::wFormatTag values
+pub const WAVE_FORMAT_PCM: u16 = 0x1;
+pub const WAVE_FORMAT_MPEGLAYER3: u16 = 0x55;
+
+/// A Wave file: a WAVEFORMATEX header followed by the sample data
+#[derive(Debug, PartialEq)]
+pub struct Wave {
+    pub header: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+/// Parse a Wave file. The layout is the file ID, the header and data sizes,
+/// then the header and data themselves.
+pub fn parse_wave(buf: &[u8]) -> Result<Wave, String> {
+    let mut reader = Cursor::new(buf);
+    reader.set_position(4);
+    let header_size = reader
+        .read_u32::<LittleEndian>()
+        .map_err(|err| format!("Failed to read header size: {}", err))?;
+    let data_size = reader
+        .read_u32::<LittleEndian>()
+        .map_err(|err| format!("Failed to read data size: {}", err))?;
+
+    let remaining = buf.len() - reader.position() as usize;
+    if header_size as usize + data_size as usize > remaining {
+        return Err(format!(
+            "Wave header and data are {} bytes but only {} remain",
+            header_size as usize + data_size as usize,
+            remaining
+        ));
+    }
+
+    let mut header = vec![0; header_size as usize];
+    let mut data = vec![0; data_size as usize];
+    reader
+        .read_exact(&mut header)
+        .and_then(|_| reader.read_exact(&mut data))
+        .map_err(|err| format!("Failed to read wave: {}", err))?;
+
+    Ok(Wave { header, data })
+}
+
+impl Wave {
+    pub fn format_tag(&self) -> Option<u16> {
+        self.header
+            .get(0..2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// The playable file, its content type and its file extension. MP3 data is
+    /// passed through as-is and everything else is wrapped in a RIFF WAV.
+    pub fn to_audio(&self) -> (Vec<u8>, &'static str, &'static str) {
+        if self.format_tag() == Some(WAVE_FORMAT_MPEGLAYER3) {
+            (self.data.clone(), "audio/mpeg", "mp3")
+        } else {
+            (self.to_riff(), "audio/wav", "wav")
+        }
+    }
+
+    pub fn to_riff(&self) -> Vec<u8> {
+        // Chunks are padded to an even length
+        let pad = self.data.len() % 2;
+        let riff_size = 4 + (8 + self.header.len()) + (8 + self.data.len() + pad);
+
+        let mut buf = Vec::with_capacity(8 + riff_size);
+        buf.extend_from_slice(b"RIFF");
+        buf.extend_from_slice(&(riff_size as u32).to_le_bytes());
+        buf.extend_from_slice(b"WAVE");
+        buf.extend_from_slice(b"fmt ");
+        buf.extend_from_slice(&(self.header.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&self.header);
+        buf.extend_from_slice(b"data");
+        buf.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&self.data);
+        if pad == 1 {
+            buf.push(0);
+        }
+
+        buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wave_file(header: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&0x0A000001u32.to_le_bytes());
+        buf.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        buf.extend_from_slice(header);
+        buf.extend_from_slice(data);
+        buf
+    }
+
+    // 22050Hz 16-bit mono PCM
+    fn pcm_header() -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&WAVE_FORMAT_PCM.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&22050u32.to_le_bytes());
+        header.extend_from_slice(&44100u32.to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header
+    }
+
+    #[test]
+    fn test_parse_wave() {
+        let wave = parse_wave(&wave_file(&pcm_header(), &[1, 2, 3, 4])).unwrap();
+        assert_eq!(wave.header, pcm_header());
+        assert_eq!(wave.data, vec![1, 2, 3, 4]);
+        assert_eq!(wave.format_tag(), Some(WAVE_FORMAT_PCM));
+    }
+
+    #[test]
+    fn test_parse_wave_rejects_truncated() {
+        let mut buf = wave_file(&pcm_header(), &[1, 2, 3, 4]);
+        buf.pop();
+        assert!(parse_wave(&buf).is_err());
+        assert!(parse_wave(&[0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_to_riff() {
+        let wave = parse_wave(&wave_file(&pcm_header(), &[1, 2, 3])).unwrap();
+        let (riff, content_type, extension) = wave.to_audio();
+
+        assert_eq!(content_type, "audio/wav");
+        assert_eq!(extension, "wav");
+        assert_eq!(&riff[0..4], b"RIFF");
+        assert_eq!(
+            u32::from_le_bytes(riff[4..8].try_into().unwrap()) as usize,
+            riff.len() - 8
+        );
+        assert_eq!(&riff[8..16], b"WAVEfmt ");
+        assert_eq!(u32::from_le_bytes(riff[16..20].try_into().unwrap()), 18);
+        assert_eq!(&riff[38..42], b"data");
+        assert_eq!(u32::from_le_bytes(riff[42..46].try_into().unwrap()), 3);
+        assert_eq!(&riff[46..], &[1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn test_mp3_passthrough() {
+        let mut header = pcm_header();
+        header[0..2].copy_from_slice(&WAVE_FORMAT_MPEGLAYER3.to_le_bytes());
+        let wave = parse_wave(&wave_file(&header, &[0xFF, 0xFB, 0x90])).unwrap();
+
+        let (audio, content_type, extension) = wave.to_audio();
+        assert_eq!(audio, vec![0xFF, 0xFB, 0x90]);
+        assert_eq!(content_type, "audio/mpeg");
+        assert_eq!(extension, "mp3");
+    }
+}
@@ -0,0 +1,515 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Cursor,
+};
+
+use acprotocol::dat::file_types::SpellTable;
+use serde::Serialize;
+
+use super::reader::Reader;
+
+pub const SPELL_TABLE_ID: u32 = 0x0E00000E;
+pub const SPELL_COMPONENT_TABLE_ID: u32 = 0x0E00000F;
+
+// The highest component ID. Decrypted IDs above it come from names and
+// descriptions with extended characters and only their low byte is valid.
+const MAX_COMPONENT_ID: u32 = 198;
+
+/// Spell level by the scarab a formula starts with
+const SCARAB_LEVELS: [(u32, u32); 10] = [
+    (1, 1),   // Lead Scarab
+    (2, 2),   // Iron Scarab
+    (3, 3),   // Copper Scarab
+    (4, 4),   // Silver Scarab
+    (5, 5),   // Gold Scarab
+    (6, 6),   // Pyreal Scarab
+    (110, 6), // Diamond Scarab
+    (112, 7), // Platinum Scarab
+    (192, 7), // Dark Scarab
+    (193, 8), // Mana Scarab
+];
+
+pub const SCHOOLS: [(u32, &str); 5] = [
+    (1, "War Magic"),
+    (2, "Life Magic"),
+    (3, "Item Enchantment"),
+    (4, "Creature Enchantment"),
+    (5, "Void Magic"),
+];
+
+/// One spell from the SpellTable, with its formula decrypted to component IDs
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Spell {
+    pub id: u32,
+    pub name: String,
+    pub description: String,
+    pub school: u32,
+    pub icon: u32,
+    pub category: u32,
+    pub bitfield: u32,
+    pub base_mana: u32,
+    pub base_range_constant: f32,
+    pub base_range_mod: f32,
+    pub power: u32,
+    pub spell_economy_mod: f32,
+    pub formula_version: u32,
+    pub component_loss: f32,
+    pub meta_spell_type: u32,
+    pub meta_spell_id: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub degrade_modifier: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub degrade_limit: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub portal_lifetime: Option<f64>,
+    /// Component IDs
+    pub formula: Vec<u32>,
+    pub caster_effect: u32,
+    pub target_effect: u32,
+    pub fizzle_effect: u32,
+    pub recovery_interval: f64,
+    pub recovery_amount: f32,
+    pub display_order: u32,
+    pub non_component_target_type: u32,
+    pub mana_mod: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SpellComponent {
+    pub name: String,
+    pub category: u32,
+    pub icon: u32,
+    #[serde(rename = "type")]
+    pub component_type: u32,
+    pub gesture: u32,
+    pub time: f32,
+    pub text: String,
+    pub cdm: f32,
+}
+
+/// The SpellComponentTable (0x0E00000F)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SpellComponentTable {
+    pub id: u32,
+    pub components: BTreeMap<u32, SpellComponent>,
+}
+
+/// A spell as listed by /spells
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SpellSummary {
+    pub id: u32,
+    pub name: String,
+    pub school: u32,
+    pub school_name: Option<&'static str>,
+    pub level: Option<u32>,
+    pub icon: u32,
+}
+
+/// A spell component in a formula, with its details when the
+/// SpellComponentTable has them
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SpellComponentRef {
+    pub id: u32,
+    #[serde(flatten)]
+    pub component: Option<SpellComponent>,
+}
+
+/// A spell as returned by /spells/:id
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SpellDetail {
+    #[serde(flatten)]
+    pub spell: Spell,
+    pub school_name: Option<&'static str>,
+    pub level: Option<u32>,
+    pub icon_url: String,
+    pub components: Vec<SpellComponentRef>,
+}
+
+fn spell_component(reader: &mut Reader) -> Result<SpellComponent, String> {
+    Ok(SpellComponent {
+        name: windows_1252(&reader.obfuscated_string()?),
+        category: reader.u32()?,
+        icon: reader.u32()?,
+        component_type: reader.u32()?,
+        gesture: reader.u32()?,
+        time: reader.f32()?,
+        text: windows_1252(&reader.obfuscated_string()?),
+        cdm: reader.f32()?,
+    })
+}
+
+// Close enough for spell text, which is plain ASCII apart from the odd
+// accented letter
+fn windows_1252(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| char::from(byte)).collect()
+}
+
+/// The bytes a string was decoded from with `windows_1252`, which is what
+/// formula keys are hashed over
+fn windows_1252_bytes(text: &str) -> Vec<u8> {
+    text.chars().map(|c| c as u32 as u8).collect()
+}
+
+/// The hash the client keys spell formulas with. Bytes are signed.
+fn string_hash(bytes: &[u8]) -> u32 {
+    let mut result: i64 = 0;
+    for &byte in bytes {
+        result = (byte as i8 as i64) + (result << 4);
+        if result & 0xF000_0000 != 0 {
+            result = (result ^ ((result & 0xF000_0000) >> 24)) & 0x0FFF_FFFF;
+        }
+    }
+    result as u32
+}
+
+fn formula_key(name: &[u8], description: &[u8]) -> u32 {
+    (string_hash(name) % 0x1210_7680).wrapping_add(string_hash(description) % 0xBEAD_CF45)
+}
+
+/// Formulas are stored offset by a key derived from the spell's name and
+/// description. Empty slots are dropped.
+fn decrypt_formula(formula: &[u32], name: &[u8], description: &[u8]) -> Vec<u32> {
+    let key = formula_key(name, description);
+    formula
+        .iter()
+        .map(|component| {
+            let component = component.wrapping_sub(key);
+            if component > MAX_COMPONENT_ID {
+                component & 0xFF
+            } else {
+                component
+            }
+        })
+        .filter(|&component| component != 0)
+        .collect()
+}
+
+/// Read the SpellTable (0x0E00000E) and decrypt each spell's formula. Spell
+/// sets are not kept.
+pub fn parse_spell_table(buf: &[u8]) -> Result<BTreeMap<u32, Spell>, String> {
+    let table = SpellTable::read(&mut Cursor::new(buf))
+        .map_err(|err| format!("Failed to read spell table: {}", err))?;
+
+    Ok(table
+        .spells
+        .iter()
+        .map(|(&id, spell)| {
+            let name = spell.name.to_string();
+            let description = spell.description.to_string();
+            let formula = decrypt_formula(
+                &spell.formula,
+                &windows_1252_bytes(&name),
+                &windows_1252_bytes(&description),
+            );
+
+            let spell = Spell {
+                id,
+                name,
+                description,
+                school: spell.school as u32,
+                icon: spell.icon,
+                category: spell.category as u32,
+                bitfield: spell.bitfield,
+                base_mana: spell.base_mana,
+                base_range_constant: spell.base_range_constant,
+                base_range_mod: spell.base_range_mod,
+                power: spell.power,
+                spell_economy_mod: spell.spell_economy_mod,
+                formula_version: spell.formula_version,
+                component_loss: spell.component_loss,
+                meta_spell_type: spell.meta_spell_type as u32,
+                meta_spell_id: spell.meta_spell_id,
+                duration: spell.duration,
+                degrade_modifier: spell.degrade_modifier,
+                degrade_limit: spell.degrade_limit,
+                portal_lifetime: spell.portal_lifetime,
+                formula,
+                caster_effect: spell.caster_effect,
+                target_effect: spell.target_effect,
+                fizzle_effect: spell.fizzle_effect,
+                recovery_interval: spell.recovery_interval,
+                recovery_amount: spell.recovery_amount,
+                display_order: spell.display_order,
+                non_component_target_type: spell.non_component_target_type,
+                mana_mod: spell.mana_mod,
+            };
+            (id, spell)
+        })
+        .collect())
+}
+
+/// Parse the SpellComponentTable (0x0E00000F).
+pub fn parse_spell_component_table(buf: &[u8]) -> Result<SpellComponentTable, String> {
+    let mut reader = Reader::new(buf, "spell component table");
+
+    Ok(SpellComponentTable {
+        id: reader.u32()?,
+        components: reader.hash_table(spell_component)?,
+    })
+}
+
+pub fn school_name(school: u32) -> Option<&'static str> {
+    SCHOOLS
+        .iter()
+        .find(|(id, _)| *id == school)
+        .map(|(_, name)| *name)
+}
+
+impl Spell {
+    /// The level of the scarab the formula starts with. Spells without one,
+    /// like most NPC-only spells, have no level.
+    pub fn level(&self) -> Option<u32> {
+        self.formula.iter().find_map(|component| {
+            SCARAB_LEVELS
+                .iter()
+                .find(|(scarab, _)| scarab == component)
+                .map(|(_, level)| *level)
+        })
+    }
+
+    pub fn summary(&self) -> SpellSummary {
+        SpellSummary {
+            id: self.id,
+            name: self.name.clone(),
+            school: self.school,
+            school_name: school_name(self.school),
+            level: self.level(),
+            icon: self.icon,
+        }
+    }
+
+    pub fn detail(&self, components: &SpellComponentTable, icon_url: String) -> SpellDetail {
+        SpellDetail {
+            spell: self.clone(),
+            school_name: school_name(self.school),
+            level: self.level(),
+            icon_url,
+            components: self
+                .formula
+                .iter()
+                .map(|id| SpellComponentRef {
+                    id: *id,
+                    component: components.components.get(id).cloned(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Filters for /spells
+#[derive(Debug, Default, PartialEq)]
+pub struct SpellFilter {
+    /// Case-insensitive substring of the name
+    pub name: Option<String>,
+    pub school: Option<u32>,
+    pub level: Option<u32>,
+}
+
+impl SpellFilter {
+    /// Read `name`, `school` and `level` query parameters. Schools can be
+    /// given by number or by the first word of their name, e.g. `war`.
+    pub fn from_params(params: &HashMap<String, String>) -> Result<SpellFilter, String> {
+        let school = match params.get("school") {
+            None => None,
+            Some(value) => match value.parse::<u32>() {
+                Ok(val) => Some(val),
+                Err(_) => {
+                    let value = value.to_ascii_lowercase();
+                    let found = SCHOOLS.iter().find(|(_, name)| {
+                        let name = name.to_ascii_lowercase();
+                        name == value || name.split(' ').next() == Some(value.as_str())
+                    });
+                    match found {
+                        Some((id, _)) => Some(*id),
+                        None => {
+                            let names: Vec<&str> = SCHOOLS.iter().map(|(_, name)| *name).collect();
+                            return Err(format!(
+                                "Unknown school: {}. Use a number or one of {}.",
+                                value,
+                                names.join(", ")
+                            ));
+                        }
+                    }
+                }
+            },
+        };
+
+        let level = match params.get("level").map(|value| value.parse::<u32>()) {
+            None => None,
+            Some(Ok(val)) => Some(val),
+            Some(Err(err)) => {
+                return Err(format!(
+                    "Failed to parse query parameter: level. Error: {}",
+                    err
+                ))
+            }
+        };
+
+        Ok(SpellFilter {
+            name: params.get("name").map(|value| value.to_lowercase()),
+            school,
+            level,
+        })
+    }
+
+    pub fn matches(&self, spell: &Spell) -> bool {
+        self.name
+            .as_ref()
+            .is_none_or(|name| spell.name.to_lowercase().contains(name))
+            && self.school.is_none_or(|school| spell.school == school)
+            && self.level.is_none_or(|level| spell.level() == Some(level))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strength_other() -> Spell {
+        Spell {
+            id: 2,
+            name: "Strength Other I".to_string(),
+            description: "Increases the target's Strength.".to_string(),
+            school: 4,
+            icon: 0x06001234,
+            category: 1,
+            bitfield: 0,
+            base_mana: 10,
+            base_range_constant: 5.0,
+            base_range_mod: 1.0,
+            power: 1,
+            spell_economy_mod: 1.0,
+            formula_version: 1,
+            component_loss: 0.5,
+            meta_spell_type: 1,
+            meta_spell_id: 2,
+            duration: Some(1800.0),
+            degrade_modifier: Some(0.0),
+            degrade_limit: Some(-666.0),
+            portal_lifetime: None,
+            formula: vec![1, 63, 45],
+            caster_effect: 6,
+            target_effect: 6,
+            fizzle_effect: 0,
+            recovery_interval: 0.0,
+            recovery_amount: 0.0,
+            display_order: 1,
+            non_component_target_type: 0,
+            mana_mod: 0,
+        }
+    }
+
+    #[test]
+    fn test_decrypt_formula() {
+        let (name, description) = (b"Strength Other I", b"Increases the target's Strength.");
+        let key = formula_key(name, description);
+        let formula: Vec<u32> = [1, 63, 45, 0, 0, 0, 0, 0]
+            .iter()
+            .map(|component: &u32| component.wrapping_add(key))
+            .collect();
+
+        assert_eq!(
+            decrypt_formula(&formula, name, description),
+            vec![1, 63, 45]
+        );
+    }
+
+    #[test]
+    fn test_windows_1252_bytes() {
+        let bytes = [b'C', 0xE9, b'!'];
+        assert_eq!(windows_1252_bytes(&windows_1252(&bytes)), bytes);
+    }
+
+    #[test]
+    fn test_level() {
+        let mut spell = strength_other();
+        assert_eq!(spell.level(), Some(1));
+
+        spell.formula = vec![63, 193];
+        assert_eq!(spell.level(), Some(8));
+
+        spell.formula = vec![63, 45];
+        assert_eq!(spell.level(), None);
+    }
+
+    #[test]
+    fn test_string_hash() {
+        assert_eq!(string_hash(b""), 0);
+        assert_eq!(string_hash(b"a"), 0x61);
+        assert_eq!(string_hash(b"ab"), 0x61 * 16 + 0x62);
+        // Long strings fold the high nibble back in
+        assert!(string_hash(&[b'z'; 64]) <= 0x0FFF_FFFF);
+    }
+
+    #[test]
+    fn test_spell_filter() {
+        let spell = &strength_other();
+
+        let params = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+
+        let filter = SpellFilter::from_params(&params(&[])).unwrap();
+        assert!(filter.matches(spell));
+
+        let filter = SpellFilter::from_params(&params(&[("name", "strength")])).unwrap();
+        assert!(filter.matches(spell));
+
+        let filter =
+            SpellFilter::from_params(&params(&[("school", "Creature"), ("level", "1")])).unwrap();
+        assert_eq!(filter.school, Some(4));
+        assert!(filter.matches(spell));
+
+        let filter = SpellFilter::from_params(&params(&[("school", "war")])).unwrap();
+        assert!(!filter.matches(spell));
+
+        let filter = SpellFilter::from_params(&params(&[("level", "2")])).unwrap();
+        assert!(!filter.matches(spell));
+
+        assert!(SpellFilter::from_params(&params(&[("school", "nope")])).is_err());
+        assert!(SpellFilter::from_params(&params(&[("level", "x")])).is_err());
+    }
+
+    #[test]
+    fn test_spell_detail() {
+        let components = SpellComponentTable {
+            id: SPELL_COMPONENT_TABLE_ID,
+            components: BTreeMap::from([(
+                1,
+                SpellComponent {
+                    name: "Lead Scarab".to_string(),
+                    category: 0,
+                    icon: 0x06000F6E,
+                    component_type: 1,
+                    gesture: 0,
+                    time: 0.0,
+                    text: String::new(),
+                    cdm: 0.0,
+                },
+            )]),
+        };
+
+        let detail = strength_other().detail(&components, "/icons/0x06001234".to_string());
+        assert_eq!(detail.school_name, Some("Creature Enchantment"));
+        assert_eq!(detail.components.len(), 3);
+        assert_eq!(
+            detail.components[0]
+                .component
+                .as_ref()
+                .map(|component| component.name.as_str()),
+            Some("Lead Scarab")
+        );
+        assert_eq!(detail.components[1].component, None);
+
+        let json = serde_json::to_value(&detail).unwrap();
+        assert_eq!(json["name"], "Strength Other I");
+        assert_eq!(json["components"][0]["name"], "Lead Scarab");
+        assert_eq!(json["components"][1]["id"], 63);
+        assert_eq!(json["formula"][1], 63);
+    }
+}
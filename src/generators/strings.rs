@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use super::reader::Reader;
+
+/// One named string in a StringTable. Variables are substituted into the
+/// strings by the client, e.g. `%s` in a ban message.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StringTableEntry {
+    pub id: u32,
+    pub var_names: Vec<String>,
+    pub vars: Vec<String>,
+    pub strings: Vec<String>,
+    pub comments: Vec<u32>,
+}
+
+/// A StringTable (0x23) file. Entry IDs are hashes of the string names.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StringTable {
+    pub id: u32,
+    pub language: u32,
+    pub entries: Vec<StringTableEntry>,
+}
+
+/// An EnumMapper (0x22) file: names for the values of one client enum
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EnumMapper {
+    pub id: u32,
+    pub base_enum_map: u32,
+    pub names: BTreeMap<u32, String>,
+}
+
+/// A DualDidMapper (0x25) file: the IDs and names that client and server enum
+/// values map to
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DualDidMapper {
+    pub id: u32,
+    pub client_enum_to_id: BTreeMap<u32, u32>,
+    pub client_enum_to_name: BTreeMap<u32, String>,
+    pub server_enum_to_id: BTreeMap<u32, u32>,
+    pub server_enum_to_name: BTreeMap<u32, String>,
+}
+
+/// The strings one ID maps to in a StringTable, EnumMapper or DualDidMapper
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StringLookup {
+    pub table_id: u32,
+    pub string_id: u32,
+    pub strings: Vec<String>,
+}
+
+fn string_table_entry(reader: &mut Reader) -> Result<StringTableEntry, String> {
+    let id = reader.u32()?;
+    let var_names = (0..reader.u16()?)
+        .map(|_| reader.unicode_string())
+        .collect::<Result<_, _>>()?;
+    let vars = (0..reader.u16()?)
+        .map(|_| reader.unicode_string())
+        .collect::<Result<_, _>>()?;
+    let strings = (0..reader.u32()?)
+        .map(|_| reader.unicode_string())
+        .collect::<Result<_, _>>()?;
+    let comments = (0..reader.u32()?)
+        .map(|_| reader.u32())
+        .collect::<Result<_, _>>()?;
+    let _unknown = reader.u8()?;
+
+    Ok(StringTableEntry {
+        id,
+        var_names,
+        vars,
+        strings,
+        comments,
+    })
+}
+
+/// Parse a StringTable (0x23) file.
+pub fn parse_string_table(buf: &[u8]) -> Result<StringTable, String> {
+    let mut reader = Reader::new(buf, "string table");
+
+    let id = reader.u32()?;
+    let language = reader.u32()?;
+    let _unknown = reader.u8()?;
+    let count = reader.compressed_u32()?;
+    let entries = (0..count)
+        .map(|_| string_table_entry(&mut reader))
+        .collect::<Result<_, _>>()?;
+
+    Ok(StringTable {
+        id,
+        language,
+        entries,
+    })
+}
+
+/// Parse an EnumMapper (0x22) file.
+pub fn parse_enum_mapper(buf: &[u8]) -> Result<EnumMapper, String> {
+    let mut reader = Reader::new(buf, "enum mapper");
+
+    Ok(EnumMapper {
+        id: reader.u32()?,
+        base_enum_map: reader.u32()?,
+        names: reader.smart_map(Reader::compressed_pstring)?,
+    })
+}
+
+/// Parse a DualDidMapper (0x25) file.
+pub fn parse_dual_did_mapper(buf: &[u8]) -> Result<DualDidMapper, String> {
+    let mut reader = Reader::new(buf, "dual DID mapper");
+
+    Ok(DualDidMapper {
+        id: reader.u32()?,
+        client_enum_to_id: reader.smart_map(Reader::u32)?,
+        client_enum_to_name: reader.smart_map(Reader::compressed_pstring)?,
+        server_enum_to_id: reader.smart_map(Reader::u32)?,
+        server_enum_to_name: reader.smart_map(Reader::compressed_pstring)?,
+    })
+}
+
+impl StringTable {
+    pub fn lookup(&self, string_id: u32) -> Option<StringLookup> {
+        self.entries
+            .iter()
+            .find(|entry| entry.id == string_id)
+            .map(|entry| StringLookup {
+                table_id: self.id,
+                string_id,
+                strings: entry.strings.clone(),
+            })
+    }
+}
+
+impl EnumMapper {
+    pub fn lookup(&self, string_id: u32) -> Option<StringLookup> {
+        self.names.get(&string_id).map(|name| StringLookup {
+            table_id: self.id,
+            string_id,
+            strings: vec![name.clone()],
+        })
+    }
+}
+
+impl DualDidMapper {
+    /// Names are looked up by client enum value
+    pub fn lookup(&self, string_id: u32) -> Option<StringLookup> {
+        self.client_enum_to_name
+            .get(&string_id)
+            .map(|name| StringLookup {
+                table_id: self.id,
+                string_id,
+                strings: vec![name.clone()],
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_unicode(buf: &mut Vec<u8>, text: &str) {
+        let units: Vec<u16> = text.encode_utf16().collect();
+        buf.push(units.len() as u8);
+        for unit in units {
+            buf.extend_from_slice(&unit.to_le_bytes());
+        }
+    }
+
+    fn put_pstring(buf: &mut Vec<u8>, text: &str) {
+        buf.push(text.len() as u8);
+        buf.extend_from_slice(text.as_bytes());
+    }
+
+    #[test]
+    fn test_parse_string_table() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&0x23000001u32.to_le_bytes());
+        buf.extend_from_slice(&1u32.to_le_bytes());
+        buf.push(0);
+        buf.push(1);
+
+        buf.extend_from_slice(&0x0ABCDEF0u32.to_le_bytes());
+        buf.extend_from_slice(&1u16.to_le_bytes());
+        put_unicode(&mut buf, "name");
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        buf.extend_from_slice(&1u32.to_le_bytes());
+        put_unicode(&mut buf, "Hëllo");
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        buf.push(0);
+
+        let table = parse_string_table(&buf).unwrap();
+        assert_eq!(table.id, 0x23000001);
+        assert_eq!(table.language, 1);
+        assert_eq!(table.entries[0].var_names, vec!["name".to_string()]);
+
+        let lookup = table.lookup(0x0ABCDEF0).unwrap();
+        assert_eq!(lookup.strings, vec!["Hëllo".to_string()]);
+        assert!(table.lookup(1).is_none());
+
+        assert!(parse_string_table(&buf[..buf.len() - 3]).is_err());
+    }
+
+    #[test]
+    fn test_parse_enum_mapper() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&0x22000005u32.to_le_bytes());
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        buf.push(2);
+        buf.extend_from_slice(&6u32.to_le_bytes());
+        put_pstring(&mut buf, "MeleeDefense");
+        buf.extend_from_slice(&7u32.to_le_bytes());
+        put_pstring(&mut buf, "MissileDefense");
+
+        let mapper = parse_enum_mapper(&buf).unwrap();
+        assert_eq!(mapper.names.len(), 2);
+        assert_eq!(
+            mapper.lookup(7).unwrap().strings,
+            vec!["MissileDefense".to_string()]
+        );
+        assert!(mapper.lookup(8).is_none());
+
+        assert_eq!(
+            parse_enum_mapper(&buf[..6]).unwrap_err(),
+            "Failed to read enum mapper: failed to fill whole buffer"
+        );
+    }
+
+    #[test]
+    fn test_parse_dual_did_mapper() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&0x25000001u32.to_le_bytes());
+        buf.push(1);
+        buf.extend_from_slice(&1u32.to_le_bytes());
+        buf.extend_from_slice(&0x06001000u32.to_le_bytes());
+        buf.push(1);
+        buf.extend_from_slice(&1u32.to_le_bytes());
+        put_pstring(&mut buf, "Strength");
+        buf.push(0);
+        buf.push(0);
+
+        let mapper = parse_dual_did_mapper(&buf).unwrap();
+        assert_eq!(mapper.client_enum_to_id[&1], 0x06001000);
+        assert_eq!(
+            mapper.lookup(1).unwrap().strings,
+            vec!["Strength".to_string()]
+        );
+        assert!(mapper.server_enum_to_name.is_empty());
+
+        assert!(parse_dual_did_mapper(&buf[..6])
+            .unwrap_err()
+            .starts_with("Failed to read dual DID mapper: "));
+    }
+}
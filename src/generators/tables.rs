@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use super::reader::Reader;
+
+/// Singleton tables live at fixed IDs in this range of the portal DAT
+pub const FIRST_TABLE_ID: u32 = 0x0E000000;
+pub const LAST_TABLE_ID: u32 = 0x0E00FFFF;
+
+pub const VITAL_TABLE_ID: u32 = 0x0E000003;
+pub const SKILL_TABLE_ID: u32 = 0x0E000004;
+pub const EXPERIENCE_TABLE_ID: u32 = 0x0E000018;
+
+/// XP costs by rank, starting from rank 0
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExperienceTable {
+    pub id: u32,
+    pub attributes: Vec<u32>,
+    pub vitals: Vec<u32>,
+    pub trained_skills: Vec<u32>,
+    pub specialized_skills: Vec<u32>,
+    /// Total XP to reach each character level
+    pub levels: Vec<u64>,
+    /// Skill credits awarded at each character level
+    pub skill_credits: Vec<u32>,
+}
+
+/// `(x * attr1 + y * attr2) / z + w`, with attributes by ID and attr2 unused
+/// when it is 0
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SkillFormula {
+    pub w: u32,
+    pub x: u32,
+    pub y: u32,
+    pub z: u32,
+    pub attr1: u32,
+    pub attr2: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Skill {
+    pub name: String,
+    pub description: String,
+    pub icon: u32,
+    pub trained_cost: i32,
+    pub specialized_cost: i32,
+    pub category: u32,
+    pub chargen_use: u32,
+    pub min_level: u32,
+    pub formula: SkillFormula,
+    pub upper_bound: f64,
+    pub lower_bound: f64,
+    pub learn_mod: f64,
+}
+
+/// The SkillTable (0x0E000004), keyed by skill ID
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SkillTable {
+    pub id: u32,
+    pub skills: BTreeMap<u32, Skill>,
+}
+
+/// The vital formulas (0x0E000003), known to the client as the secondary
+/// attribute table
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VitalTable {
+    pub id: u32,
+    pub max_health: SkillFormula,
+    pub max_stamina: SkillFormula,
+    pub max_mana: SkillFormula,
+}
+
+/// `count + 1` values, the file storing the highest rank rather than the
+/// number of ranks
+fn ranks<'a, T>(
+    reader: &mut Reader<'a>,
+    count: u32,
+    read: fn(&mut Reader<'a>) -> Result<T, String>,
+) -> Result<Vec<T>, String> {
+    (0..=count).map(|_| read(reader)).collect()
+}
+
+fn skill_formula(reader: &mut Reader) -> Result<SkillFormula, String> {
+    Ok(SkillFormula {
+        w: reader.u32()?,
+        x: reader.u32()?,
+        y: reader.u32()?,
+        z: reader.u32()?,
+        attr1: reader.u32()?,
+        attr2: reader.u32()?,
+    })
+}
+
+fn skill(reader: &mut Reader) -> Result<Skill, String> {
+    Ok(Skill {
+        description: reader.pstring()?,
+        name: reader.pstring()?,
+        icon: reader.u32()?,
+        trained_cost: reader.i32()?,
+        specialized_cost: reader.i32()?,
+        category: reader.u32()?,
+        chargen_use: reader.u32()?,
+        min_level: reader.u32()?,
+        formula: skill_formula(reader)?,
+        upper_bound: reader.f64()?,
+        lower_bound: reader.f64()?,
+        learn_mod: reader.f64()?,
+    })
+}
+
+/// Parse the ExperienceTable (0x0E000018).
+pub fn parse_experience_table(buf: &[u8]) -> Result<ExperienceTable, String> {
+    let mut reader = Reader::new(buf, "table");
+
+    let id = reader.u32()?;
+    let attribute_count = reader.u32()?;
+    let vital_count = reader.u32()?;
+    let trained_skill_count = reader.u32()?;
+    let specialized_skill_count = reader.u32()?;
+    let level_count = reader.u32()?;
+
+    Ok(ExperienceTable {
+        id,
+        attributes: ranks(&mut reader, attribute_count, Reader::u32)?,
+        vitals: ranks(&mut reader, vital_count, Reader::u32)?,
+        trained_skills: ranks(&mut reader, trained_skill_count, Reader::u32)?,
+        specialized_skills: ranks(&mut reader, specialized_skill_count, Reader::u32)?,
+        levels: ranks(&mut reader, level_count, Reader::u64)?,
+        skill_credits: ranks(&mut reader, level_count, Reader::u32)?,
+    })
+}
+
+/// Parse the SkillTable (0x0E000004).
+pub fn parse_skill_table(buf: &[u8]) -> Result<SkillTable, String> {
+    let mut reader = Reader::new(buf, "table");
+
+    Ok(SkillTable {
+        id: reader.u32()?,
+        skills: reader.hash_table(skill)?,
+    })
+}
+
+/// Parse the VitalTable (0x0E000003).
+pub fn parse_vital_table(buf: &[u8]) -> Result<VitalTable, String> {
+    let mut reader = Reader::new(buf, "table");
+
+    Ok(VitalTable {
+        id: reader.u32()?,
+        max_health: skill_formula(&mut reader)?,
+        max_stamina: skill_formula(&mut reader)?,
+        max_mana: skill_formula(&mut reader)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(buf: &mut Vec<u8>, value: u32) {
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_pstring(buf: &mut Vec<u8>, text: &str) {
+        buf.extend_from_slice(&(text.len() as u16).to_le_bytes());
+        buf.extend_from_slice(text.as_bytes());
+        while !buf.len().is_multiple_of(4) {
+            buf.push(0);
+        }
+    }
+
+    #[test]
+    fn test_parse_experience_table() {
+        let mut buf = Vec::new();
+        put(&mut buf, EXPERIENCE_TABLE_ID);
+        for count in [1, 1, 2, 1, 2] {
+            put(&mut buf, count);
+        }
+        for value in [0, 110, 0, 88, 0, 50, 100, 0, 40] {
+            put(&mut buf, value);
+        }
+        for value in [0u64, 1000, 6_000_000_000] {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [0, 0, 1] {
+            put(&mut buf, value);
+        }
+
+        let table = parse_experience_table(&buf).unwrap();
+        assert_eq!(table.attributes, vec![0, 110]);
+        assert_eq!(table.vitals, vec![0, 88]);
+        assert_eq!(table.trained_skills, vec![0, 50, 100]);
+        assert_eq!(table.specialized_skills, vec![0, 40]);
+        assert_eq!(table.levels, vec![0, 1000, 6_000_000_000]);
+        assert_eq!(table.skill_credits, vec![0, 0, 1]);
+
+        assert!(parse_experience_table(&buf[..buf.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_parse_skill_table() {
+        let mut buf = Vec::new();
+        put(&mut buf, SKILL_TABLE_ID);
+        put(&mut buf, 1 | (32 << 16));
+        put(&mut buf, 6);
+        put_pstring(&mut buf, "Helps you avoid melee attacks.");
+        put_pstring(&mut buf, "Melee Defense");
+        put(&mut buf, 0x06000001);
+        buf.extend_from_slice(&10i32.to_le_bytes());
+        buf.extend_from_slice(&10i32.to_le_bytes());
+        for value in [2, 1, 1, 0, 1, 1, 3, 2, 4] {
+            put(&mut buf, value);
+        }
+        for value in [1.0f64, 0.0, 1.0] {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let table = parse_skill_table(&buf).unwrap();
+        let skill = &table.skills[&6];
+        assert_eq!(skill.name, "Melee Defense");
+        assert_eq!(skill.description, "Helps you avoid melee attacks.");
+        assert_eq!(skill.icon, 0x06000001);
+        assert_eq!(skill.trained_cost, 10);
+        assert_eq!(
+            skill.formula,
+            SkillFormula {
+                w: 0,
+                x: 1,
+                y: 1,
+                z: 3,
+                attr1: 2,
+                attr2: 4,
+            }
+        );
+        assert_eq!(skill.learn_mod, 1.0);
+    }
+
+    #[test]
+    fn test_parse_vital_table() {
+        let mut buf = Vec::new();
+        put(&mut buf, VITAL_TABLE_ID);
+        for _ in 0..3 {
+            for value in [0, 1, 0, 2, 1, 0] {
+                put(&mut buf, value);
+            }
+        }
+
+        let table = parse_vital_table(&buf).unwrap();
+        assert_eq!(table.max_mana.z, 2);
+        assert_eq!(table.max_health.attr1, 1);
+        assert!(parse_vital_table(&buf[..8]).is_err());
+    }
+}
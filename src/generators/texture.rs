@@ -0,0 +1,375 @@
+use std::io::Cursor;
+
+use acprotocol::dat::file_types::texture::Texture;
+use byteorder::{LittleEndian, ReadBytesExt};
+use image::{imageops, ImageFormat, Rgba, RgbaImage};
+
+// D3D pixel formats as stored in Texture::format
+pub const PIXEL_FORMAT_R8G8B8: u32 = 20;
+pub const PIXEL_FORMAT_A8R8G8B8: u32 = 21;
+pub const PIXEL_FORMAT_X8R8G8B8: u32 = 22;
+pub const PIXEL_FORMAT_R5G6B5: u32 = 23;
+pub const PIXEL_FORMAT_A4R4G4B4: u32 = 26;
+pub const PIXEL_FORMAT_A8: u32 = 28;
+pub const PIXEL_FORMAT_P8: u32 = 41;
+pub const PIXEL_FORMAT_INDEX16: u32 = 101;
+pub const PIXEL_FORMAT_CUSTOM_LSCAPE_R8G8B8: u32 = 243;
+pub const PIXEL_FORMAT_CUSTOM_LSCAPE_ALPHA: u32 = 244;
+pub const PIXEL_FORMAT_CUSTOM_RAW_JPEG: u32 = 500;
+pub const PIXEL_FORMAT_DXT1: u32 = 0x31545844;
+pub const PIXEL_FORMAT_DXT3: u32 = 0x33545844;
+pub const PIXEL_FORMAT_DXT5: u32 = 0x35545844;
+
+/// Widest or tallest a scaled texture can be, in pixels
+pub const MAX_SCALED_SIZE: u32 = 4096;
+
+/// Whether pixels in this format are indices into a palette.
+pub fn is_paletted(format: u32) -> bool {
+    matches!(format, PIXEL_FORMAT_P8 | PIXEL_FORMAT_INDEX16)
+}
+
+/// Parse a Palette (0x04) file into its ARGB colors.
+pub fn parse_palette(buf: &[u8]) -> Result<Vec<u32>, String> {
+    let mut reader = Cursor::new(buf);
+    // Skip the file ID
+    reader.set_position(4);
+
+    let read_error = |err: std::io::Error| format!("Failed to read palette: {}", err);
+    let count = reader.read_u32::<LittleEndian>().map_err(read_error)?;
+    (0..count)
+        .map(|_| reader.read_u32::<LittleEndian>().map_err(read_error))
+        .collect()
+}
+
+/// Palette colors are stored as ARGB.
+pub fn argb_to_rgba(argb: u32) -> Rgba<u8> {
+    let [a, r, g, b] = argb.to_be_bytes();
+    Rgba([r, g, b, a])
+}
+
+fn rgb565_to_rgb(value: u16) -> [u8; 3] {
+    let r = ((value >> 11) & 0x1F) as u8;
+    let g = ((value >> 5) & 0x3F) as u8;
+    let b = (value & 0x1F) as u8;
+    [
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+    ]
+}
+
+/// Decode one 4x4 DXT color block into 16 RGBA pixels. DXT1 blocks may use
+/// the 3-color mode with a transparent fourth color.
+fn decode_color_block(block: &[u8], allow_transparent: bool) -> [Rgba<u8>; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let [r0, g0, b0] = rgb565_to_rgb(c0);
+    let [r1, g1, b1] = rgb565_to_rgb(c1);
+    let mix = |a: u8, b: u8, wa: u16, wb: u16| ((a as u16 * wa + b as u16 * wb) / (wa + wb)) as u8;
+
+    let colors = if c0 > c1 || !allow_transparent {
+        [
+            Rgba([r0, g0, b0, 255]),
+            Rgba([r1, g1, b1, 255]),
+            Rgba([mix(r0, r1, 2, 1), mix(g0, g1, 2, 1), mix(b0, b1, 2, 1), 255]),
+            Rgba([mix(r0, r1, 1, 2), mix(g0, g1, 1, 2), mix(b0, b1, 1, 2), 255]),
+        ]
+    } else {
+        [
+            Rgba([r0, g0, b0, 255]),
+            Rgba([r1, g1, b1, 255]),
+            Rgba([mix(r0, r1, 1, 1), mix(g0, g1, 1, 1), mix(b0, b1, 1, 1), 255]),
+            Rgba([0, 0, 0, 0]),
+        ]
+    };
+
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    std::array::from_fn(|i| colors[((indices >> (i * 2)) & 0x3) as usize])
+}
+
+/// Decode the 16 alpha values of a DXT5 alpha block.
+fn decode_interpolated_alpha(block: &[u8]) -> [u8; 16] {
+    let (a0, a1) = (block[0] as u16, block[1] as u16);
+    let alphas: [u8; 8] = if a0 > a1 {
+        std::array::from_fn(|i| match i {
+            0 => a0 as u8,
+            1 => a1 as u8,
+            _ => (((8 - i as u16) * a0 + (i as u16 - 1) * a1) / 7) as u8,
+        })
+    } else {
+        std::array::from_fn(|i| match i {
+            0 => a0 as u8,
+            1 => a1 as u8,
+            6 => 0,
+            7 => 255,
+            _ => (((6 - i as u16) * a0 + (i as u16 - 1) * a1) / 5) as u8,
+        })
+    };
+
+    let mut bits = 0u64;
+    for (i, byte) in block[2..8].iter().enumerate() {
+        bits |= (*byte as u64) << (8 * i);
+    }
+    std::array::from_fn(|i| alphas[((bits >> (i * 3)) & 0x7) as usize])
+}
+
+fn decode_dxt(format: u32, width: u32, height: u32, data: &[u8]) -> Result<RgbaImage, String> {
+    let block_size = if format == PIXEL_FORMAT_DXT1 { 8 } else { 16 };
+    let blocks_wide = width.div_ceil(4);
+    let blocks_high = height.div_ceil(4);
+    let needed = (blocks_wide * blocks_high) as usize * block_size;
+    if data.len() < needed {
+        return Err(format!(
+            "Texture data is {} bytes but {} are needed",
+            data.len(),
+            needed
+        ));
+    }
+
+    let mut image = RgbaImage::new(width, height);
+    for (index, block) in data[..needed].chunks_exact(block_size).enumerate() {
+        let pixels = match format {
+            PIXEL_FORMAT_DXT1 => decode_color_block(block, true),
+            PIXEL_FORMAT_DXT3 => {
+                let mut pixels = decode_color_block(&block[8..], false);
+                for (i, pixel) in pixels.iter_mut().enumerate() {
+                    let nibble = (block[i / 2] >> ((i % 2) * 4)) & 0xF;
+                    pixel.0[3] = nibble * 17;
+                }
+                pixels
+            }
+            _ => {
+                let mut pixels = decode_color_block(&block[8..], false);
+                let alphas = decode_interpolated_alpha(&block[..8]);
+                for (pixel, alpha) in pixels.iter_mut().zip(alphas) {
+                    pixel.0[3] = alpha;
+                }
+                pixels
+            }
+        };
+
+        let block_x = (index as u32 % blocks_wide) * 4;
+        let block_y = (index as u32 / blocks_wide) * 4;
+        for (i, pixel) in pixels.into_iter().enumerate() {
+            let (x, y) = (block_x + i as u32 % 4, block_y + i as u32 / 4);
+            if x < width && y < height {
+                image.put_pixel(x, y, pixel);
+            }
+        }
+    }
+
+    Ok(image)
+}
+
+/// Decode raw texture data in any supported pixel format to RGBA. Paletted
+/// formats need the palette's ARGB colors.
+pub fn decode(
+    format: u32,
+    width: u32,
+    height: u32,
+    data: &[u8],
+    palette: Option<&[u32]>,
+) -> Result<RgbaImage, String> {
+    let pixel_count = (width * height) as usize;
+    let bytes_per_pixel = match format {
+        PIXEL_FORMAT_DXT1 | PIXEL_FORMAT_DXT3 | PIXEL_FORMAT_DXT5 => {
+            return decode_dxt(format, width, height, data)
+        }
+        PIXEL_FORMAT_CUSTOM_RAW_JPEG => {
+            return image::load_from_memory_with_format(data, ImageFormat::Jpeg)
+                .map(|image| image.to_rgba8())
+                .map_err(|err| format!("Failed to decode JPEG texture: {}", err))
+        }
+        PIXEL_FORMAT_A8 | PIXEL_FORMAT_CUSTOM_LSCAPE_ALPHA | PIXEL_FORMAT_P8 => 1,
+        PIXEL_FORMAT_R5G6B5 | PIXEL_FORMAT_A4R4G4B4 | PIXEL_FORMAT_INDEX16 => 2,
+        PIXEL_FORMAT_R8G8B8 | PIXEL_FORMAT_CUSTOM_LSCAPE_R8G8B8 => 3,
+        PIXEL_FORMAT_A8R8G8B8 | PIXEL_FORMAT_X8R8G8B8 => 4,
+        _ => return Err(format!("Unsupported pixel format {}", format)),
+    };
+
+    if data.len() < pixel_count * bytes_per_pixel {
+        return Err(format!(
+            "Texture data is {} bytes but {} are needed",
+            data.len(),
+            pixel_count * bytes_per_pixel
+        ));
+    }
+
+    let palette = match (is_paletted(format), palette) {
+        (true, Some(palette)) => palette,
+        (true, None) => return Err("Paletted texture requires a palette".to_string()),
+        (false, _) => &[],
+    };
+    let palette_color = |index: usize| -> Result<Rgba<u8>, String> {
+        palette
+            .get(index)
+            .map(|argb| argb_to_rgba(*argb))
+            .ok_or_else(|| format!("Palette index {} is out of range", index))
+    };
+
+    let mut pixels = Vec::with_capacity(pixel_count);
+    for px in data[..pixel_count * bytes_per_pixel].chunks_exact(bytes_per_pixel) {
+        let pixel = match format {
+            PIXEL_FORMAT_A8 | PIXEL_FORMAT_CUSTOM_LSCAPE_ALPHA => Rgba([px[0], px[0], px[0], 255]),
+            PIXEL_FORMAT_P8 => palette_color(px[0] as usize)?,
+            PIXEL_FORMAT_INDEX16 => palette_color(u16::from_le_bytes([px[0], px[1]]) as usize)?,
+            PIXEL_FORMAT_R5G6B5 => {
+                let [r, g, b] = rgb565_to_rgb(u16::from_le_bytes([px[0], px[1]]));
+                Rgba([r, g, b, 255])
+            }
+            PIXEL_FORMAT_A4R4G4B4 => {
+                let value = u16::from_le_bytes([px[0], px[1]]);
+                let channel = |shift: u16| ((value >> shift) & 0xF) as u8 * 17;
+                Rgba([channel(8), channel(4), channel(0), channel(12)])
+            }
+            PIXEL_FORMAT_R8G8B8 | PIXEL_FORMAT_CUSTOM_LSCAPE_R8G8B8 => {
+                Rgba([px[2], px[1], px[0], 255])
+            }
+            PIXEL_FORMAT_X8R8G8B8 => Rgba([px[2], px[1], px[0], 255]),
+            _ => Rgba([px[2], px[1], px[0], px[3]]),
+        };
+        pixels.push(pixel);
+    }
+
+    Ok(RgbaImage::from_fn(width, height, |x, y| {
+        pixels[(y * width + x) as usize]
+    }))
+}
+
+/// Decode a Texture file to RGBA.
+pub fn decode_texture(texture: &Texture, palette: Option<&[u32]>) -> Result<RgbaImage, String> {
+    decode(
+        texture.format as u32,
+        texture.width as u32,
+        texture.height as u32,
+        &texture.source_data,
+        palette,
+    )
+}
+
+/// Encode an image as A8R8G8B8 pixel data.
+pub fn encode_argb(image: &RgbaImage) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(image.as_raw().len());
+    for pixel in image.pixels() {
+        let [r, g, b, a] = pixel.0;
+        buf.extend_from_slice(&[b, g, r, a]);
+    }
+
+    buf
+}
+
+/// Replace a texture's pixels with an image stored as A8R8G8B8, for handing
+/// recolored textures to code that only reads Textures.
+pub fn with_argb_pixels(texture: Texture, image: &RgbaImage) -> Texture {
+    Texture {
+        width: image.width() as _,
+        height: image.height() as _,
+        format: PIXEL_FORMAT_A8R8G8B8 as _,
+        source_data: encode_argb(image),
+        ..texture
+    }
+}
+
+/// Scale an image up by an integer factor without smoothing.
+pub fn scale(image: &RgbaImage, factor: u32) -> RgbaImage {
+    if factor == 1 {
+        return image.clone();
+    }
+
+    imageops::resize(
+        image,
+        image.width() * factor,
+        image.height() * factor,
+        imageops::FilterType::Nearest,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_a8r8g8b8_is_stored_bgra() {
+        let image = decode(PIXEL_FORMAT_A8R8G8B8, 1, 1, &[0x10, 0x20, 0x30, 0x40], None).unwrap();
+        assert_eq!(image.get_pixel(0, 0).0, [0x30, 0x20, 0x10, 0x40]);
+    }
+
+    #[test]
+    fn test_decode_r8g8b8_is_opaque() {
+        let image = decode(PIXEL_FORMAT_R8G8B8, 2, 1, &[1, 2, 3, 4, 5, 6], None).unwrap();
+        assert_eq!(image.get_pixel(0, 0).0, [3, 2, 1, 255]);
+        assert_eq!(image.get_pixel(1, 0).0, [6, 5, 4, 255]);
+    }
+
+    #[test]
+    fn test_decode_paletted() {
+        let palette = [0xFF102030, 0x80405060];
+        let image = decode(PIXEL_FORMAT_INDEX16, 2, 1, &[1, 0, 0, 0], Some(&palette)).unwrap();
+        assert_eq!(image.get_pixel(0, 0).0, [0x40, 0x50, 0x60, 0x80]);
+        assert_eq!(image.get_pixel(1, 0).0, [0x10, 0x20, 0x30, 0xFF]);
+
+        let image = decode(PIXEL_FORMAT_P8, 1, 1, &[0], Some(&palette)).unwrap();
+        assert_eq!(image.get_pixel(0, 0).0, [0x10, 0x20, 0x30, 0xFF]);
+
+        assert!(decode(PIXEL_FORMAT_P8, 1, 1, &[0], None).is_err());
+        assert!(decode(PIXEL_FORMAT_P8, 1, 1, &[2], Some(&palette)).is_err());
+    }
+
+    #[test]
+    fn test_decode_dxt1() {
+        // Pure red and pure blue endpoints, every pixel using color 1 (blue)
+        // except the first which uses color 0 (red)
+        let block = [0x00, 0xF8, 0x1F, 0x00, 0x54, 0x55, 0x55, 0x55];
+        let image = decode(PIXEL_FORMAT_DXT1, 4, 4, &block, None).unwrap();
+        assert_eq!(image.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(3, 3).0, [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_decode_dxt5_alpha() {
+        let mut block = [0u8; 16];
+        // Alpha endpoints 255 and 0, every pixel using alpha 1 (0)
+        block[0] = 255;
+        block[2..8].copy_from_slice(&[0x49, 0x92, 0x24, 0x49, 0x92, 0x24]);
+        let image = decode(PIXEL_FORMAT_DXT5, 4, 4, &block, None).unwrap();
+        assert_eq!(image.get_pixel(0, 0).0[3], 0);
+        assert_eq!(image.get_pixel(3, 3).0[3], 0);
+    }
+
+    #[test]
+    fn test_decode_rejects_short_data() {
+        assert!(decode(PIXEL_FORMAT_A8R8G8B8, 2, 2, &[0; 4], None).is_err());
+        assert!(decode(PIXEL_FORMAT_DXT1, 8, 8, &[0; 8], None).is_err());
+        assert!(decode(12345, 1, 1, &[0; 4], None).is_err());
+    }
+
+    #[test]
+    fn test_parse_palette() {
+        let buf = [
+            0x01, 0x00, 0x00, 0x04, // ID
+            0x02, 0x00, 0x00, 0x00, // count
+            0x30, 0x20, 0x10, 0xFF, // 0xFF102030
+            0x60, 0x50, 0x40, 0x80, // 0x80405060
+        ];
+        assert_eq!(parse_palette(&buf).unwrap(), vec![0xFF102030, 0x80405060]);
+        assert!(parse_palette(&buf[..12]).is_err());
+    }
+
+    #[test]
+    fn test_encode_argb() {
+        let mut image = RgbaImage::new(2, 1);
+        image.put_pixel(1, 0, Rgba([0x30, 0x20, 0x10, 0x40]));
+        let buf = encode_argb(&image);
+
+        assert_eq!(buf, [0, 0, 0, 0, 0x10, 0x20, 0x30, 0x40]);
+        let decoded = decode(PIXEL_FORMAT_A8R8G8B8, 2, 1, &buf, None).unwrap();
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn test_scale() {
+        let image = RgbaImage::new(3, 2);
+        assert_eq!(scale(&image, 1).dimensions(), (3, 2));
+        assert_eq!(scale(&image, 4).dimensions(), (12, 8));
+    }
+}
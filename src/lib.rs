@@ -1,20 +1,31 @@
+use std::collections::HashMap;
 use std::error::Error;
 use std::io::Cursor;
 
-use acprotocol::dat::reader::{
-    dat_file_reader::DatFileReader, worker_r2_reader::WorkerR2RangeReader,
+use acprotocol::dat::{
+    reader::{dat_file_reader::DatFileReader, worker_r2_reader::WorkerR2RangeReader},
+    DatDatabaseType,
 };
-use counting_reader::CountingRangeReader;
 use byteorder::{BigEndian, ReadBytesExt};
-use routes::{files_get, files_index, icons_get, icons_index, index_get};
+use counting_reader::CountingRangeReader;
+use routes::{
+    clothing_get, diff_get, files_get, files_index, icons_atlas_get, icons_batch_post, icons_get,
+    icons_index, index_get, models_get, palettes_get, releases_index, search_get, sounds_get,
+    spells_get, spells_index, strings_get, tables_index, textures_get,
+};
 use worker::*;
 
 mod counting_reader;
-mod db;
-mod generators;
+mod data_format;
+pub mod db;
+mod exporters;
+mod file_query;
+pub mod generators;
 mod lib_test;
 mod openapi;
+mod projection;
 mod routes;
+mod search;
 
 fn with_cors_headers(mut response: Response) -> Response {
     let headers = response.headers_mut();
@@ -23,16 +34,22 @@ fn with_cors_headers(mut response: Response) -> Response {
         .set("Access-Control-Allow-Methods", "GET, POST, OPTIONS")
         .ok();
     headers
-        .set("Access-Control-Allow-Headers", "Content-Type")
+        .set(
+            "Access-Control-Allow-Headers",
+            "Content-Type, If-None-Match",
+        )
         .ok();
     headers
-        .set("Access-Control-Expose-Headers", "X-R2-Read-Count")
+        .set(
+            "Access-Control-Expose-Headers",
+            "X-R2-Read-Count, X-Cache, X-Render-Cache, ETag",
+        )
         .ok();
     response
 }
 
 #[event(fetch)]
-async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+async fn fetch(req: Request, env: Env, ctx: Context) -> Result<Response> {
     console_error_panic_hook::set_once();
 
     // Handle preflight OPTIONS requests
@@ -41,21 +58,46 @@ async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
         return Ok(with_cors_headers(response));
     }
 
-    let router = Router::new();
+    // Routes get the worker's Context for work that outlives the response
+    let router = Router::with_data(ctx);
 
-    let url_string = req.url()?;
-    let files_url = url_string.clone();
-    let icons_url = url_string.clone();
+    // Every DAT route is also available pinned to a release under /v/:release
     let response = router
         .get_async("/", |_, ctx| index_get(ctx))
-        .get_async("/files", |_, ctx| files_index(ctx))
-        .get_async("/files/:file_id", move |_, ctx| {
-            files_get(files_url.clone(), ctx)
-        })
-        .get_async("/icons", |_, ctx| icons_index(ctx))
-        .get_async("/icons/:id", move |_, ctx| {
-            icons_get(icons_url.clone(), ctx)
-        })
+        .get_async("/releases", |_, ctx| releases_index(ctx))
+        .get_async("/diff", diff_get)
+        .get_async("/search", search_get)
+        .get_async("/files", files_index)
+        .get_async("/files/:file_id", files_get)
+        .get_async("/icons", icons_index)
+        .get_async("/icons/atlas", icons_atlas_get)
+        .get_async("/icons/:id", icons_get)
+        .post_async("/icons/batch", icons_batch_post)
+        .get_async("/textures/:id", textures_get)
+        .get_async("/models/:id", models_get)
+        .get_async("/sounds/:id", sounds_get)
+        .get_async("/palettes/:id", palettes_get)
+        .get_async("/clothing/:id", clothing_get)
+        .get_async("/strings/:table_id/:string_id", strings_get)
+        .get_async("/tables", tables_index)
+        .get_async("/spells", spells_index)
+        .get_async("/spells/:id", spells_get)
+        .get_async("/v/:release/search", search_get)
+        .get_async("/v/:release/files", files_index)
+        .get_async("/v/:release/files/:file_id", files_get)
+        .get_async("/v/:release/icons", icons_index)
+        .get_async("/v/:release/icons/atlas", icons_atlas_get)
+        .get_async("/v/:release/icons/:id", icons_get)
+        .post_async("/v/:release/icons/batch", icons_batch_post)
+        .get_async("/v/:release/textures/:id", textures_get)
+        .get_async("/v/:release/models/:id", models_get)
+        .get_async("/v/:release/sounds/:id", sounds_get)
+        .get_async("/v/:release/palettes/:id", palettes_get)
+        .get_async("/v/:release/clothing/:id", clothing_get)
+        .get_async("/v/:release/strings/:table_id/:string_id", strings_get)
+        .get_async("/v/:release/tables", tables_index)
+        .get_async("/v/:release/spells", spells_index)
+        .get_async("/v/:release/spells/:id", spells_get)
         .run(req, env)
         .await?;
 
@@ -64,13 +106,20 @@ async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
 }
 
 pub async fn get_buf_for_file(
-    ctx: &RouteContext<()>,
+    ctx: &RouteContext<Context>,
+    release: &db::DatRelease,
     file: &db::File,
 ) -> std::result::Result<(Vec<u8>, usize), worker::Error> {
+    let dat = release.dat_for(file).ok_or_else(|| {
+        worker::Error::RustError(format!(
+            "Release {} has no DAT for database type {}",
+            release.release.name, file.database_type
+        ))
+    })?;
     let bucket = ctx.bucket("DATS_BUCKET")?;
-    let worker_reader = WorkerR2RangeReader::new(bucket, "client_portal.dat".to_string());
+    let worker_reader = WorkerR2RangeReader::new(bucket, dat.object_key.clone());
     let mut counting_reader = CountingRangeReader::new(worker_reader);
-    let mut reader = DatFileReader::new(file.file_size as usize, 1024_usize)
+    let mut reader = DatFileReader::new(file.file_size as usize, dat.block_size as usize)
         .map_err(|e| worker::Error::RustError(format!("Failed to create reader: {}", e)))?;
     let buf = reader
         .read_file(&mut counting_reader, file.file_offset as u32)
@@ -80,21 +129,172 @@ pub async fn get_buf_for_file(
     Ok((buf, counting_reader.count))
 }
 
-pub async fn get_file_by_id(ctx: &RouteContext<()>, file_id: i32) -> Result<Option<db::File>> {
+/// Read a previously rendered image from the R2 render cache, if present.
+pub async fn get_render(ctx: &RouteContext<Context>, key: &str) -> Result<Option<Vec<u8>>> {
+    let bucket = ctx.bucket("DATS_BUCKET")?;
+    let object = match bucket.get(key).execute().await? {
+        Some(val) => val,
+        None => return Ok(None),
+    };
+
+    match object.body() {
+        Some(body) => Ok(Some(body.bytes().await?)),
+        None => Ok(None),
+    }
+}
+
+/// Write a rendered image to the R2 render cache. Takes the bucket rather
+/// than the route context so it can run after the response is sent.
+pub async fn put_render(
+    bucket: &Bucket,
+    key: &str,
+    buf: Vec<u8>,
+    content_type: &str,
+) -> Result<()> {
+    bucket
+        .put(key, buf)
+        .http_metadata(HttpMetadata {
+            content_type: Some(content_type.to_string()),
+            ..Default::default()
+        })
+        .execute()
+        .await?;
+
+    Ok(())
+}
+
+/// Look up a file in one of the release's databases. IDs repeat across
+/// databases, so the database has to be given.
+pub async fn get_file_by_id(
+    ctx: &RouteContext<Context>,
+    release: &db::DatRelease,
+    database_type: &DatDatabaseType,
+    file_id: u32,
+) -> Result<Option<db::File>> {
     let db = ctx.d1("DATS_DB")?;
-    let statement = db.prepare("SELECT * FROM files WHERE id = ?1 LIMIT 1");
-    let query = statement.bind(&[file_id.into()])?;
+    let statement = db.prepare(
+        "SELECT * FROM files WHERE release_id = ?1 AND database_type = ?2 AND id = ?3 LIMIT 1",
+    );
+    // Cell IDs like 0xA9B4FFFF don't fit in an i32 so bind as f64 (a JS number)
+    let query = statement.bind(&[
+        (release.release.id as f64).into(),
+        (database_type.as_u32() as f64).into(),
+        (file_id as f64).into(),
+    ])?;
 
     query.first::<crate::db::File>(None).await
 }
 
+/// Look up many files in one of the release's databases at once. IDs that
+/// aren't in the release are left out of the returned map.
+pub async fn get_files_by_ids(
+    ctx: &RouteContext<Context>,
+    release: &db::DatRelease,
+    database_type: &DatDatabaseType,
+    file_ids: &[u32],
+) -> Result<HashMap<db::FileKey, db::File>> {
+    // D1 allows at most 100 bound parameters per query
+    const MAX_IDS_PER_QUERY: usize = 98;
+
+    let db = ctx.d1("DATS_DB")?;
+    let mut files = HashMap::new();
+
+    for chunk in file_ids.chunks(MAX_IDS_PER_QUERY) {
+        let placeholders: Vec<String> = (0..chunk.len()).map(|i| format!("?{}", i + 3)).collect();
+        let statement = db.prepare(format!(
+            "SELECT * FROM files WHERE release_id = ?1 AND database_type = ?2 AND id IN ({})",
+            placeholders.join(", ")
+        ));
+
+        let mut params = vec![
+            (release.release.id as f64).into(),
+            (database_type.as_u32() as f64).into(),
+        ];
+        params.extend(chunk.iter().map(|file_id| (*file_id as f64).into()));
+        let query = statement.bind(&params)?;
+
+        for file in query.all().await?.results::<db::File>()? {
+            files.insert(file.key(), file);
+        }
+    }
+
+    Ok(files)
+}
+
+/// Look up a release by name along with its DATs, or the most recently
+/// imported release when no name is given.
+pub async fn get_release(
+    ctx: &RouteContext<Context>,
+    name: Option<&str>,
+) -> Result<Option<db::DatRelease>> {
+    let db = ctx.d1("DATS_DB")?;
+    let query = match name {
+        Some(name) => db
+            .prepare("SELECT * FROM releases WHERE name = ?1 LIMIT 1")
+            .bind(&[name.into()])?,
+        None => db
+            .prepare("SELECT * FROM releases ORDER BY id DESC LIMIT 1")
+            .bind(&[])?,
+    };
+
+    let release = match query.first::<db::Release>(None).await? {
+        Some(val) => val,
+        None => return Ok(None),
+    };
+
+    let statement = db.prepare("SELECT * FROM release_dats WHERE release_id = ?1");
+    let query = statement.bind(&[(release.id as f64).into()])?;
+    let dats = query.all().await?.results::<db::ReleaseDat>()?;
+
+    Ok(Some(db::DatRelease { release, dats }))
+}
+
+/// The release requested via the /v/:release prefix or the ?release= query
+/// parameter, if any. The path prefix wins when both are given.
+pub fn release_param<'a>(
+    ctx: &'a RouteContext<Context>,
+    query_params: &'a HashMap<String, String>,
+) -> Option<&'a str> {
+    ctx.param("release")
+        .or_else(|| query_params.get("release"))
+        .map(|value| value.as_str())
+}
+
+/// Format a content hash as a strong ETag.
+pub fn quote_etag(hash: &str) -> String {
+    format!("\"{}\"", hash)
+}
+
+/// Whether an If-None-Match header value matches the given ETag. Weak
+/// comparison is used, as RFC 9110 requires for If-None-Match.
+pub fn etag_matches(if_none_match: Option<&str>, etag: &str) -> bool {
+    let if_none_match = match if_none_match {
+        Some(val) => val,
+        None => return false,
+    };
+
+    if_none_match.trim() == "*"
+        || if_none_match
+            .split(',')
+            .map(|tag| tag.trim())
+            .map(|tag| tag.strip_prefix("W/").unwrap_or(tag))
+            .any(|tag| tag == etag)
+}
+
+pub fn not_modified(etag: &str) -> Result<Response> {
+    let mut response = Response::empty()?.with_status(304);
+    response.headers_mut().set("ETag", etag)?;
+
+    Ok(with_cors_headers(response))
+}
+
 /// Parse a file ID from decimal or hex (0x-prefixed) string.
 /// Unlike parse_decimal_or_hex_string, this does not apply any icon-specific offsets.
-pub fn parse_file_id(text: &str) -> std::result::Result<i32, Box<dyn Error>> {
+pub fn parse_file_id(text: &str) -> std::result::Result<u32, Box<dyn Error>> {
     if let Some(hex_str) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
-        i32::from_str_radix(hex_str, 16).map_err(|e| e.into())
+        u32::from_str_radix(hex_str, 16).map_err(|e| e.into())
     } else {
-        text.parse::<i32>().map_err(|e| e.into())
+        text.parse::<u32>().map_err(|e| e.into())
     }
 }
 
@@ -1,7 +1,10 @@
 #[cfg(test)]
 mod tests {
-    use crate::{db::File, parse_decimal_or_hex_string};
-    use acprotocol::dat::DatFileType;
+    use crate::{
+        db::{file_type_for, portal_key, DatRelease, File, Release, ReleaseDat},
+        etag_matches, parse_decimal_or_hex_string, parse_file_id, quote_etag,
+    };
+    use acprotocol::dat::{DatDatabaseType, DatFileType};
 
     #[test]
     fn test_parse_icon_id_string() {
@@ -55,6 +58,101 @@ mod tests {
         assert!(parse_decimal_or_hex_string("0x12345").is_err());
     }
 
+    #[test]
+    fn test_parse_file_id() {
+        assert_eq!(parse_file_id("16777217").unwrap(), 0x1000001);
+        assert_eq!(parse_file_id("0x1000001").unwrap(), 0x1000001);
+        // Cell IDs use the full u32 range
+        assert_eq!(parse_file_id("0xA9B4FFFF").unwrap(), 0xA9B4FFFF);
+        assert_eq!(parse_file_id("2847211519").unwrap(), 0xA9B4FFFF);
+
+        assert!(parse_file_id("").is_err());
+        assert!(parse_file_id("-1").is_err());
+        assert!(parse_file_id("0x1FFFFFFFF").is_err());
+    }
+
+    #[test]
+    fn test_dat_for_follows_database_type() {
+        let release = DatRelease {
+            release: Release {
+                id: 2,
+                name: "retail".to_string(),
+                created_at: "2025-01-01 00:00:00".to_string(),
+            },
+            dats: vec![
+                ReleaseDat {
+                    release_id: 2,
+                    database_type: DatDatabaseType::Portal.as_u32() as i64,
+                    object_key: "retail/client_portal.dat".to_string(),
+                    block_size: 1024,
+                },
+                ReleaseDat {
+                    release_id: 2,
+                    database_type: DatDatabaseType::Cell.as_u32() as i64,
+                    object_key: "retail/client_cell_1.dat".to_string(),
+                    block_size: 256,
+                },
+            ],
+        };
+        let mut file = File {
+            id: 0xA9B4FFFF,
+            database_type: DatDatabaseType::Cell.as_u32() as i64,
+            file_type: DatFileType::LandBlock.as_u32() as i64,
+            file_subtype: 0,
+            file_offset: 0,
+            file_size: 0,
+            release_id: 2,
+            content_hash: None,
+        };
+        let dat = release.dat_for(&file).unwrap();
+        assert_eq!(dat.object_key, "retail/client_cell_1.dat");
+        assert_eq!(dat.block_size, 256);
+
+        file.database_type = DatDatabaseType::Portal.as_u32() as i64;
+        assert_eq!(
+            release.dat_for(&file).unwrap().object_key,
+            "retail/client_portal.dat"
+        );
+
+        // Files from other releases never resolve against this one
+        file.release_id = 1;
+        assert!(release.dat_for(&file).is_none());
+    }
+
+    #[test]
+    fn test_file_key_includes_database() {
+        let landblock = File {
+            id: 0x0E00FFFF,
+            database_type: DatDatabaseType::Cell.as_u32() as i64,
+            file_type: DatFileType::LandBlock.as_u32() as i64,
+            file_subtype: 0,
+            file_offset: 0,
+            file_size: 0,
+            release_id: 2,
+            content_hash: None,
+        };
+        assert_eq!(
+            landblock.key(),
+            (DatDatabaseType::Cell.as_u32(), 0x0E00FFFF)
+        );
+        assert_ne!(landblock.key(), portal_key(0x0E00FFFF));
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        let etag = quote_etag("abc123");
+        assert_eq!(etag, "\"abc123\"");
+
+        assert!(etag_matches(Some("\"abc123\""), &etag));
+        assert!(etag_matches(Some("W/\"abc123\""), &etag));
+        assert!(etag_matches(Some("\"other\", \"abc123\""), &etag));
+        assert!(etag_matches(Some("*"), &etag));
+
+        assert!(!etag_matches(None, &etag));
+        assert!(!etag_matches(Some("\"other\""), &etag));
+        assert!(!etag_matches(Some("abc123"), &etag));
+    }
+
     #[test]
     fn test_resolved_file_type_prefers_object_id_mapping() {
         let file = File {
@@ -64,8 +162,31 @@ mod tests {
             file_subtype: 0,
             file_offset: 0,
             file_size: 0,
+            release_id: 1,
+            content_hash: None,
         };
 
         assert_eq!(file.resolved_file_type(), DatFileType::CharacterGenerator);
     }
+
+    #[test]
+    fn test_resolved_file_type_for_cell_files() {
+        let cell = DatDatabaseType::Cell;
+        assert_eq!(file_type_for(&cell, 0x0600FFFF), DatFileType::LandBlock);
+        assert_eq!(file_type_for(&cell, 0x0600FFFE), DatFileType::LandBlockInfo);
+        assert_eq!(file_type_for(&cell, 0x06000100), DatFileType::EnvCell);
+
+        // 0x06 is the Texture range in the portal DAT
+        let file = File {
+            id: 0x0600FFFF,
+            database_type: cell.as_u32() as i64,
+            file_type: DatFileType::LandBlock.as_u32() as i64,
+            file_subtype: 0,
+            file_offset: 0,
+            file_size: 0,
+            release_id: 1,
+            content_hash: None,
+        };
+        assert_eq!(file.resolved_file_type(), DatFileType::LandBlock);
+    }
 }
@@ -43,6 +43,8 @@ pub struct Operation {
 pub struct PathItem {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub get: Option<Operation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post: Option<Operation>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -115,6 +117,25 @@ pub enum Schema {
     },
 }
 
+impl Schema {
+    /// An unconstrained schema of the given type, e.g. "string" or "integer"
+    pub fn of_type(schema_type: &str) -> Self {
+        Schema::ObjectSchema {
+            schema_type: schema_type.to_string(),
+            format: None,
+            default: None,
+            minimum: None,
+            maximum: None,
+            min_length: None,
+            max_length: None,
+            read_only: None,
+            description: None,
+            properties: None,
+            required: vec![],
+        }
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Serialize, Deserialize)]
 struct Components {
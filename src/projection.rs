@@ -0,0 +1,511 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/// One step of a path into a JSON document
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    /// An object key, or an index into an array
+    Key(String),
+    /// Every value of an object or every element of an array
+    Any,
+}
+
+/// Parse a dotted path (`spells.*.name`) or a JSON pointer (`/spells/*/name`).
+/// `*` matches every key or element.
+pub fn parse_path(text: &str) -> Result<Vec<Segment>, String> {
+    let keys: Vec<String> = match text.strip_prefix('/') {
+        Some(pointer) => pointer
+            .split('/')
+            .map(|key| key.replace("~1", "/").replace("~0", "~"))
+            .collect(),
+        None => text.split('.').map(|key| key.to_string()).collect(),
+    };
+
+    if text.is_empty() || keys.iter().any(|key| key.is_empty()) {
+        return Err(format!("Invalid path: {:?}", text));
+    }
+
+    Ok(keys
+        .into_iter()
+        .map(|key| match key.as_str() {
+            "*" => Segment::Any,
+            _ => Segment::Key(key),
+        })
+        .collect())
+}
+
+fn child<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    match value {
+        Value::Object(map) => map.get(key),
+        Value::Array(items) => key.parse::<usize>().ok().and_then(|index| items.get(index)),
+        _ => None,
+    }
+}
+
+fn child_mut<'a>(value: &'a mut Value, key: &str) -> Option<&'a mut Value> {
+    match value {
+        Value::Object(map) => map.get_mut(key),
+        Value::Array(items) => key
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| items.get_mut(index)),
+        _ => None,
+    }
+}
+
+/// The parts of a document picked out by `?fields=`. Arrays keep a slot for
+/// every element so paths into the same array merge by position; the slots
+/// no path picked are dropped once every path has been merged.
+#[derive(Debug, Clone, PartialEq)]
+enum Selection {
+    Value(Value),
+    Object(BTreeMap<String, Selection>),
+    Array(Vec<Option<Selection>>),
+}
+
+impl Selection {
+    fn into_value(self) -> Value {
+        match self {
+            Selection::Value(value) => value,
+            Selection::Object(map) => Value::Object(
+                map.into_iter()
+                    .map(|(key, selection)| (key, selection.into_value()))
+                    .collect(),
+            ),
+            Selection::Array(items) => Value::Array(
+                items
+                    .into_iter()
+                    .flatten()
+                    .map(Selection::into_value)
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// The parts of `value` under `path`, keeping the structure around them.
+fn select(value: &Value, path: &[Segment]) -> Option<Selection> {
+    let (segment, rest) = match path.split_first() {
+        Some(val) => val,
+        None => return Some(Selection::Value(value.clone())),
+    };
+
+    match (segment, value) {
+        (Segment::Key(key), Value::Object(_)) => {
+            let selected = select(child(value, key)?, rest)?;
+            Some(Selection::Object(BTreeMap::from([(key.clone(), selected)])))
+        }
+        (Segment::Key(key), Value::Array(items)) => {
+            let index = key.parse::<usize>().ok()?;
+            let selected = select(items.get(index)?, rest)?;
+            let mut slots = vec![None; index];
+            slots.push(Some(selected));
+            Some(Selection::Array(slots))
+        }
+        (Segment::Any, Value::Object(map)) => Some(Selection::Object(
+            map.iter()
+                .filter_map(|(key, item)| Some((key.clone(), select(item, rest)?)))
+                .collect(),
+        )),
+        (Segment::Any, Value::Array(items)) => Some(Selection::Array(
+            items.iter().map(|item| select(item, rest)).collect(),
+        )),
+        _ => None,
+    }
+}
+
+/// Fold `other` into `into`. Objects are merged key by key and arrays slot
+/// by slot. A whole value already holds anything narrower picked from it, so
+/// it wins whichever side it's on.
+fn merge(into: &mut Selection, other: Selection) {
+    match (into, other) {
+        (Selection::Object(into), Selection::Object(other)) => {
+            for (key, value) in other {
+                match into.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        into.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Selection::Array(into), Selection::Array(other)) => {
+            if into.len() < other.len() {
+                into.resize(other.len(), None);
+            }
+            for (slot, value) in into.iter_mut().zip(other) {
+                match (slot, value) {
+                    (_, None) => {}
+                    (Some(existing), Some(value)) => merge(existing, value),
+                    (slot, value) => *slot = value,
+                }
+            }
+        }
+        (Selection::Value(_), _) => {}
+        (into, other) => *into = other,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Equals,
+    NotEquals,
+    Contains,
+}
+
+impl Op {
+    fn symbol(&self) -> &'static str {
+        match self {
+            Op::Equals => "=",
+            Op::NotEquals => "!=",
+            Op::Contains => "~=",
+        }
+    }
+}
+
+/// Keep only the members of a collection whose field compares to a value,
+/// e.g. `spells.*.school=4`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    collection: Vec<Segment>,
+    field: Vec<Segment>,
+    op: Op,
+    value: String,
+}
+
+impl Filter {
+    /// The path must have exactly one `*`, standing for the members of the
+    /// collection being filtered.
+    pub fn parse(text: &str) -> Result<Filter, String> {
+        let (path, op, value) = [Op::NotEquals, Op::Contains, Op::Equals]
+            .iter()
+            .find_map(|op| {
+                text.split_once(op.symbol())
+                    .map(|(path, value)| (path, *op, value))
+            })
+            .ok_or_else(|| {
+                format!(
+                    "Invalid filter: {:?}. Use path=value, path!=value or path~=value.",
+                    text
+                )
+            })?;
+
+        let segments = parse_path(path)?;
+        let wildcards = segments
+            .iter()
+            .filter(|segment| **segment == Segment::Any)
+            .count();
+        if wildcards != 1 {
+            return Err(format!(
+                "Invalid filter: {:?}. The path needs exactly one * for the members to filter.",
+                text
+            ));
+        }
+
+        let split = segments
+            .iter()
+            .position(|segment| *segment == Segment::Any)
+            .unwrap_or_default();
+
+        Ok(Filter {
+            collection: segments[..split].to_vec(),
+            field: segments[split + 1..].to_vec(),
+            op,
+            value: value.to_string(),
+        })
+    }
+
+    fn matches(&self, member: &Value) -> bool {
+        let mut field = Some(member);
+        for segment in &self.field {
+            field = match segment {
+                Segment::Key(key) => field.and_then(|value| child(value, key)),
+                Segment::Any => None,
+            };
+        }
+
+        let equals = |field: &Value| match field {
+            Value::String(text) => *text == self.value,
+            Value::Number(number) => parse_number(&self.value) == number.as_f64(),
+            Value::Bool(flag) => self.value == flag.to_string(),
+            Value::Null => self.value == "null",
+            _ => false,
+        };
+
+        match (self.op, field) {
+            (Op::Equals, Some(field)) => equals(field),
+            (Op::NotEquals, Some(field)) => !equals(field),
+            (Op::NotEquals, None) => true,
+            (Op::Contains, Some(Value::String(text))) => {
+                text.to_lowercase().contains(&self.value.to_lowercase())
+            }
+            (Op::Contains, Some(Value::Number(number))) => number.to_string().contains(&self.value),
+            _ => false,
+        }
+    }
+
+    fn apply(&self, value: &mut Value) {
+        let mut collection = Some(value);
+        for segment in &self.collection {
+            collection = match segment {
+                Segment::Key(key) => collection.and_then(|value| child_mut(value, key)),
+                Segment::Any => None,
+            };
+        }
+
+        match collection {
+            Some(Value::Object(map)) => map.retain(|_, member| self.matches(member)),
+            Some(Value::Array(items)) => items.retain(|member| self.matches(member)),
+            _ => {}
+        }
+    }
+}
+
+/// Numbers in filters can be decimal or 0x-prefixed hex like file IDs
+fn parse_number(text: &str) -> Option<f64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok().map(|value| value as f64),
+        None => text.parse::<f64>().ok(),
+    }
+}
+
+/// `?fields=` and `?filter=` for JSON exports. Filters run first so they can
+/// test fields that aren't kept.
+#[derive(Debug, Default, PartialEq)]
+pub struct Projection {
+    fields: Vec<Vec<Segment>>,
+    filters: Vec<Filter>,
+    // The parameters as given, for ETags
+    source: String,
+}
+
+impl Projection {
+    /// Both parameters are comma-separated lists. Missing parameters leave
+    /// the document as it is.
+    pub fn from_params(params: &HashMap<String, String>) -> Result<Projection, String> {
+        let list = |name: &str| -> Vec<&str> {
+            params
+                .get(name)
+                .map(|value| value.split(',').map(|item| item.trim()).collect())
+                .unwrap_or_default()
+        };
+
+        let fields = list("fields")
+            .into_iter()
+            .map(parse_path)
+            .collect::<Result<_, _>>()?;
+        let filters = list("filter")
+            .into_iter()
+            .map(Filter::parse)
+            .collect::<Result<_, _>>()?;
+
+        Ok(Projection {
+            fields,
+            filters,
+            source: format!(
+                "fields={}&filter={}",
+                params.get("fields").map(String::as_str).unwrap_or_default(),
+                params.get("filter").map(String::as_str).unwrap_or_default()
+            ),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.filters.is_empty()
+    }
+
+    /// A short digest of the parameters, or None when there's nothing to do
+    pub fn tag(&self) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let digest = Sha256::digest(self.source.as_bytes());
+        Some(format!("{:x}", digest)[..16].to_string())
+    }
+
+    pub fn apply(&self, value: &mut Value) {
+        for filter in &self.filters {
+            filter.apply(value);
+        }
+
+        if self.fields.is_empty() {
+            return;
+        }
+
+        let mut projected = Selection::Object(BTreeMap::new());
+        for path in &self.fields {
+            if let Some(selected) = select(value, path) {
+                merge(&mut projected, selected);
+            }
+        }
+        *value = projected.into_value();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn spell_table() -> Value {
+        json!({
+            "id": 0x0E00000E_u32,
+            "spells": {
+                "1": {"name": "Strength Other I", "school": 4, "icon": 0x06001234_u32},
+                "2": {"name": "Flame Bolt I", "school": 1, "icon": 0x06001235_u32},
+                "3": {"name": "Flame Bolt II", "school": 1, "icon": 0x06001236_u32},
+            },
+            "sets": [{"name": "A", "spells": [1, 2]}, {"name": "B", "spells": [3]}],
+        })
+    }
+
+    fn projection(pairs: &[(&str, &str)]) -> Result<Projection, String> {
+        let params = pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Projection::from_params(&params)
+    }
+
+    #[test]
+    fn test_parse_path() {
+        assert_eq!(
+            parse_path("spells.*.name"),
+            Ok(vec![
+                Segment::Key("spells".to_string()),
+                Segment::Any,
+                Segment::Key("name".to_string()),
+            ])
+        );
+        assert_eq!(parse_path("/spells/*/name"), parse_path("spells.*.name"));
+        assert_eq!(
+            parse_path("/a~1b/c~0d"),
+            Ok(vec![
+                Segment::Key("a/b".to_string()),
+                Segment::Key("c~d".to_string()),
+            ])
+        );
+        assert!(parse_path("").is_err());
+        assert!(parse_path("spells..name").is_err());
+    }
+
+    #[test]
+    fn test_fields() {
+        let mut value = spell_table();
+        projection(&[("fields", "id,spells.*.name")])
+            .unwrap()
+            .apply(&mut value);
+
+        assert_eq!(
+            value,
+            json!({
+                "id": 0x0E00000E_u32,
+                "spells": {
+                    "1": {"name": "Strength Other I"},
+                    "2": {"name": "Flame Bolt I"},
+                    "3": {"name": "Flame Bolt II"},
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_fields_merge_arrays() {
+        let mut value = spell_table();
+        projection(&[("fields", "/sets/*/name,sets.*.spells.0")])
+            .unwrap()
+            .apply(&mut value);
+
+        assert_eq!(
+            value,
+            json!({"sets": [{"name": "A", "spells": [1]}, {"name": "B", "spells": [3]}]})
+        );
+    }
+
+    #[test]
+    fn test_fields_keep_array_positions() {
+        let mut value = json!({"items": [{"b": 1}, {"a": 2, "b": 3}]});
+        projection(&[("fields", "items.*.a,items.*.b")])
+            .unwrap()
+            .apply(&mut value);
+        assert_eq!(value, json!({"items": [{"b": 1}, {"a": 2, "b": 3}]}));
+
+        let mut value = spell_table();
+        projection(&[("fields", "sets.1.name,sets.0.spells")])
+            .unwrap()
+            .apply(&mut value);
+        assert_eq!(value, json!({"sets": [{"spells": [1, 2]}, {"name": "B"}]}));
+
+        // Elements no path reaches are dropped, nulls that were picked aren't
+        let mut value = json!({"items": [{"a": null}, {"b": 1}, {"a": 3}]});
+        projection(&[("fields", "items.*.a")])
+            .unwrap()
+            .apply(&mut value);
+        assert_eq!(value, json!({"items": [{"a": null}, {"a": 3}]}));
+    }
+
+    #[test]
+    fn test_fields_order_does_not_matter() {
+        let mut broad_first = spell_table();
+        projection(&[("fields", "spells,spells.*.name")])
+            .unwrap()
+            .apply(&mut broad_first);
+
+        let mut narrow_first = spell_table();
+        projection(&[("fields", "spells.*.name,spells")])
+            .unwrap()
+            .apply(&mut narrow_first);
+
+        assert_eq!(broad_first, narrow_first);
+        assert_eq!(broad_first, json!({"spells": spell_table()["spells"]}));
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut value = spell_table();
+        projection(&[("filter", "spells.*.school=1"), ("fields", "spells.*.name")])
+            .unwrap()
+            .apply(&mut value);
+        assert_eq!(
+            value,
+            json!({"spells": {"2": {"name": "Flame Bolt I"}, "3": {"name": "Flame Bolt II"}}})
+        );
+
+        let mut value = spell_table();
+        projection(&[(
+            "filter",
+            "spells.*.name~=bolt ii, spells.*.icon!=0x06001234",
+        )])
+        .unwrap()
+        .apply(&mut value);
+        let spells = value["spells"].as_object().unwrap();
+        assert_eq!(spells.keys().collect::<Vec<_>>(), vec!["3"]);
+
+        let mut value = spell_table();
+        projection(&[("filter", "sets.*.name=B")])
+            .unwrap()
+            .apply(&mut value);
+        assert_eq!(value["sets"], json!([{"name": "B", "spells": [3]}]));
+    }
+
+    #[test]
+    fn test_invalid_projection() {
+        assert!(projection(&[("filter", "spells.*.school")]).is_err());
+        assert!(projection(&[("filter", "spells.school=1")]).is_err());
+        assert!(projection(&[("filter", "spells.*.*=1")]).is_err());
+        assert!(projection(&[("fields", "spells,")]).is_err());
+    }
+
+    #[test]
+    fn test_tag() {
+        assert_eq!(projection(&[]).unwrap().tag(), None);
+
+        let a = projection(&[("fields", "id")]).unwrap().tag().unwrap();
+        let b = projection(&[("fields", "spells")]).unwrap().tag().unwrap();
+        assert_eq!(a.len(), 16);
+        assert_ne!(a, b);
+    }
+}
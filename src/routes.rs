@@ -1,15 +1,37 @@
 use acprotocol::dat::{
-    file_types::{dat_file::DatFile, texture::Texture, CharGen, SpellTable},
-    DatFileSubtype, DatFileType, Icon,
+    file_types::{dat_file::DatFile, texture::Texture, GfxObj, Setup, Surface, SurfaceTexture},
+    DatDatabaseType, DatFileSubtype, DatFileType,
+};
+use base64::prelude::*;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    io::{Cursor, Write},
 };
-use std::{collections::HashMap, fmt::Debug, io::Cursor};
 use worker::*;
 
 use crate::{
-    generators::icon::generate_icon,
-    get_buf_for_file, get_file_by_id,
+    data_format::{DataFormat, Exported},
+    db::{portal_key, DatRelease, File, FileKey},
+    etag_matches, exporters,
+    file_query::{page_limit, FileQuery, QueryValue},
+    generators::{
+        atlas::{self, Atlas},
+        clothing,
+        icon::{
+            BatchIcon, IconSpec, ICON_CACHE_CONTROL, LATEST_ICON_CACHE_CONTROL, MAX_ATLAS_COLUMNS,
+            MAX_ATLAS_ICONS, MAX_ATLAS_PIXELS, MAX_BATCH_ICONS,
+        },
+        model::{self, Model},
+        output::{generate_image, OutputFormat},
+        palette::{self, PaletteChoice},
+        sound, spells, strings, tables, texture,
+    },
+    get_buf_for_file, get_file_by_id, get_files_by_ids, get_release, get_render, not_modified,
     openapi::{Contact, Info, OpenApiDocument, Operation, Parameter, PathItem, Schema, Server},
-    parse_decimal_or_hex_string, parse_file_id, with_cors_headers,
+    parse_decimal_or_hex_string, parse_file_id,
+    projection::Projection,
+    put_render, quote_etag, release_param, search, with_cors_headers,
 };
 
 #[allow(dead_code)]
@@ -23,26 +45,231 @@ struct DebugResponse {
     ui_effect: Option<String>,
 }
 
-pub async fn index_get(_ctx: RouteContext<()>) -> Result<Response> {
+fn release_parameter() -> Parameter {
+    Parameter {
+        name: "release".to_string(),
+        location: "query".to_string(),
+        description: "Optional DAT release name. Defaults to the most recently imported release. Routes can also be pinned to a release with a /v/:release prefix, e.g. /v/retail/icons/26967.".to_string(),
+        required: false,
+        schema: Schema::of_type("string"),
+    }
+}
+
+fn database_parameter() -> Parameter {
+    Parameter {
+        name: "database".to_string(),
+        location: "query".to_string(),
+        description: "Optional database to look the ID up in, by number or name, e.g. Portal or Cell. IDs repeat across databases; without this the portal's file is used, and an ID found only in several other databases is a 400.".to_string(),
+        required: false,
+        schema: Schema::of_type("string"),
+    }
+}
+
+fn image_format_parameter() -> Parameter {
+    let names: Vec<&str> = OutputFormat::ALL.iter().map(|f| f.name()).collect();
+    Parameter {
+        name: "format".to_string(),
+        location: "query".to_string(),
+        description: format!("Optional image format, one of {}. When omitted the format is picked from the Accept header, falling back to png.", names.join(", ")),
+        required: false,
+        schema: Schema::of_type("string"),
+    }
+}
+
+fn palette_parameter() -> Parameter {
+    Parameter {
+        name: "palette".to_string(),
+        location: "query".to_string(),
+        description:
+            "Optional Palette (0x04) ID to use in place of a paletted texture's own palette."
+                .to_string(),
+        required: false,
+        schema: Schema::of_type("string"),
+    }
+}
+
+fn palette_set_parameter() -> Parameter {
+    Parameter {
+        name: "palette_set".to_string(),
+        location: "query".to_string(),
+        description: "Optional PaletteSet (0x0F) ID to pick a substitute palette from using shade. Can't be combined with palette.".to_string(),
+        required: false,
+        schema: Schema::of_type("string"),
+    }
+}
+
+fn shade_parameter() -> Parameter {
+    Parameter {
+        name: "shade".to_string(),
+        location: "query".to_string(),
+        description:
+            "Optional shade from 0 to 1 picking a palette from palette_set. Defaults to 0."
+                .to_string(),
+        required: false,
+        schema: Schema::of_type("number"),
+    }
+}
+
+pub async fn index_get(_ctx: RouteContext<Context>) -> Result<Response> {
     let mut paths = HashMap::new();
+    paths.insert(
+        "/releases".to_string(),
+        PathItem {
+            post: None,
+            get: Some(Operation {
+                summary: "List all DAT releases".to_string(),
+                description: "Returns a newline-separated list of all imported DAT releases, most recent first.".to_string(),
+                operation_id: "releases_index".to_string(),
+                parameters: vec![],
+            }),
+        },
+    );
+    paths.insert(
+        "/diff".to_string(),
+        PathItem {
+            post: None,
+            get: Some(Operation {
+                summary: "Diff two DAT releases".to_string(),
+                description: "Returns a newline-separated list of files that were added, removed, or changed between two releases. Each line has the same shape as the /files listing plus a change field of added, removed, or changed. Files are considered changed when their size or content hash differs.".to_string(),
+                operation_id: "diff_get".to_string(),
+                parameters: vec![
+                    Parameter {
+                        name: "from".to_string(),
+                        location: "query".to_string(),
+                        description: "Name of the release to diff from.".to_string(),
+                        required: true,
+                        schema: Schema::of_type("string"),
+                    },
+                    Parameter {
+                        name: "to".to_string(),
+                        location: "query".to_string(),
+                        description: "Optional name of the release to diff to. Defaults to the most recently imported release.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("string"),
+                    },
+                ],
+            }),
+        },
+    );
+    paths.insert(
+        "/search".to_string(),
+        PathItem {
+            post: None,
+            get: Some(Operation {
+                summary: "Search files".to_string(),
+                description: "Full-text search over file type and subtype names and the names decoded from files. Spell names are found on the spell's icon and DualDidMapper names on the file each one maps to, while StringTable strings and EnumMapper names are found on the table itself. Returns newline-delimited JSON in the same shape as the /files listing plus a snippet of the matched text, best matches first. Words are matched whole unless they end in *, which matches any word starting with them.".to_string(),
+                operation_id: "search_get".to_string(),
+                parameters: vec![
+                    Parameter {
+                        name: "q".to_string(),
+                        location: "query".to_string(),
+                        description: "Words to search for, e.g. Strength Other or Drudge*.".to_string(),
+                        required: true,
+                        schema: Schema::of_type("string"),
+                    },
+                    Parameter {
+                        name: "limit".to_string(),
+                        location: "query".to_string(),
+                        description: "Maximum number of results, 1 to 1000. Defaults to 100.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("integer"),
+                    },
+                    release_parameter(),
+                ],
+            }),
+        },
+    );
     paths.insert(
         "/files".to_string(),
         PathItem {
+            post: None,
             get: Some(Operation {
-                summary: "List all file IDs".to_string(),
-                description: "Returns a newline-separated list of all file IDs in the database."
-                    .to_string(),
+                summary: "List files".to_string(),
+                description: "Lists files in a release as newline-delimited JSON, optionally filtered by type, subtype, database, ID range and size. Every matching file is returned unless limit or after is given; then pages hold up to limit files and, when there are more, a Link header with rel=\"next\" points at the next page.".to_string(),
                 operation_id: "files_index".to_string(),
-                parameters: vec![],
+                parameters: vec![
+                    Parameter {
+                        name: "type".to_string(),
+                        location: "query".to_string(),
+                        description: "Only files of this type, by number or name, e.g. Wave or 10.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("string"),
+                    },
+                    Parameter {
+                        name: "subtype".to_string(),
+                        location: "query".to_string(),
+                        description: "Only files of this subtype, by number or name, e.g. Icon.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("string"),
+                    },
+                    Parameter {
+                        name: "database".to_string(),
+                        location: "query".to_string(),
+                        description: "Only files from this database, by number or name, e.g. Portal or Cell.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("string"),
+                    },
+                    Parameter {
+                        name: "min_id".to_string(),
+                        location: "query".to_string(),
+                        description: "Only files with this ID or higher, in decimal or 0x-prefixed hex.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("string"),
+                    },
+                    Parameter {
+                        name: "max_id".to_string(),
+                        location: "query".to_string(),
+                        description: "Only files with this ID or lower, in decimal or 0x-prefixed hex.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("string"),
+                    },
+                    Parameter {
+                        name: "min_size".to_string(),
+                        location: "query".to_string(),
+                        description: "Only files of at least this many bytes.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("integer"),
+                    },
+                    Parameter {
+                        name: "max_size".to_string(),
+                        location: "query".to_string(),
+                        description: "Only files of at most this many bytes.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("integer"),
+                    },
+                    Parameter {
+                        name: "order".to_string(),
+                        location: "query".to_string(),
+                        description: "Sort by id or size, prefixed with - for descending. Defaults to id.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("string"),
+                    },
+                    Parameter {
+                        name: "limit".to_string(),
+                        location: "query".to_string(),
+                        description: "Files per page, 1 to 1000. Leave it out to list every file, or to page by 100 when following a Link header.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("integer"),
+                    },
+                    Parameter {
+                        name: "after".to_string(),
+                        location: "query".to_string(),
+                        description: "Start after this position. Use the Link header rather than setting it by hand.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("string"),
+                    },
+                    release_parameter(),
+                ],
             }),
         },
     );
     paths.insert(
         "/files/:file_id".to_string(),
         PathItem {
+            post: None,
             get: Some(Operation {
                 summary: "Get a file by ID".to_string(),
-                description: "Returns the raw binary content of a DAT file by its ID. The file_id can be specified as a decimal number (e.g., 16777217) or as a hex string with 0x prefix (e.g., 0x1000001). Add ?format=json to request a JSON representation for file types that support it.".to_string(),
+                description: format!("Returns the raw binary content of a DAT file by its ID. The file_id can be specified as a decimal number (e.g., 16777217) or as a hex string with 0x prefix (e.g., 0x1000001). Add ?format=json, cbor, msgpack or yaml to request a structured representation for file types that support it: {}. Other types get a 415 listing the supported ones.", exporters::describe()),
                 operation_id: "files_get".to_string(),
                 parameters: vec![
                     Parameter {
@@ -67,7 +294,7 @@ pub async fn index_get(_ctx: RouteContext<()>) -> Result<Response> {
                     Parameter {
                         name: "format".to_string(),
                         location: "query".to_string(),
-                        description: "Optional response format. Use json to request a JSON representation for file types that support export.".to_string(),
+                        description: "Optional response format: json, cbor, msgpack or yaml for a structured representation of file types that support export. Leave it out for the raw file.".to_string(),
                         required: false,
                         schema: Schema::ObjectSchema {
                             schema_type: "string".to_string(),
@@ -83,6 +310,22 @@ pub async fn index_get(_ctx: RouteContext<()>) -> Result<Response> {
                             required: vec![],
                         },
                     },
+                    Parameter {
+                        name: "fields".to_string(),
+                        location: "query".to_string(),
+                        description: "Optional comma-separated paths to keep in the JSON, as dotted paths (spells.*.name) or JSON pointers (/spells/*/name). * matches every key or element. Requires format=json.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("string"),
+                    },
+                    Parameter {
+                        name: "filter".to_string(),
+                        location: "query".to_string(),
+                        description: "Optional comma-separated filters applied before fields, as path=value, path!=value or path~=value (contains, ignoring case). The path's single * stands for the members to keep or drop, e.g. spells.*.school=1. Requires format=json.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("string"),
+                    },
+                    database_parameter(),
+                    release_parameter(),
                 ],
             }),
         },
@@ -90,20 +333,92 @@ pub async fn index_get(_ctx: RouteContext<()>) -> Result<Response> {
     paths.insert(
         "/icons".to_string(),
         PathItem {
+            post: None,
             get: Some(Operation {
                 summary: "List all icon IDs".to_string(),
                 description: "Returns a newline-separated list of all icon IDs in the database (files with Icon subtype).".to_string(),
                 operation_id: "icons_index".to_string(),
-                parameters: vec![],
+                parameters: vec![release_parameter()],
+            }),
+        },
+    );
+    paths.insert(
+        "/icons/atlas".to_string(),
+        PathItem {
+            post: None,
+            get: Some(Operation {
+                summary: "Get an icon atlas".to_string(),
+                description: format!("Returns a single PNG with up to {} icons and {} pixels laid out in a grid, or with ?format=json a manifest mapping each icon ID to its {{x, y, w, h}} position within the atlas. Icons are chosen with ids or with min_id and max_id, and every icon is rendered with the same scale, background, underlay, overlay, and ui_effect parameters as /icons/:icon_id.", MAX_ATLAS_ICONS, MAX_ATLAS_PIXELS),
+                operation_id: "icons_atlas_get".to_string(),
+                parameters: vec![
+                    Parameter {
+                        name: "ids".to_string(),
+                        location: "query".to_string(),
+                        description: "Comma-separated icon IDs as decimal or hex, absolute or relative. Repeated IDs are only placed once.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("string"),
+                    },
+                    Parameter {
+                        name: "min_id".to_string(),
+                        location: "query".to_string(),
+                        description: "Lowest icon ID to include when ids isn't given.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("string"),
+                    },
+                    Parameter {
+                        name: "max_id".to_string(),
+                        location: "query".to_string(),
+                        description: "Highest icon ID to include when ids isn't given.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("string"),
+                    },
+                    Parameter {
+                        name: "columns".to_string(),
+                        location: "query".to_string(),
+                        description: format!("Optional number of icons per row, up to {}. Defaults to a roughly square grid.", MAX_ATLAS_COLUMNS),
+                        required: false,
+                        schema: Schema::of_type("integer"),
+                    },
+                    Parameter {
+                        name: "format".to_string(),
+                        location: "query".to_string(),
+                        description: "Optional response format, png (default) or json.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("string"),
+                    },
+                    release_parameter(),
+                ],
+            }),
+        },
+    );
+    paths.insert(
+        "/icons/batch".to_string(),
+        PathItem {
+            get: None,
+            post: Some(Operation {
+                summary: "Render a batch of icons".to_string(),
+                description: format!("Renders up to {} icons in one request. The request body is a JSON list of objects with the same fields as /icons/:icon_id's path and query parameters (id, scale, background, underlay, overlay, ui_effect) plus an optional name to return the icon under, which defaults to the id as given. Names must be unique and can't contain /, \\ or .. Returns a JSON object mapping each name to a PNG data URI or, with ?format=zip, a ZIP of name.png files.", MAX_BATCH_ICONS),
+                operation_id: "icons_batch_post".to_string(),
+                parameters: vec![
+                    Parameter {
+                        name: "format".to_string(),
+                        location: "query".to_string(),
+                        description: "Optional response format, json (default) or zip.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("string"),
+                    },
+                    release_parameter(),
+                ],
             }),
         },
     );
     paths.insert(
         "/icons/:icon_id".to_string(),
         PathItem {
+            post: None,
             get: Some(Operation {
                 summary: "Get an icon".to_string(),
-                description: "Returns a PNG icon with optional scaling applied and any provided underlay, overlay, or UI effect mixed in. Example https://dats.treestats.net/icons/26967?scale=2. All Icon IDs can be passed as decimal or hex and either absolute or relative (to 0x06000000) values can be used. For example, all of these values return the same icon: 0x6957, 0x06006957, 26967, 100690263.".to_string(),
+                description: "Returns an icon (PNG by default) with optional scaling applied and any provided underlay, overlay, or UI effect mixed in. Example https://dats.treestats.net/icons/26967?scale=2. All Icon IDs can be passed as decimal or hex and either absolute or relative (to 0x06000000) values can be used. For example, all of these values return the same icon: 0x6957, 0x06006957, 26967, 100690263. Rendered icons are cached at the edge and the X-Cache response header reports HIT or MISS. Renders are also persisted to R2, keyed on the content of every input texture, and the X-Render-Cache header reports whether one was reused.".to_string(),
                 operation_id: "icons_get".to_string(),
                 parameters: vec![Parameter {
                     name: "icon_id".to_string(),
@@ -218,65 +533,431 @@ pub async fn index_get(_ctx: RouteContext<()>) -> Result<Response> {
                         properties: None,
                         required: vec![],
                     },
-                }],
+                },
+                palette_parameter(),
+                palette_set_parameter(),
+                shade_parameter(),
+                image_format_parameter(),
+                release_parameter()],
             }),
         },
     );
 
-    let openapi_doc = OpenApiDocument {
-        openapi: "3.1.1".to_string(),
-        info: Info {
-            title: "ACDatService API".to_string(),
-            description: "API for the ACDatService".to_string(),
-            version: "0.1.0".to_string(),
-            contact: Contact {
-                name: "Contact Info".to_string(),
-                email: "petridish@gmail.com".to_string(),
-                url: "https://github.com/amoeba/acdatservice".to_string(),
-            },
+    paths.insert(
+        "/textures/:texture_id".to_string(),
+        PathItem {
+            post: None,
+            get: Some(Operation {
+                summary: "Get a texture".to_string(),
+                description: "Returns any texture as an image (PNG by default), whatever its size or pixel format, with optional scaling applied. Paletted textures are rendered with their default palette unless palette or palette_set and shade pick another. Texture IDs can be passed the same ways as icon IDs.".to_string(),
+                operation_id: "textures_get".to_string(),
+                parameters: vec![
+                    Parameter {
+                        name: "texture_id".to_string(),
+                        location: "path".to_string(),
+                        description: "Texture ID as decimal or hex. Accepts absolute or relative values.".to_string(),
+                        required: true,
+                        schema: Schema::of_type("string"),
+                    },
+                    Parameter {
+                        name: "scale".to_string(),
+                        location: "query".to_string(),
+                        description: format!("Optional integer value to scale the image by. The scaled texture can be at most {} pixels wide or tall.", texture::MAX_SCALED_SIZE),
+                        required: false,
+                        schema: Schema::ObjectSchema {
+                            schema_type: "integer".to_string(),
+                            default: Some(serde_json::json!(1)),
+                            minimum: Some(1),
+                            maximum: Some(8),
+                            format: None,
+                            min_length: None,
+                            max_length: None,
+                            read_only: None,
+                            description: None,
+                            properties: None,
+                            required: vec![],
+                        },
+                    },
+                    palette_parameter(),
+                    palette_set_parameter(),
+                    shade_parameter(),
+                    image_format_parameter(),
+                    release_parameter(),
+                ],
+            }),
         },
-        servers: vec![Server {
-            url: "https://dats.treestats.net/".to_string(),
-            description: "Main ACDatService Server".to_string(),
-        }],
-        paths,
-    };
-
-    let json = serde_json::to_string_pretty(&openapi_doc)?;
+    );
 
-    let mut response = Response::from_body(worker::ResponseBody::Body(json.into()))?;
-    response
-        .headers_mut()
-        .set("Content-Type", "application/json")?;
+    paths.insert(
+        "/models/:model_id".to_string(),
+        PathItem {
+            post: None,
+            get: Some(Operation {
+                summary: "Get a 3D model".to_string(),
+                description: "Exports a GfxObj or Setup as a 3D model. Each GfxObj's Surfaces are resolved to their SurfaceTextures and Textures, which are embedded as PNGs. The default glb format is a self-contained binary glTF, gltf embeds the same buffer as a data URI, and obj is geometry only with materials referenced by Surface ID. Models are converted from the game's Z-up coordinates to Y-up.".to_string(),
+                operation_id: "models_get".to_string(),
+                parameters: vec![
+                    Parameter {
+                        name: "model_id".to_string(),
+                        location: "path".to_string(),
+                        description: "GfxObj (0x01) or Setup (0x02) ID as decimal or hex.".to_string(),
+                        required: true,
+                        schema: Schema::of_type("string"),
+                    },
+                    Parameter {
+                        name: "format".to_string(),
+                        location: "query".to_string(),
+                        description: "Optional model format, glb (default), gltf, or obj.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("string"),
+                    },
+                    release_parameter(),
+                ],
+            }),
+        },
+    );
 
-    Ok(with_cors_headers(response))
-}
+    paths.insert(
+        "/sounds/:sound_id".to_string(),
+        PathItem {
+            post: None,
+            get: Some(Operation {
+                summary: "Get a sound".to_string(),
+                description: "Returns a Wave file as playable audio. MP3 data is passed through as audio/mpeg and everything else is wrapped in a RIFF WAV as audio/wav.".to_string(),
+                operation_id: "sounds_get".to_string(),
+                parameters: vec![
+                    Parameter {
+                        name: "sound_id".to_string(),
+                        location: "path".to_string(),
+                        description: "Wave (0x0A) ID as decimal or hex.".to_string(),
+                        required: true,
+                        schema: Schema::of_type("string"),
+                    },
+                    release_parameter(),
+                ],
+            }),
+        },
+    );
 
-pub async fn files_index(ctx: RouteContext<()>) -> Result<Response> {
-    let db = ctx.d1("DATS_DB")?;
-    let statement = db.prepare("SELECT * FROM files");
-    let query = statement.bind(&[])?;
+    paths.insert(
+        "/palettes/:palette_id".to_string(),
+        PathItem {
+            post: None,
+            get: Some(Operation {
+                summary: "Get a palette".to_string(),
+                description: format!("Returns a Palette's colors as JSON [r, g, b, a] arrays. Add a .png suffix to the ID, e.g. /palettes/0x04000001.png, to render the colors as {}px swatches, {} to a row.", palette::SWATCH_SIZE, palette::SWATCH_COLUMNS),
+                operation_id: "palettes_get".to_string(),
+                parameters: vec![
+                    Parameter {
+                        name: "palette_id".to_string(),
+                        location: "path".to_string(),
+                        description: "Palette (0x04) ID as decimal or hex, optionally followed by .png.".to_string(),
+                        required: true,
+                        schema: Schema::of_type("string"),
+                    },
+                    release_parameter(),
+                ],
+            }),
+        },
+    );
 
-    let results = query.all().await?;
-    let mut file_lines = Vec::new();
+    paths.insert(
+        "/tables".to_string(),
+        PathItem {
+            post: None,
+            get: Some(Operation {
+                summary: "List singleton tables".to_string(),
+                description: "Lists the singleton tables (0x0E000000-0x0E00FFFF) in a release as newline-delimited JSON. Tables with a JSON export have a name, e.g. SkillTable or ExperienceTable; fetch them from /files/:file_id?format=json.".to_string(),
+                operation_id: "tables_index".to_string(),
+                parameters: vec![release_parameter()],
+            }),
+        },
+    );
 
-    for result in results.results::<crate::db::File>()? {
-        let response: crate::db::FileResponse = (&result).into();
-        let json = serde_json::to_string(&response)?;
+    paths.insert(
+        "/spells".to_string(),
+        PathItem {
+            post: None,
+            get: Some(Operation {
+                summary: "List spells".to_string(),
+                description: "Lists spells from the SpellTable as newline-delimited JSON, ordered by ID. Pages hold up to limit spells; when there are more, a Link header with rel=\"next\" points at the next page. Levels come from the scarab a spell's formula starts with, so spells without one have no level.".to_string(),
+                operation_id: "spells_index".to_string(),
+                parameters: vec![
+                    Parameter {
+                        name: "name".to_string(),
+                        location: "query".to_string(),
+                        description: "Only spells whose name contains this text, ignoring case.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("string"),
+                    },
+                    Parameter {
+                        name: "school".to_string(),
+                        location: "query".to_string(),
+                        description: "Only spells of this school, by number (1-5) or name, e.g. war, life, item, creature or void.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("string"),
+                    },
+                    Parameter {
+                        name: "level".to_string(),
+                        location: "query".to_string(),
+                        description: "Only spells of this level.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("integer"),
+                    },
+                    Parameter {
+                        name: "limit".to_string(),
+                        location: "query".to_string(),
+                        description: "Spells per page, 1 to 1000. Defaults to 100.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("integer"),
+                    },
+                    Parameter {
+                        name: "after".to_string(),
+                        location: "query".to_string(),
+                        description: "Start after this spell ID. Use the Link header rather than setting it by hand.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("string"),
+                    },
+                    release_parameter(),
+                ],
+            }),
+        },
+    );
+
+    paths.insert(
+        "/spells/:spell_id".to_string(),
+        PathItem {
+            post: None,
+            get: Some(Operation {
+                summary: "Get a spell".to_string(),
+                description: "Returns one spell from the SpellTable as JSON, with its formula resolved against the SpellComponentTable and an icon_url for its icon.".to_string(),
+                operation_id: "spells_get".to_string(),
+                parameters: vec![
+                    Parameter {
+                        name: "spell_id".to_string(),
+                        location: "path".to_string(),
+                        description: "Spell ID as decimal or hex.".to_string(),
+                        required: true,
+                        schema: Schema::of_type("string"),
+                    },
+                    release_parameter(),
+                ],
+            }),
+        },
+    );
+
+    paths.insert(
+        "/strings/:table_id/:string_id".to_string(),
+        PathItem {
+            post: None,
+            get: Some(Operation {
+                summary: "Look up a string".to_string(),
+                description: "Looks up one string by ID in a StringTable (0x23), EnumMapper (0x22) or DualDidMapper (0x25). StringTable IDs are hashes of the string names; EnumMapper and DualDidMapper IDs are enum values.".to_string(),
+                operation_id: "strings_get".to_string(),
+                parameters: vec![
+                    Parameter {
+                        name: "table_id".to_string(),
+                        location: "path".to_string(),
+                        description: "StringTable, EnumMapper or DualDidMapper ID as decimal or hex.".to_string(),
+                        required: true,
+                        schema: Schema::of_type("string"),
+                    },
+                    Parameter {
+                        name: "string_id".to_string(),
+                        location: "path".to_string(),
+                        description: "String ID or enum value as decimal or hex.".to_string(),
+                        required: true,
+                        schema: Schema::of_type("string"),
+                    },
+                    database_parameter(),
+                    release_parameter(),
+                ],
+            }),
+        },
+    );
+
+    paths.insert(
+        "/clothing/:clothing_id".to_string(),
+        PathItem {
+            post: None,
+            get: Some(Operation {
+                summary: "Resolve a clothing table".to_string(),
+                description: "Resolves a ClothingTable for one wearer Setup into the parts it replaces and the SurfaceTextures it swaps. With palette_template, also returns the item's icon and the Palette picked by shade from each sub palette's PaletteSet, along with the color ranges it covers.".to_string(),
+                operation_id: "clothing_get".to_string(),
+                parameters: vec![
+                    Parameter {
+                        name: "clothing_id".to_string(),
+                        location: "path".to_string(),
+                        description: "ClothingTable (0x10) ID as decimal or hex.".to_string(),
+                        required: true,
+                        schema: Schema::of_type("string"),
+                    },
+                    Parameter {
+                        name: "setup".to_string(),
+                        location: "query".to_string(),
+                        description: "Setup ID of the wearer. Optional when the table only covers one Setup.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("string"),
+                    },
+                    Parameter {
+                        name: "palette_template".to_string(),
+                        location: "query".to_string(),
+                        description: "Optional palette template to resolve palette substitutions for.".to_string(),
+                        required: false,
+                        schema: Schema::of_type("integer"),
+                    },
+                    shade_parameter(),
+                    release_parameter(),
+                ],
+            }),
+        },
+    );
+
+    let openapi_doc = OpenApiDocument {
+        openapi: "3.1.1".to_string(),
+        info: Info {
+            title: "ACDatService API".to_string(),
+            description: "API for the ACDatService".to_string(),
+            version: "0.1.0".to_string(),
+            contact: Contact {
+                name: "Contact Info".to_string(),
+                email: "petridish@gmail.com".to_string(),
+                url: "https://github.com/amoeba/acdatservice".to_string(),
+            },
+        },
+        servers: vec![Server {
+            url: "https://dats.treestats.net/".to_string(),
+            description: "Main ACDatService Server".to_string(),
+        }],
+        paths,
+    };
+
+    let json = serde_json::to_string_pretty(&openapi_doc)?;
+
+    let mut response = Response::from_body(worker::ResponseBody::Body(json.into()))?;
+    response
+        .headers_mut()
+        .set("Content-Type", "application/json")?;
+
+    Ok(with_cors_headers(response))
+}
+
+fn release_not_found(name: Option<&str>) -> Result<Response> {
+    match name {
+        Some(name) => Response::error(format!("Release not found: {}", name), 404),
+        None => Response::error("No releases have been indexed.", 404),
+    }
+}
+
+pub async fn releases_index(ctx: RouteContext<Context>) -> Result<Response> {
+    let db = ctx.d1("DATS_DB")?;
+    let statement = db.prepare("SELECT * FROM releases ORDER BY id DESC");
+    let query = statement.bind(&[])?;
+
+    let results = query.all().await?;
+    let mut release_lines = Vec::new();
+
+    for result in results.results::<crate::db::Release>()? {
+        let json = serde_json::to_string(&result)?;
+        release_lines.push(json);
+    }
+
+    let response_text = release_lines.join("\n");
+    let response = Response::ok(response_text)?;
+    Ok(with_cors_headers(response))
+}
+
+/// Run a FileQuery against D1, returning at most `limit` files if given
+async fn query_files(
+    ctx: &RouteContext<Context>,
+    file_query: &FileQuery,
+    release_id: i64,
+    limit: Option<usize>,
+) -> Result<Vec<File>> {
+    let (sql, values) = file_query.sql(release_id, limit);
+    let values: Vec<worker::wasm_bindgen::JsValue> = values
+        .into_iter()
+        .map(|value| match value {
+            QueryValue::Number(number) => number.into(),
+            QueryValue::Text(text) => text.into(),
+        })
+        .collect();
+
+    let db = ctx.d1("DATS_DB")?;
+    let statement = db.prepare(sql);
+    let query = statement.bind(&values)?;
+
+    query.all().await?.results::<File>()
+}
+
+pub async fn files_index(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let url = req.url()?;
+    let query_params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+
+    let file_query = match FileQuery::from_params(&query_params) {
+        Ok(val) => val,
+        Err(err) => return Response::error(err, 400),
+    };
+
+    let release_name = release_param(&ctx, &query_params);
+    let release = match get_release(&ctx, release_name).await? {
+        Some(val) => val,
+        None => return release_not_found(release_name),
+    };
+
+    // One extra row tells us whether there's another page
+    let limit = file_query.limit();
+    let mut files = query_files(
+        &ctx,
+        &file_query,
+        release.release.id,
+        limit.map(|limit| limit + 1),
+    )
+    .await?;
+
+    let has_more = limit.is_some_and(|limit| files.len() > limit);
+    if let Some(limit) = limit {
+        files.truncate(limit);
+    }
+
+    let mut file_lines = Vec::new();
+    for result in &files {
+        let response: crate::db::FileResponse = result.into();
+        let json = serde_json::to_string(&response)?;
         file_lines.push(json);
     }
 
     let response_text = file_lines.join("\n");
-    let response = Response::ok(response_text)?;
+    let mut response = Response::ok(response_text)?;
+    if let (true, Some(last)) = (has_more, files.last()) {
+        response
+            .headers_mut()
+            .set("Link", &next_page_link(&url, &file_query.cursor(last)))?;
+    }
     Ok(with_cors_headers(response))
 }
 
-pub async fn icons_index(ctx: RouteContext<()>) -> Result<Response> {
+pub async fn icons_index(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let url = req.url()?;
+    let query_params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+    let release_name = release_param(&ctx, &query_params);
+    let release = match get_release(&ctx, release_name).await? {
+        Some(val) => val,
+        None => return release_not_found(release_name),
+    };
+
     let db = ctx.d1("DATS_DB")?;
-    let statement = db.prepare("SELECT * FROM files WHERE file_subtype = ?1");
+    // Icons are only rendered from the portal DAT
+    let statement = db.prepare(
+        "SELECT * FROM files WHERE release_id = ?1 AND database_type = ?2 AND file_subtype = ?3",
+    );
     // We cast to f64 to apparently work around JS
     let icon_subtype = DatFileSubtype::Icon.as_u32() as f64;
-    let query = statement.bind(&[icon_subtype.into()])?;
+    let query = statement.bind(&[
+        (release.release.id as f64).into(),
+        (DatDatabaseType::Portal.as_u32() as f64).into(),
+        icon_subtype.into(),
+    ])?;
 
     let results = query.all().await?;
     let mut icon_lines = Vec::new();
@@ -287,333 +968,2124 @@ pub async fn icons_index(ctx: RouteContext<()>) -> Result<Response> {
         icon_lines.push(json);
     }
 
-    let response_text = icon_lines.join("\n");
-    let response = Response::ok(response_text)?;
+    let response_text = icon_lines.join("\n");
+    let response = Response::ok(response_text)?;
+    Ok(with_cors_headers(response))
+}
+
+// Files are matched across releases by ID and database. Removed files are
+// reported as they were in the old release, everything else as it is in the
+// new one.
+const DIFF_QUERY: &str = "
+    SELECT new.*, 'added' AS change FROM files new
+    WHERE new.release_id = ?2 AND NOT EXISTS (
+        SELECT 1 FROM files old
+        WHERE old.release_id = ?1 AND old.id = new.id AND old.database_type = new.database_type
+    )
+    UNION ALL
+    SELECT old.*, 'removed' AS change FROM files old
+    WHERE old.release_id = ?1 AND NOT EXISTS (
+        SELECT 1 FROM files new
+        WHERE new.release_id = ?2 AND new.id = old.id AND new.database_type = old.database_type
+    )
+    UNION ALL
+    SELECT new.*, 'changed' AS change FROM files new
+    JOIN files old
+        ON old.release_id = ?1 AND old.id = new.id AND old.database_type = new.database_type
+    WHERE new.release_id = ?2
+        AND (old.file_size != new.file_size OR old.content_hash IS NOT new.content_hash)
+    ORDER BY id";
+
+pub async fn diff_get(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let url = req.url()?;
+    let query_params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+
+    // from - required
+    let from_name = match query_params.get("from") {
+        Some(val) => val.as_str(),
+        None => return Response::error("Must specify a release to diff from.", 400),
+    };
+    let from_release = match get_release(&ctx, Some(from_name)).await? {
+        Some(val) => val,
+        None => return release_not_found(Some(from_name)),
+    };
+
+    // to - defaults to the latest
+    let to_name = query_params.get("to").map(|value| value.as_str());
+    let to_release = match get_release(&ctx, to_name).await? {
+        Some(val) => val,
+        None => return release_not_found(to_name),
+    };
+
+    let db = ctx.d1("DATS_DB")?;
+    let statement = db.prepare(DIFF_QUERY);
+    let query = statement.bind(&[
+        (from_release.release.id as f64).into(),
+        (to_release.release.id as f64).into(),
+    ])?;
+
+    let results = query.all().await?;
+    let mut change_lines = Vec::new();
+
+    for result in results.results::<crate::db::FileChange>()? {
+        let response: crate::db::FileChangeResponse = (&result).into();
+        let json = serde_json::to_string(&response)?;
+        change_lines.push(json);
+    }
+
+    let response_text = change_lines.join("\n");
+    let response = Response::ok(response_text)?;
+    Ok(with_cors_headers(response))
+}
+
+// Search rows are matched back to files by release, ID and database, and
+// ordered by FTS5's bm25 rank
+const SEARCH_QUERY: &str = "
+    SELECT files.*, snippet(files_search, -1, '<mark>', '</mark>', '...', 16) AS snippet
+    FROM files_search
+    JOIN files
+        ON files.release_id = files_search.release_id
+        AND files.id = files_search.id
+        AND files.database_type = files_search.database_type
+    WHERE files_search MATCH ?1 AND files_search.release_id = ?2
+    ORDER BY rank
+    LIMIT ?3";
+
+pub async fn search_get(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let url = req.url()?;
+    let query_params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+
+    // q - required
+    let match_query = match query_params
+        .get("q")
+        .and_then(|value| search::match_query(value))
+    {
+        Some(val) => val,
+        None => return Response::error("Must specify something to search for with q.", 400),
+    };
+
+    let limit = match page_limit(&query_params) {
+        Ok(val) => val,
+        Err(err) => return Response::error(err, 400),
+    };
+
+    // release - defaults to the latest
+    let release_name = release_param(&ctx, &query_params);
+    let release = match get_release(&ctx, release_name).await? {
+        Some(val) => val,
+        None => return release_not_found(release_name),
+    };
+
+    let db = ctx.d1("DATS_DB")?;
+    let statement = db.prepare(SEARCH_QUERY);
+    let query = statement.bind(&[
+        match_query.into(),
+        (release.release.id as f64).into(),
+        (limit as f64).into(),
+    ])?;
+
+    let results = query.all().await?;
+    let mut result_lines = Vec::new();
+
+    for result in results.results::<crate::db::SearchResult>()? {
+        let response: crate::db::SearchResultResponse = (&result).into();
+        let json = serde_json::to_string(&response)?;
+        result_lines.push(json);
+    }
+
+    let response_text = result_lines.join("\n");
+    let response = Response::ok(response_text)?;
+    Ok(with_cors_headers(response))
+}
+
+/// Look up a file that may be in any of the release's databases, narrowed by
+/// `?database=`. IDs repeat across databases, so when no database is given
+/// the portal's file wins and an ID found only in several others is an
+/// error. The inner error is the message and status to respond with.
+async fn find_file(
+    ctx: &RouteContext<Context>,
+    release: &DatRelease,
+    file_id: u32,
+    query_params: &HashMap<String, String>,
+) -> Result<std::result::Result<File, (String, u16)>> {
+    let file_query = FileQuery::for_id(query_params, file_id);
+    let mut files = query_files(ctx, &file_query, release.release.id, Some(10)).await?;
+
+    let portal = DatDatabaseType::Portal.as_u32() as i64;
+    if let Some(index) = files.iter().position(|file| file.database_type == portal) {
+        return Ok(Ok(files.swap_remove(index)));
+    }
+
+    match files.len() {
+        0 => Ok(Err((
+            format!("File not found with ID {} (0x{:X})", file_id, file_id),
+            404,
+        ))),
+        1 => Ok(Ok(files.remove(0))),
+        _ => {
+            let databases: Vec<String> = files
+                .iter()
+                .map(|file| crate::db::FileResponse::from(file).database_type)
+                .collect();
+            Ok(Err((
+                format!(
+                    "File 0x{:08X} is in more than one database ({}). Choose one with ?database=",
+                    file_id,
+                    databases.join(", ")
+                ),
+                400,
+            )))
+        }
+    }
+}
+
+pub async fn files_get(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let url = req.url()?;
+    let query_params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+    let param_file_id = match ctx.param("file_id") {
+        Some(val) => val,
+        None => return Response::error("Must specify file ID.", 400),
+    };
+
+    let file_id = match parse_file_id(param_file_id) {
+        Ok(val) => val,
+        Err(err) => return Response::error(format!("Invalid file ID: {}", err), 400),
+    };
+
+    let release_name = release_param(&ctx, &query_params);
+    let release = match get_release(&ctx, release_name).await? {
+        Some(val) => val,
+        None => return release_not_found(release_name),
+    };
+
+    // database - IDs repeat across DATs, so this picks which one's file
+    let file = match find_file(&ctx, &release, file_id, &query_params).await? {
+        Ok(val) => val,
+        Err((message, status)) => return Response::error(message, status),
+    };
+
+    // format - the raw bytes unless a structured format is asked for
+    let format = match query_params.get("format") {
+        None => None,
+        Some(name) => match DataFormat::from_name(name) {
+            Some(val) => Some(val),
+            None => {
+                let names: Vec<&str> = DataFormat::ALL.iter().map(|f| f.name()).collect();
+                return Response::error(
+                    format!(
+                        "Unsupported format: {}. Use one of {}, or leave it out for the raw file.",
+                        name,
+                        names.join(", ")
+                    ),
+                    400,
+                );
+            }
+        },
+    };
+
+    // fields and filter - trim the export down before it's sent
+    let projection = match Projection::from_params(&query_params) {
+        Ok(val) => val,
+        Err(err) => return Response::error(err, 400),
+    };
+    // Projecting goes through JSON values, which would turn integer map keys
+    // into strings in the binary formats
+    if format != Some(DataFormat::Json) && !projection.is_empty() {
+        return Response::error("fields and filter require format=json", 400);
+    }
+
+    // Unsupported types are turned away before the conditional check, since
+    // a matching tag can't make them servable, and before reading from R2
+    let file_type = file.resolved_file_type();
+    let exporter = match format {
+        Some(format) => match exporters::find(&file_type, file_id) {
+            Some(val) => Some((val, format)),
+            None => {
+                return Response::error(
+                    format!(
+                        "{} export is not supported for file type {}. Supported types: {}",
+                        format.name(),
+                        file_type,
+                        exporters::names().join(", ")
+                    ),
+                    415,
+                )
+            }
+        },
+        None => None,
+    };
+
+    // Each export format is a different entity than the raw bytes so it gets
+    // its own tag, as does every projection of it
+    let etag = file
+        .content_hash
+        .as_ref()
+        .map(|hash| match (format, projection.tag()) {
+            (Some(format), Some(tag)) => quote_etag(&format!("{}-{}-{}", hash, format.name(), tag)),
+            (Some(format), None) => quote_etag(&format!("{}-{}", hash, format.name())),
+            (None, _) => quote_etag(hash),
+        });
+    if let Some(etag) = &etag {
+        if etag_matches(req.headers().get("If-None-Match")?.as_deref(), etag) {
+            return not_modified(etag);
+        }
+    }
+
+    let (file_data, read_count) = get_buf_for_file(&ctx, &release, &file).await?;
+
+    if let Some((exporter, format)) = exporter {
+        let exported = exporter.export(file_id, &file_data);
+        let buf = file_export(file_id, &file_type, exported, format, &projection)?;
+
+        let mut response = Response::from_body(worker::ResponseBody::Body(buf))?;
+        response
+            .headers_mut()
+            .set("Content-Type", format.content_type())?;
+        response
+            .headers_mut()
+            .set("X-R2-Read-Count", &read_count.to_string())?;
+        if let Some(etag) = &etag {
+            response.headers_mut().set("ETag", etag)?;
+        }
+        return Ok(with_cors_headers(response));
+    }
+
+    let mut response = Response::from_bytes(file_data)?;
+    response
+        .headers_mut()
+        .set("Content-Type", "application/octet-stream")?;
+    response
+        .headers_mut()
+        .set("X-R2-Read-Count", &read_count.to_string())?;
+    if let Some(etag) = &etag {
+        response.headers_mut().set("ETag", etag)?;
+    }
+
+    Ok(with_cors_headers(response))
+}
+
+/// Serialize the result of parsing a DAT file in one of the export formats,
+/// applying any projection
+fn file_export(
+    file_id: u32,
+    file_type: &DatFileType,
+    parsed: std::result::Result<Box<dyn Exported>, String>,
+    format: DataFormat,
+    projection: &Projection,
+) -> Result<Vec<u8>> {
+    let exported = parsed.map_err(|err| {
+        worker::Error::RustError(format!(
+            "Failed to parse file {} (0x{:X}) as {}: {}",
+            file_id, file_id, file_type, err
+        ))
+    })?;
+
+    let encoded = if projection.is_empty() {
+        exported.encode(format)
+    } else {
+        exported.to_json().and_then(|mut value| {
+            projection.apply(&mut value);
+            format.encode(&value)
+        })
+    };
+
+    encoded.map_err(|err| {
+        worker::Error::RustError(format!(
+            "Failed to serialize file {} (0x{:X}) as {}: {}",
+            file_id,
+            file_id,
+            format.name(),
+            err
+        ))
+    })
+}
+
+pub async fn icons_get(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let url = req.url()?;
+    let query_params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+
+    // :icon_id
+    let param_id = match ctx.param("id") {
+        Some(val) => val,
+        None => return Response::error("Must specify icon ID.", 400),
+    };
+
+    let mut spec = match IconSpec::from_params(param_id, &query_params) {
+        Ok(val) => val,
+        Err(err) => return Response::error(err, 400),
+    };
+
+    // format - ?format wins over Accept
+    let param_format = query_params.get("format").map(|value| value.as_str());
+    let accept = req.headers().get("Accept")?;
+    let format = match OutputFormat::negotiate(param_format, accept.as_deref()) {
+        Ok(val) => val,
+        Err(err) => return Response::error(err, 400),
+    };
+    let file_name = format!("0x{:08X}", spec.icon_id);
+
+    // A random background or effect should be re-rolled on every request
+    let is_random = ["background", "ui_effect"].iter().any(|name| {
+        query_params
+            .get(*name)
+            .is_some_and(|value| value.eq_ignore_ascii_case("random"))
+    });
+
+    // release - defaults to the latest
+    let release_name = release_param(&ctx, &query_params);
+    let release = match get_release(&ctx, release_name).await? {
+        Some(val) => val,
+        None => return release_not_found(release_name),
+    };
+
+    let cache_control = if is_random {
+        "no-store"
+    } else if release_name.is_some() {
+        ICON_CACHE_CONTROL
+    } else {
+        LATEST_ICON_CACHE_CONTROL
+    };
+    let if_none_match = req.headers().get("If-None-Match")?;
+
+    // Check the edge cache before doing any more D1 or R2 work
+    let cache = Cache::default();
+    let cache_key = format!(
+        "{}{}&format={}",
+        url.origin().ascii_serialization(),
+        spec.cache_key(release.release.id),
+        format.name()
+    );
+    if let Some(mut cached) = cache.get(cache_key.as_str(), false).await? {
+        let etag = cached.headers().get("ETag")?;
+        if let Some(etag) = &etag {
+            if etag_matches(if_none_match.as_deref(), etag) {
+                return not_modified(etag);
+            }
+        }
+
+        let mut response = generate_image(cached.bytes().await?, format, &file_name)?;
+        response.headers_mut().set("Cache-Control", cache_control)?;
+        response.headers_mut().set("X-Cache", "HIT")?;
+        response.headers_mut().set("X-R2-Read-Count", "0")?;
+        if let Some(etag) = &etag {
+            response.headers_mut().set("ETag", etag)?;
+        }
+        if param_format.is_none() {
+            response.headers_mut().set("Vary", "Accept")?;
+        }
+        return Ok(with_cors_headers(response));
+    }
+
+    let mut total_read_count: usize = 0;
+    if let Err(err) = resolve_palette_sets(
+        &ctx,
+        &release,
+        std::iter::once(&mut spec.palette),
+        &mut total_read_count,
+    )
+    .await?
+    {
+        return Response::error(err, 400);
+    }
+
+    // Look up every input file against D1 before reading anything else from
+    // R2 so unchanged icons can be answered with a 304
+    let files =
+        get_files_by_ids(&ctx, &release, &DatDatabaseType::Portal, &spec.file_ids()).await?;
+    if let Some(file_id) = spec
+        .file_ids()
+        .into_iter()
+        .find(|id| !files.contains_key(&portal_key(*id)))
+    {
+        return Response::error(format!("Failed to get DAT file for ID {:X}", file_id), 400);
+    }
+
+    // PNG keeps the bare content hash so tags from before other formats were
+    // supported stay valid
+    let etag = spec.content_hash(&files).map(|hash| match format {
+        OutputFormat::Png => quote_etag(&hash),
+        _ => quote_etag(&format!("{}-{}", hash, format.name())),
+    });
+    if let Some(etag) = &etag {
+        if etag_matches(if_none_match.as_deref(), etag) {
+            return not_modified(etag);
+        }
+    }
+
+    let (buf, render_cache_hit) = match render_icon(
+        &ctx,
+        &release,
+        &spec,
+        &files,
+        &mut HashMap::new(),
+        &mut total_read_count,
+    )
+    .await?
+    {
+        Ok(val) => val,
+        Err(err) => return Response::error(err, 400),
+    };
+    let render_cache_status = if render_cache_hit { "HIT" } else { "MISS" };
+
+    let buf = match format.transcode(buf) {
+        Ok(val) => val,
+        Err(err) => return Response::error(err, 400),
+    };
+
+    let mut response = generate_image(buf, format, &file_name)?;
+    response
+        .headers_mut()
+        .set("X-Render-Cache", render_cache_status)?;
+    if let Some(etag) = &etag {
+        response.headers_mut().set("ETag", etag)?;
+    }
+
+    // The cached copy always gets the long-lived header since its key pins
+    // the release
+    response
+        .headers_mut()
+        .set("Cache-Control", ICON_CACHE_CONTROL)?;
+    let cached = response.cloned()?;
+    // Written after responding so a MISS isn't held up by the cache
+    ctx.data.wait_until(async move {
+        if let Err(err) = Cache::default().put(cache_key.as_str(), cached).await {
+            console_error!("Failed to cache icon {}: {}", cache_key, err);
+        }
+    });
+
+    // Vary is left off the cached copy since its key already pins the format
+    response.headers_mut().set("Cache-Control", cache_control)?;
+    response.headers_mut().set("X-Cache", "MISS")?;
+    response
+        .headers_mut()
+        .set("X-R2-Read-Count", &total_read_count.to_string())?;
+    if param_format.is_none() {
+        response.headers_mut().set("Vary", "Accept")?;
+    }
+    Ok(with_cors_headers(response))
+}
+
+/// Swap every PaletteSet choice for the Palette its shade picks. Each set is
+/// read once however many choices use it. The inner error is a message about
+/// the set rather than a failure to talk to D1 or R2.
+async fn resolve_palette_sets<'a>(
+    ctx: &RouteContext<Context>,
+    release: &DatRelease,
+    choices: impl IntoIterator<Item = &'a mut Option<PaletteChoice>>,
+    read_count: &mut usize,
+) -> Result<std::result::Result<(), String>> {
+    let mut sets: HashMap<u32, Vec<u32>> = HashMap::new();
+
+    for choice in choices {
+        let (set_id, shade) = match *choice {
+            Some(PaletteChoice::PaletteSet { id, shade }) => (id, shade),
+            _ => continue,
+        };
+
+        if !sets.contains_key(&set_id) {
+            let buf = match read_file_by_id(ctx, release, set_id, read_count).await? {
+                Some((_, buf)) => buf,
+                None => return Ok(Err(format!("PaletteSet 0x{:08X} not found", set_id))),
+            };
+            match palette::parse_palette_set(&buf) {
+                Ok(val) => sets.insert(set_id, val),
+                Err(err) => return Ok(Err(err)),
+            };
+        }
+
+        match palette::palette_from_set(&sets[&set_id], shade) {
+            Some(palette_id) => *choice = Some(PaletteChoice::Palette(palette_id)),
+            None => return Ok(Err(format!("PaletteSet 0x{:08X} is empty", set_id))),
+        }
+    }
+
+    Ok(Ok(()))
+}
+
+/// Read every file an icon is built from that isn't already in `bufs`
+async fn read_icon_files(
+    ctx: &RouteContext<Context>,
+    release: &DatRelease,
+    spec: &IconSpec,
+    files: &HashMap<FileKey, File>,
+    bufs: &mut HashMap<u32, Vec<u8>>,
+    read_count: &mut usize,
+) -> Result<()> {
+    for file_id in spec.file_ids() {
+        if bufs.contains_key(&file_id) {
+            continue;
+        }
+
+        let file = files.get(&portal_key(file_id)).ok_or_else(|| {
+            worker::Error::RustError(format!("Failed to get DAT file for ID {:X}", file_id))
+        })?;
+        let (buf, count) = get_buf_for_file(ctx, release, file).await?;
+        *read_count += count;
+        bufs.insert(file_id, buf);
+    }
+
+    Ok(())
+}
+
+/// Render an icon, reusing a persisted render of the same inputs when one
+/// exists. Input buffers are read into `bufs` on demand so callers rendering
+/// several icons read each file at most once. Returns the PNG and whether
+/// it came from the render cache.
+async fn render_icon(
+    ctx: &RouteContext<Context>,
+    release: &DatRelease,
+    spec: &IconSpec,
+    files: &HashMap<FileKey, File>,
+    bufs: &mut HashMap<u32, Vec<u8>>,
+    read_count: &mut usize,
+) -> Result<std::result::Result<(Vec<u8>, bool), String>> {
+    // The probe isn't a DAT read so it's left out of the read count; hits
+    // show up in X-Render-Cache instead
+    let render_key = spec.render_key(files);
+    if let Some(key) = &render_key {
+        if let Some(buf) = get_render(ctx, key).await? {
+            return Ok(Ok((buf, true)));
+        }
+    }
+
+    read_icon_files(ctx, release, spec, files, bufs, read_count).await?;
+
+    let icon = match spec.to_icon(bufs) {
+        Ok(val) => val,
+        Err(err) => return Ok(Err(err)),
+    };
+    let buf = icon.export()?;
+    if let Some(key) = render_key {
+        // Written after responding so a miss isn't held up by R2
+        let bucket = ctx.bucket("DATS_BUCKET")?;
+        let png = buf.clone();
+        ctx.data.wait_until(async move {
+            if let Err(err) = put_render(&bucket, &key, png, "image/png").await {
+                console_error!("Failed to persist render {}: {}", key, err);
+            }
+        });
+    }
+
+    Ok(Ok((buf, false)))
+}
+
+pub async fn icons_batch_post(mut req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let url = req.url()?;
+    let query_params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+
+    // format - json (default) or zip
+    let want_zip = match query_params.get("format").map(|value| value.as_str()) {
+        None | Some("json") => false,
+        Some("zip") => true,
+        Some(other) => {
+            return Response::error(
+                format!("Unsupported format: {}. Use json or zip.", other),
+                400,
+            )
+        }
+    };
+
+    let entries: Vec<serde_json::Value> = match req.json().await {
+        Ok(val) => val,
+        Err(err) => {
+            return Response::error(
+                format!("Request body must be a JSON list of icon specs: {}", err),
+                400,
+            )
+        }
+    };
+
+    if entries.is_empty() || entries.len() > MAX_BATCH_ICONS {
+        return Response::error(
+            format!("Specify between 1 and {} icons", MAX_BATCH_ICONS),
+            400,
+        );
+    }
+
+    let mut batch = Vec::new();
+    let mut names = HashSet::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let icon = match BatchIcon::from_json(entry) {
+            Ok(val) => val,
+            Err(err) => {
+                return Response::error(format!("Invalid icon at index {}: {}", index, err), 400)
+            }
+        };
+        // Icons are keyed by name in both the JSON and ZIP responses
+        if !names.insert(icon.name.clone()) {
+            return Response::error(
+                format!(
+                    "Invalid icon at index {}: duplicate name {}. Give each icon a unique name.",
+                    index, icon.name
+                ),
+                400,
+            );
+        }
+        batch.push(icon);
+    }
+
+    // release - defaults to the latest
+    let release_name = release_param(&ctx, &query_params);
+    let release = match get_release(&ctx, release_name).await? {
+        Some(val) => val,
+        None => return release_not_found(release_name),
+    };
+
+    let mut total_read_count: usize = 0;
+    let choices = batch.iter_mut().map(|icon| &mut icon.spec.palette);
+    if let Err(err) = resolve_palette_sets(&ctx, &release, choices, &mut total_read_count).await? {
+        return Response::error(err, 400);
+    }
+
+    // One D1 lookup for every file used by any icon in the batch
+    let mut file_ids: Vec<u32> = batch.iter().flat_map(|icon| icon.spec.file_ids()).collect();
+    file_ids.sort_unstable();
+    file_ids.dedup();
+
+    let files = get_files_by_ids(&ctx, &release, &DatDatabaseType::Portal, &file_ids).await?;
+    if let Some(file_id) = file_ids
+        .iter()
+        .find(|id| !files.contains_key(&portal_key(**id)))
+    {
+        return Response::error(format!("Failed to get DAT file for ID {:X}", file_id), 400);
+    }
+
+    // Shared textures like the default UI effect are only read once
+    let mut bufs = HashMap::new();
+    let mut rendered = Vec::new();
+    for icon in &batch {
+        let (buf, _) = match render_icon(
+            &ctx,
+            &release,
+            &icon.spec,
+            &files,
+            &mut bufs,
+            &mut total_read_count,
+        )
+        .await?
+        {
+            Ok(val) => val,
+            Err(err) => {
+                return Response::error(
+                    format!("Failed to render icon {}: {}", icon.name, err),
+                    400,
+                )
+            }
+        };
+        rendered.push((icon.name.as_str(), buf));
+    }
+
+    let mut response = if want_zip {
+        let zip_error = |err: zip::result::ZipError| {
+            worker::Error::RustError(format!("Failed to write ZIP: {}", err))
+        };
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        for (name, buf) in &rendered {
+            writer
+                .start_file(format!("{}.png", name), options)
+                .map_err(zip_error)?;
+            writer.write_all(buf).map_err(|err| zip_error(err.into()))?;
+        }
+        let archive = writer.finish().map_err(zip_error)?.into_inner();
+
+        let mut response = Response::from_bytes(archive)?;
+        response
+            .headers_mut()
+            .set("Content-Type", "application/zip")?;
+        response
+            .headers_mut()
+            .set("Content-Disposition", "attachment; filename=\"icons.zip\"")?;
+        response
+    } else {
+        let data_uris: serde_json::Map<String, serde_json::Value> = rendered
+            .iter()
+            .map(|(name, buf)| {
+                (
+                    name.to_string(),
+                    format!("data:image/png;base64,{}", BASE64_STANDARD.encode(buf)).into(),
+                )
+            })
+            .collect();
+
+        let json = serde_json::to_string(&data_uris)?;
+        let mut response = Response::from_body(worker::ResponseBody::Body(json.into()))?;
+        response
+            .headers_mut()
+            .set("Content-Type", "application/json")?;
+        response
+    };
+
+    response
+        .headers_mut()
+        .set("X-R2-Read-Count", &total_read_count.to_string())?;
+    Ok(with_cors_headers(response))
+}
+
+pub async fn icons_atlas_get(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let url = req.url()?;
+    let query_params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+
+    // format - png (default) or json
+    let want_json = match query_params.get("format").map(|value| value.as_str()) {
+        None | Some("png") => false,
+        Some("json") => true,
+        Some(other) => {
+            return Response::error(
+                format!("Unsupported format: {}. Use png or json.", other),
+                400,
+            )
+        }
+    };
+
+    // release - defaults to the latest
+    let release_name = release_param(&ctx, &query_params);
+    let release = match get_release(&ctx, release_name).await? {
+        Some(val) => val,
+        None => return release_not_found(release_name),
+    };
+
+    // ids - comma-separated, or every icon between min_id and max_id.
+    // Repeats (including 0x6957 and 26967) are dropped since the manifest is
+    // keyed by ID.
+    let icon_ids: Vec<u32> = if let Some(ids) = query_params.get("ids") {
+        let mut icon_ids = Vec::new();
+        let mut seen = HashSet::new();
+        for id in ids.split(',').filter(|id| !id.is_empty()) {
+            match parse_decimal_or_hex_string(id.trim()) {
+                Ok(val) => {
+                    if seen.insert(val as u32) {
+                        icon_ids.push(val as u32);
+                    }
+                }
+                Err(err) => {
+                    return Response::error(
+                        format!("Failed to parse query parameter: ids. Error: {}", err),
+                        400,
+                    )
+                }
+            }
+        }
+        icon_ids
+    } else if let (Some(min_id), Some(max_id)) =
+        (query_params.get("min_id"), query_params.get("max_id"))
+    {
+        let (min_id, max_id) = match (
+            parse_decimal_or_hex_string(min_id),
+            parse_decimal_or_hex_string(max_id),
+        ) {
+            (Ok(min_id), Ok(max_id)) => (min_id as u32, max_id as u32),
+            _ => return Response::error("Failed to parse query parameters: min_id, max_id", 400),
+        };
+
+        let db = ctx.d1("DATS_DB")?;
+        let statement = db.prepare(
+            "SELECT * FROM files WHERE release_id = ?1 AND database_type = ?2 AND file_subtype = ?3 AND id BETWEEN ?4 AND ?5 ORDER BY id LIMIT ?6",
+        );
+        let query = statement.bind(&[
+            (release.release.id as f64).into(),
+            (DatDatabaseType::Portal.as_u32() as f64).into(),
+            (DatFileSubtype::Icon.as_u32() as f64).into(),
+            (min_id as f64).into(),
+            (max_id as f64).into(),
+            ((MAX_ATLAS_ICONS + 1) as f64).into(),
+        ])?;
+        query
+            .all()
+            .await?
+            .results::<File>()?
+            .iter()
+            .map(|file| file.id as u32)
+            .collect()
+    } else {
+        return Response::error("Must specify ids or min_id and max_id.", 400);
+    };
+
+    if icon_ids.is_empty() || icon_ids.len() > MAX_ATLAS_ICONS {
+        return Response::error(
+            format!("An atlas must have between 1 and {} icons", MAX_ATLAS_ICONS),
+            400,
+        );
+    }
+
+    // columns - defaults to roughly square
+    let columns = match query_params
+        .get("columns")
+        .map(|value| value.parse::<u32>())
+    {
+        None => atlas::default_columns(icon_ids.len()),
+        Some(Ok(val)) if (1..=MAX_ATLAS_COLUMNS).contains(&val) => val,
+        Some(_) => {
+            return Response::error(
+                format!("Choose a columns value between 1 and {}", MAX_ATLAS_COLUMNS),
+                400,
+            )
+        }
+    };
+
+    // Every icon shares the remaining parameters (scale, background, etc.)
+    let mut specs = Vec::new();
+    for icon_id in &icon_ids {
+        match IconSpec::from_params(&format!("0x{:08X}", icon_id), &query_params) {
+            Ok(val) => specs.push(val),
+            Err(err) => return Response::error(err, 400),
+        }
+    }
+
+    let (width, height, tiles) = atlas::layout(specs.len(), columns, 32 * specs[0].scale);
+    if width as u64 * height as u64 > MAX_ATLAS_PIXELS {
+        return Response::error(
+            format!(
+                "An atlas of {}x{} pixels is too large. Use fewer icons, a smaller scale, or a different number of columns to stay within {} pixels.",
+                width, height, MAX_ATLAS_PIXELS
+            ),
+            400,
+        );
+    }
+
+    if want_json {
+        let manifest: serde_json::Map<String, serde_json::Value> = icon_ids
+            .iter()
+            .zip(&tiles)
+            .map(|(icon_id, tile)| Ok((format!("0x{:08X}", icon_id), serde_json::to_value(tile)?)))
+            .collect::<serde_json::Result<_>>()?;
+
+        let json = serde_json::to_string(&manifest)?;
+        let mut response = Response::from_body(worker::ResponseBody::Body(json.into()))?;
+        response
+            .headers_mut()
+            .set("Content-Type", "application/json")?;
+        return Ok(with_cors_headers(response));
+    }
+
+    let mut total_read_count: usize = 0;
+    let choices = specs.iter_mut().map(|spec| &mut spec.palette);
+    if let Err(err) = resolve_palette_sets(&ctx, &release, choices, &mut total_read_count).await? {
+        return Response::error(err, 400);
+    }
+
+    let mut file_ids: Vec<u32> = specs.iter().flat_map(|spec| spec.file_ids()).collect();
+    file_ids.sort_unstable();
+    file_ids.dedup();
+
+    let files = get_files_by_ids(&ctx, &release, &DatDatabaseType::Portal, &file_ids).await?;
+    if let Some(file_id) = file_ids
+        .iter()
+        .find(|id| !files.contains_key(&portal_key(**id)))
+    {
+        return Response::error(format!("Failed to get DAT file for ID {:X}", file_id), 400);
+    }
+
+    let mut bufs = HashMap::new();
+    for spec in &specs {
+        read_icon_files(
+            &ctx,
+            &release,
+            spec,
+            &files,
+            &mut bufs,
+            &mut total_read_count,
+        )
+        .await?;
+    }
+
+    // Tiles are pasted as they're rendered rather than going through the
+    // render cache, which would cost a read and a write per tile
+    let mut canvas = Atlas::new(width, height);
+    for (spec, tile) in specs.iter().zip(&tiles) {
+        let icon = match spec.to_icon(&bufs) {
+            Ok(val) => val,
+            Err(err) => return Response::error(err, 400),
+        };
+        canvas
+            .paste_png(tile, &icon.export()?)
+            .map_err(|err| worker::Error::RustError(format!("Failed to compose atlas: {}", err)))?;
+    }
+
+    let buf = canvas
+        .to_png()
+        .map_err(|err| worker::Error::RustError(format!("Failed to compose atlas: {}", err)))?;
+
+    let mut response = generate_image(buf, OutputFormat::Png, "atlas")?;
+    response
+        .headers_mut()
+        .set("X-R2-Read-Count", &total_read_count.to_string())?;
+    Ok(with_cors_headers(response))
+}
+
+/// Look up a portal file and read its contents, adding to the R2 read count.
+/// None if the release's portal DAT has no file with that ID.
+async fn read_file_by_id(
+    ctx: &RouteContext<Context>,
+    release: &DatRelease,
+    file_id: u32,
+    read_count: &mut usize,
+) -> Result<Option<(File, Vec<u8>)>> {
+    let file = match get_file_by_id(ctx, release, &DatDatabaseType::Portal, file_id).await? {
+        Some(val) => val,
+        None => return Ok(None),
+    };
+
+    let (buf, count) = get_buf_for_file(ctx, release, &file).await?;
+    *read_count += count;
+
+    Ok(Some((file, buf)))
+}
+
+pub async fn textures_get(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let url = req.url()?;
+    let query_params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+
+    // :id
+    let param_id = match ctx.param("id") {
+        Some(val) => val,
+        None => return Response::error("Must specify texture ID.", 400),
+    };
+
+    let texture_id = match parse_decimal_or_hex_string(param_id) {
+        Ok(val) => val as u32,
+        Err(err) => return Response::error(err.to_string(), 400),
+    };
+
+    // scale
+    let param_scale = match query_params
+        .get("scale")
+        .map(|value| value.parse::<u32>())
+        .unwrap_or_else(|| Ok(1))
+    {
+        Ok(val) => val,
+        Err(err) => {
+            return Response::error(
+                format!("Failed to parse query parameter: scale.{}", err),
+                400,
+            );
+        }
+    };
+
+    // Error for unreasonable scale values
+    if !(1..=8).contains(&param_scale) {
+        return Response::error("Choose a scale value between 1 and 8", 400);
+    }
+
+    // format - ?format wins over Accept
+    let param_format = query_params.get("format").map(|value| value.as_str());
+    let accept = req.headers().get("Accept")?;
+    let format = match OutputFormat::negotiate(param_format, accept.as_deref()) {
+        Ok(val) => val,
+        Err(err) => return Response::error(err, 400),
+    };
+
+    // palette, or palette_set and shade
+    let mut palette_choice = match PaletteChoice::from_params(&query_params) {
+        Ok(val) => val,
+        Err(err) => return Response::error(err, 400),
+    };
+
+    // release - defaults to the latest
+    let release_name = release_param(&ctx, &query_params);
+    let release = match get_release(&ctx, release_name).await? {
+        Some(val) => val,
+        None => return release_not_found(release_name),
+    };
+
+    let mut total_read_count: usize = 0;
+    let (file, buf) =
+        match read_file_by_id(&ctx, &release, texture_id, &mut total_read_count).await? {
+            Some(val) => val,
+            None => {
+                return Response::error(
+                    format!(
+                        "Texture not found with ID {} (0x{:X})",
+                        texture_id, texture_id
+                    ),
+                    404,
+                )
+            }
+        };
+
+    let file_type = file.resolved_file_type();
+    if file_type != DatFileType::Texture {
+        return Response::error(
+            format!(
+                "File 0x{:08X} is a {}, not a Texture",
+                texture_id, file_type
+            ),
+            400,
+        );
+    }
+
+    let mut buf_reader = Cursor::new(buf);
+    let texture_file: DatFile<Texture> = match DatFile::read(&mut buf_reader) {
+        Ok(val) => val,
+        Err(_) => return Response::error("Failed to parse texture file", 400),
+    };
+    let texture = texture_file.inner;
+
+    let (width, height) = (texture.width as u32, texture.height as u32);
+    if width.max(height).saturating_mul(param_scale) > texture::MAX_SCALED_SIZE {
+        return Response::error(
+            format!(
+                "A {}x{} texture at scale {} is larger than {} pixels. Choose a smaller scale.",
+                width,
+                height,
+                param_scale,
+                texture::MAX_SCALED_SIZE
+            ),
+            400,
+        );
+    }
+
+    // Paletted textures are decoded through the requested palette, falling
+    // back to their default one
+    let palette = if texture::is_paletted(texture.format as u32) {
+        if let Err(err) = resolve_palette_sets(
+            &ctx,
+            &release,
+            std::iter::once(&mut palette_choice),
+            &mut total_read_count,
+        )
+        .await?
+        {
+            return Response::error(err, 400);
+        }
+        let palette_id = match palette_choice
+            .map(|choice| choice.file_id())
+            .or(texture.default_palette_id)
+        {
+            Some(val) => val,
+            None => return Response::error("Paletted texture has no default palette", 400),
+        };
+        let (_, palette_buf) =
+            match read_file_by_id(&ctx, &release, palette_id, &mut total_read_count).await? {
+                Some(val) => val,
+                None => {
+                    return Response::error(
+                        format!("Palette not found with ID 0x{:08X}", palette_id),
+                        400,
+                    )
+                }
+            };
+        match texture::parse_palette(&palette_buf) {
+            Ok(val) => Some(val),
+            Err(err) => {
+                return Response::error(
+                    format!("Failed to parse palette 0x{:08X}: {}", palette_id, err),
+                    400,
+                )
+            }
+        }
+    } else {
+        None
+    };
+
+    let image = match texture::decode_texture(&texture, palette.as_deref()) {
+        Ok(val) => val,
+        Err(err) => return Response::error(format!("Failed to decode texture: {}", err), 400),
+    };
+    let buf = match format.encode(&texture::scale(&image, param_scale)) {
+        Ok(val) => val,
+        Err(err) => return Response::error(err, 400),
+    };
+
+    let mut response = generate_image(buf, format, &format!("0x{:08X}", texture_id))?;
+    response
+        .headers_mut()
+        .set("X-R2-Read-Count", &total_read_count.to_string())?;
+    if param_format.is_none() {
+        response.headers_mut().set("Vary", "Accept")?;
+    }
+    Ok(with_cors_headers(response))
+}
+
+/// Look up several portal files and read the contents of each one that
+/// exists, adding to the R2 read count
+async fn read_files_by_ids(
+    ctx: &RouteContext<Context>,
+    release: &DatRelease,
+    file_ids: &[u32],
+    read_count: &mut usize,
+) -> Result<HashMap<u32, Vec<u8>>> {
+    let files = get_files_by_ids(ctx, release, &DatDatabaseType::Portal, file_ids).await?;
+    let mut bufs = HashMap::new();
+
+    for ((_, file_id), file) in files {
+        let (buf, count) = get_buf_for_file(ctx, release, &file).await?;
+        *read_count += count;
+        bufs.insert(file_id, buf);
+    }
+
+    Ok(bufs)
+}
+
+pub async fn models_get(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let url = req.url()?;
+    let query_params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+
+    // :id
+    let param_id = match ctx.param("id") {
+        Some(val) => val,
+        None => return Response::error("Must specify model ID.", 400),
+    };
+
+    let model_id = match parse_file_id(param_id) {
+        Ok(val) => val,
+        Err(err) => return Response::error(format!("Invalid model ID: {}", err), 400),
+    };
+
+    // format - glb (default), gltf, or obj
+    let format = query_params
+        .get("format")
+        .map(|value| value.as_str())
+        .unwrap_or("glb");
+    if !model::MODEL_FORMATS.contains(&format) {
+        return Response::error(
+            format!(
+                "Unsupported format: {}. Use one of {}.",
+                format,
+                model::MODEL_FORMATS.join(", ")
+            ),
+            400,
+        );
+    }
+
+    // release - defaults to the latest
+    let release_name = release_param(&ctx, &query_params);
+    let release = match get_release(&ctx, release_name).await? {
+        Some(val) => val,
+        None => return release_not_found(release_name),
+    };
+
+    let mut total_read_count: usize = 0;
+    let (file, buf) = match read_file_by_id(&ctx, &release, model_id, &mut total_read_count).await?
+    {
+        Some(val) => val,
+        None => {
+            return Response::error(
+                format!("Model not found with ID {} (0x{:X})", model_id, model_id),
+                404,
+            )
+        }
+    };
+
+    // OBJ can't embed textures so don't bother loading them
+    let with_textures = format != "obj";
+    let model = match load_model(
+        &ctx,
+        &release,
+        &file,
+        buf,
+        with_textures,
+        &mut total_read_count,
+    )
+    .await?
+    {
+        Ok(val) => val,
+        Err(err) => return Response::error(err, 400),
+    };
+
+    let (buf, content_type) = match format {
+        "gltf" => (model.to_gltf()?, "model/gltf+json"),
+        "obj" => (model.to_obj().into_bytes(), "model/obj"),
+        _ => (model.to_glb()?, "model/gltf-binary"),
+    };
+
+    let mut response = Response::from_body(worker::ResponseBody::Body(buf))?;
+    response.headers_mut().set("Content-Type", content_type)?;
+    response.headers_mut().set(
+        "Content-Disposition",
+        &format!("inline; filename=\"0x{:08X}.{}\"", model_id, format),
+    )?;
+    response
+        .headers_mut()
+        .set("X-R2-Read-Count", &total_read_count.to_string())?;
+    Ok(with_cors_headers(response))
+}
+
+/// Resolve a GfxObj or Setup into a Model, following each GfxObj's Surfaces
+/// to SurfaceTextures to Textures (and their palettes) through the index. The
+/// inner error is a message about the model's data rather than a failure to
+/// talk to D1 or R2.
+async fn load_model(
+    ctx: &RouteContext<Context>,
+    release: &DatRelease,
+    file: &File,
+    buf: Vec<u8>,
+    with_textures: bool,
+    read_count: &mut usize,
+) -> Result<std::result::Result<Model, String>> {
+    let model_id = file.id as u32;
+
+    // Parts as (GfxObj ID, translation, rotation, scale)
+    let mut gfx_obj_bufs = HashMap::new();
+    let parts = match file.resolved_file_type() {
+        DatFileType::GfxObj => {
+            gfx_obj_bufs.insert(model_id, buf);
+            vec![(model_id, [0.0; 3], [0.0, 0.0, 0.0, 1.0], [1.0; 3])]
+        }
+        DatFileType::Setup => match DatFile::<Setup>::read(&mut Cursor::new(buf)) {
+            Ok(val) => model::setup_parts(&val.inner),
+            Err(err) => return Ok(Err(format!("Failed to parse Setup: {}", err))),
+        },
+        other => {
+            return Ok(Err(format!(
+                "File 0x{:08X} is a {}, not a GfxObj or Setup",
+                model_id, other
+            )))
+        }
+    };
+
+    // GfxObjs
+    let mut gfx_obj_ids: Vec<u32> = parts.iter().map(|part| part.0).collect();
+    gfx_obj_ids.sort();
+    gfx_obj_ids.dedup();
+    let missing: Vec<u32> = gfx_obj_ids
+        .iter()
+        .copied()
+        .filter(|id| !gfx_obj_bufs.contains_key(id))
+        .collect();
+    gfx_obj_bufs.extend(read_files_by_ids(ctx, release, &missing, read_count).await?);
+
+    let mut gfx_objs = HashMap::new();
+    for gfx_obj_id in &gfx_obj_ids {
+        let buf = match gfx_obj_bufs.remove(gfx_obj_id) {
+            Some(val) => val,
+            None => return Ok(Err(format!("GfxObj 0x{:08X} not found", gfx_obj_id))),
+        };
+        match DatFile::<GfxObj>::read(&mut Cursor::new(buf)) {
+            Ok(val) => gfx_objs.insert(*gfx_obj_id, val.inner),
+            Err(err) => {
+                return Ok(Err(format!(
+                    "Failed to parse GfxObj 0x{:08X}: {}",
+                    gfx_obj_id, err
+                )))
+            }
+        };
+    }
+
+    // Surfaces
+    let mut surface_ids: Vec<u32> = gfx_objs
+        .values()
+        .flat_map(|gfx_obj| gfx_obj.surfaces.iter().copied())
+        .collect();
+    surface_ids.sort();
+    surface_ids.dedup();
+    let mut surface_bufs = read_files_by_ids(ctx, release, &surface_ids, read_count).await?;
+
+    let mut surfaces = HashMap::new();
+    for surface_id in &surface_ids {
+        let buf = match surface_bufs.remove(surface_id) {
+            Some(val) => val,
+            None => return Ok(Err(format!("Surface 0x{:08X} not found", surface_id))),
+        };
+        match DatFile::<Surface>::read(&mut Cursor::new(buf)) {
+            Ok(val) => surfaces.insert(*surface_id, val.inner),
+            Err(err) => {
+                return Ok(Err(format!(
+                    "Failed to parse Surface 0x{:08X}: {}",
+                    surface_id, err
+                )))
+            }
+        };
+    }
+
+    let mut textures = if with_textures {
+        match load_surface_textures(ctx, release, &surfaces, read_count).await? {
+            Ok(val) => val,
+            Err(err) => return Ok(Err(err)),
+        }
+    } else {
+        HashMap::new()
+    };
+
+    let mut model = Model::default();
+    let mut material_indices = HashMap::new();
+    for surface_id in &surface_ids {
+        model.materials.push(model::surface_material(
+            *surface_id,
+            &surfaces[surface_id],
+            textures.remove(surface_id),
+        ));
+        material_indices.insert(*surface_id, model.materials.len() - 1);
+    }
+
+    let mut mesh_indices = HashMap::new();
+    for gfx_obj_id in &gfx_obj_ids {
+        let gfx_obj = &gfx_objs[gfx_obj_id];
+        let materials: Vec<usize> = gfx_obj
+            .surfaces
+            .iter()
+            .map(|surface_id| material_indices[surface_id])
+            .collect();
+        let (vertices, faces) = model::gfx_obj_geometry(gfx_obj);
+        let mesh = match model::build_mesh(
+            format!("0x{:08X}", gfx_obj_id),
+            &vertices,
+            &faces,
+            &materials,
+        ) {
+            Ok(val) => val,
+            Err(err) => return Ok(Err(format!("GfxObj 0x{:08X}: {}", gfx_obj_id, err))),
+        };
+        model.meshes.push(mesh);
+        mesh_indices.insert(*gfx_obj_id, model.meshes.len() - 1);
+    }
+
+    for (gfx_obj_id, translation, rotation, scale) in parts {
+        model.nodes.push(model::Node {
+            name: format!("0x{:08X}", gfx_obj_id),
+            mesh: mesh_indices[&gfx_obj_id],
+            translation,
+            rotation,
+            scale,
+        });
+    }
+
+    Ok(Ok(model))
+}
+
+/// Decode the texture behind each textured Surface as a PNG, keyed by
+/// Surface ID. Surfaces can override their texture's default palette.
+async fn load_surface_textures(
+    ctx: &RouteContext<Context>,
+    release: &DatRelease,
+    surfaces: &HashMap<u32, Surface>,
+    read_count: &mut usize,
+) -> Result<std::result::Result<HashMap<u32, Vec<u8>>, String>> {
+    // Surface -> SurfaceTexture
+    let mut surface_texture_ids: Vec<u32> = surfaces
+        .values()
+        .filter_map(|surface| surface.orig_texture_id)
+        .collect();
+    surface_texture_ids.sort();
+    surface_texture_ids.dedup();
+
+    // SurfaceTexture -> Texture
+    let mut texture_ids = HashMap::new();
+    for (surface_texture_id, buf) in
+        read_files_by_ids(ctx, release, &surface_texture_ids, read_count).await?
+    {
+        match DatFile::<SurfaceTexture>::read(&mut Cursor::new(buf)) {
+            Ok(val) => {
+                if let Some(texture_id) = val.inner.textures.first() {
+                    texture_ids.insert(surface_texture_id, *texture_id);
+                }
+            }
+            Err(err) => {
+                return Ok(Err(format!(
+                    "Failed to parse SurfaceTexture 0x{:08X}: {}",
+                    surface_texture_id, err
+                )))
+            }
+        }
+    }
+
+    let mut unique_texture_ids: Vec<u32> = texture_ids.values().copied().collect();
+    unique_texture_ids.sort();
+    unique_texture_ids.dedup();
+
+    let mut textures = HashMap::new();
+    for (texture_id, buf) in
+        read_files_by_ids(ctx, release, &unique_texture_ids, read_count).await?
+    {
+        match DatFile::<Texture>::read(&mut Cursor::new(buf)) {
+            Ok(val) => textures.insert(texture_id, val.inner),
+            Err(err) => {
+                return Ok(Err(format!(
+                    "Failed to parse Texture 0x{:08X}: {}",
+                    texture_id, err
+                )))
+            }
+        };
+    }
+
+    // Work out which texture and palette each surface uses
+    let mut surface_inputs = Vec::new();
+    for (surface_id, surface) in surfaces {
+        let texture_id = match surface.orig_texture_id.and_then(|id| texture_ids.get(&id)) {
+            Some(val) => *val,
+            None => continue,
+        };
+        let texture = match textures.get(&texture_id) {
+            Some(val) => val,
+            None => return Ok(Err(format!("Texture 0x{:08X} not found", texture_id))),
+        };
+        let palette_id = if texture::is_paletted(texture.format as u32) {
+            surface.orig_palette_id.or(texture.default_palette_id)
+        } else {
+            None
+        };
+        surface_inputs.push((*surface_id, texture_id, palette_id));
+    }
+
+    let mut palette_ids: Vec<u32> = surface_inputs
+        .iter()
+        .filter_map(|(_, _, palette_id)| *palette_id)
+        .collect();
+    palette_ids.sort();
+    palette_ids.dedup();
+
+    let mut palettes = HashMap::new();
+    for (palette_id, buf) in read_files_by_ids(ctx, release, &palette_ids, read_count).await? {
+        match texture::parse_palette(&buf) {
+            Ok(val) => palettes.insert(palette_id, val),
+            Err(err) => {
+                return Ok(Err(format!(
+                    "Failed to parse Palette 0x{:08X}: {}",
+                    palette_id, err
+                )))
+            }
+        };
+    }
+
+    let mut pngs = HashMap::new();
+    for (surface_id, texture_id, palette_id) in surface_inputs {
+        let palette = palette_id.and_then(|id| palettes.get(&id));
+        let image =
+            match texture::decode_texture(&textures[&texture_id], palette.map(|p| p.as_slice())) {
+                Ok(val) => val,
+                Err(err) => {
+                    return Ok(Err(format!(
+                        "Failed to decode Texture 0x{:08X}: {}",
+                        texture_id, err
+                    )))
+                }
+            };
+        match OutputFormat::Png.encode(&image) {
+            Ok(val) => pngs.insert(surface_id, val),
+            Err(err) => return Ok(Err(err)),
+        };
+    }
+
+    Ok(Ok(pngs))
+}
+
+pub async fn sounds_get(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let url = req.url()?;
+    let query_params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+
+    // :id
+    let param_id = match ctx.param("id") {
+        Some(val) => val,
+        None => return Response::error("Must specify sound ID.", 400),
+    };
+
+    let sound_id = match parse_file_id(param_id) {
+        Ok(val) => val,
+        Err(err) => return Response::error(format!("Invalid sound ID: {}", err), 400),
+    };
+
+    // release - defaults to the latest
+    let release_name = release_param(&ctx, &query_params);
+    let release = match get_release(&ctx, release_name).await? {
+        Some(val) => val,
+        None => return release_not_found(release_name),
+    };
+
+    let file = match get_file_by_id(&ctx, &release, &DatDatabaseType::Portal, sound_id).await? {
+        Some(val) => val,
+        None => {
+            return Response::error(
+                format!("Sound not found with ID {} (0x{:X})", sound_id, sound_id),
+                404,
+            )
+        }
+    };
+
+    let file_type = file.resolved_file_type();
+    if file_type != DatFileType::Wave {
+        return Response::error(
+            format!("File 0x{:08X} is a {}, not a Wave", sound_id, file_type),
+            400,
+        );
+    }
+
+    let etag = file.content_hash.as_ref().map(|hash| quote_etag(hash));
+    if let Some(etag) = &etag {
+        if etag_matches(req.headers().get("If-None-Match")?.as_deref(), etag) {
+            return not_modified(etag);
+        }
+    }
+
+    let (buf, read_count) = get_buf_for_file(&ctx, &release, &file).await?;
+    let wave = match sound::parse_wave(&buf) {
+        Ok(val) => val,
+        Err(err) => return Response::error(format!("Failed to parse Wave: {}", err), 400),
+    };
+    let (audio, content_type, extension) = wave.to_audio();
+
+    let mut response = Response::from_body(worker::ResponseBody::Body(audio))?;
+    response.headers_mut().set("Content-Type", content_type)?;
+    response.headers_mut().set(
+        "Content-Disposition",
+        &format!("inline; filename=\"0x{:08X}.{}\"", sound_id, extension),
+    )?;
+    response
+        .headers_mut()
+        .set("X-R2-Read-Count", &read_count.to_string())?;
+    if let Some(etag) = &etag {
+        response.headers_mut().set("ETag", etag)?;
+    }
     Ok(with_cors_headers(response))
 }
 
-pub async fn files_get(url: Url, ctx: RouteContext<()>) -> Result<Response> {
+pub async fn palettes_get(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let url = req.url()?;
     let query_params: HashMap<_, _> = url.query_pairs().into_owned().collect();
-    let param_file_id = match ctx.param("file_id") {
+
+    // :id, optionally with a .png suffix to render swatches
+    let param_id = match ctx.param("id") {
         Some(val) => val,
-        None => return Response::error("Must specify file ID.", 400),
+        None => return Response::error("Must specify palette ID.", 400),
+    };
+    let (param_id, want_png) = match param_id.strip_suffix(".png") {
+        Some(val) => (val, true),
+        None => (param_id.as_str(), false),
     };
 
-    let file_id = match parse_file_id(param_file_id) {
+    let palette_id = match parse_file_id(param_id) {
         Ok(val) => val,
-        Err(err) => return Response::error(format!("Invalid file ID: {}", err), 400),
+        Err(err) => return Response::error(format!("Invalid palette ID: {}", err), 400),
     };
 
-    let file = match get_file_by_id(&ctx, file_id).await? {
+    // release - defaults to the latest
+    let release_name = release_param(&ctx, &query_params);
+    let release = match get_release(&ctx, release_name).await? {
         Some(val) => val,
-        None => {
-            return Response::error(
-                format!("File not found with ID {} (0x{:X})", file_id, file_id),
-                404,
-            )
-        }
+        None => return release_not_found(release_name),
     };
 
-    let (file_data, read_count) = get_buf_for_file(&ctx, &file).await?;
-
-    if query_params.get("format").map(|value| value.as_str()) == Some("json") {
-        let file_type = file.resolved_file_type();
-        let json = match file_type {
-            DatFileType::CharGen | DatFileType::CharacterGenerator => {
-                let mut reader = Cursor::new(file_data.as_slice());
-                reader.set_position(4);
-                let chargen = CharGen::read(&mut reader).map_err(|err| {
-                    worker::Error::RustError(format!(
-                        "Failed to parse file {} (0x{:X}) as {}: {}",
-                        file_id, file_id, file_type, err
-                    ))
-                })?;
-                serde_json::to_string_pretty(&chargen).map_err(|err| {
-                    worker::Error::RustError(format!(
-                        "Failed to serialize file {} (0x{:X}) as JSON: {}",
-                        file_id, file_id, err
-                    ))
-                })?
-            }
-            DatFileType::SpellTable => {
-                let mut reader = Cursor::new(file_data.as_slice());
-                let spell_table = SpellTable::read(&mut reader).map_err(|err| {
-                    worker::Error::RustError(format!(
-                        "Failed to parse file {} (0x{:X}) as {}: {}",
-                        file_id, file_id, file_type, err
-                    ))
-                })?;
-                serde_json::to_string_pretty(&spell_table).map_err(|err| {
-                    worker::Error::RustError(format!(
-                        "Failed to serialize file {} (0x{:X}) as JSON: {}",
-                        file_id, file_id, err
-                    ))
-                })?
-            }
-            _ => {
+    let mut total_read_count: usize = 0;
+    let (file, buf) =
+        match read_file_by_id(&ctx, &release, palette_id, &mut total_read_count).await? {
+            Some(val) => val,
+            None => {
                 return Response::error(
-                    format!("JSON export is not supported for file type {}", file_type),
-                    400,
+                    format!(
+                        "Palette not found with ID {} (0x{:X})",
+                        palette_id, palette_id
+                    ),
+                    404,
                 )
             }
         };
 
+    let file_type = file.resolved_file_type();
+    if file_type != DatFileType::Palette {
+        return Response::error(
+            format!(
+                "File 0x{:08X} is a {}, not a Palette",
+                palette_id, file_type
+            ),
+            400,
+        );
+    }
+
+    let colors = match texture::parse_palette(&buf) {
+        Ok(val) => val,
+        Err(err) => return Response::error(err, 400),
+    };
+
+    let mut response = if want_png {
+        let buf = match palette::render_swatches(&colors)
+            .and_then(|image| OutputFormat::Png.encode(&image))
+        {
+            Ok(val) => val,
+            Err(err) => return Response::error(err, 400),
+        };
+        generate_image(buf, OutputFormat::Png, &format!("0x{:08X}", palette_id))?
+    } else {
+        let json = serde_json::to_string(&palette::PaletteColors::new(palette_id, &colors))?;
         let mut response = Response::from_body(worker::ResponseBody::Body(json.into()))?;
         response
             .headers_mut()
             .set("Content-Type", "application/json")?;
         response
-            .headers_mut()
-            .set("X-R2-Read-Count", &read_count.to_string())?;
-        return Ok(with_cors_headers(response));
-    }
-
-    let mut response = Response::from_bytes(file_data)?;
-    response
-        .headers_mut()
-        .set("Content-Type", "application/octet-stream")?;
+    };
     response
         .headers_mut()
-        .set("X-R2-Read-Count", &read_count.to_string())?;
-
+        .set("X-R2-Read-Count", &total_read_count.to_string())?;
     Ok(with_cors_headers(response))
 }
 
-pub async fn icons_get(url: Url, ctx: RouteContext<()>) -> Result<Response> {
+pub async fn clothing_get(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let url = req.url()?;
     let query_params: HashMap<_, _> = url.query_pairs().into_owned().collect();
 
-    // :icon_id
+    // :id
     let param_id = match ctx.param("id") {
         Some(val) => val,
-        None => return Response::error("Must specify icon ID.", 400),
+        None => return Response::error("Must specify clothing table ID.", 400),
     };
 
-    let param_id_num = match parse_decimal_or_hex_string(param_id) {
+    let clothing_id = match parse_file_id(param_id) {
         Ok(val) => val,
-        Err(err) => return Response::error(err.to_string(), 400),
+        Err(err) => return Response::error(format!("Invalid clothing table ID: {}", err), 400),
     };
 
-    // scale
-    let param_scale = match query_params
-        .get("scale")
+    // setup - optional when the table only covers one
+    let setup_id = match query_params.get("setup").map(|value| parse_file_id(value)) {
+        None => None,
+        Some(Ok(val)) => Some(val),
+        Some(Err(err)) => {
+            return Response::error(
+                format!("Failed to parse query parameter: setup. Error: {}", err),
+                400,
+            )
+        }
+    };
+
+    // palette_template - optional, without it only part changes are resolved
+    let palette_template = match query_params
+        .get("palette_template")
         .map(|value| value.parse::<u32>())
-        .unwrap_or_else(|| Ok(1))
     {
-        Ok(val) => val,
-        Err(err) => {
+        None => None,
+        Some(Ok(val)) => Some(val),
+        Some(Err(err)) => {
             return Response::error(
-                format!("Failed to parse query parameter: scale.{}", err),
+                format!(
+                    "Failed to parse query parameter: palette_template. Error: {}",
+                    err
+                ),
                 400,
-            );
+            )
         }
     };
 
-    // Error for unreasonable scale values
-    if !(1..=8).contains(&param_scale) {
-        return Response::error("Choose a scale value between 1 and 8", 400);
-    }
-
-    // background - accepts ID or ItemType name
-    let param_background = query_params.get("background").cloned();
-
-    // underlay - accepts ID only
-    let param_underlay = match query_params.get("underlay") {
-        Some(value) => match parse_decimal_or_hex_string(value) {
-            Ok(value) => Some(value),
-            Err(err) => {
-                return Response::error(
-                    format!("Failed to parse query parameter: underlay. Error: {}", err),
-                    400,
-                )
-            }
-        },
-        None => None,
+    // shade
+    let shade = match palette::parse_shade(&query_params) {
+        Ok(val) => val.unwrap_or(0.0),
+        Err(err) => return Response::error(err, 400),
     };
 
-    // overlay - accepts ID only
-    let param_overlay = match query_params.get("overlay") {
-        Some(value) => match parse_decimal_or_hex_string(value) {
-            Ok(value) => Some(value),
-            Err(err) => {
-                return Response::error(
-                    format!("Failed to parse query parameter: overlay. Error: {}", err),
-                    400,
-                )
-            }
-        },
-        None => None,
+    // release - defaults to the latest
+    let release_name = release_param(&ctx, &query_params);
+    let release = match get_release(&ctx, release_name).await? {
+        Some(val) => val,
+        None => return release_not_found(release_name),
     };
 
-    // ui_effect - accepts ID or UiEffects name
-    let param_ui_effect = query_params.get("ui_effect").cloned();
-
-    // Helper to load texture by ID; returns the texture and the number of R2 reads performed.
-    async fn load_texture_by_id(
-        ctx: &RouteContext<()>,
-        texture_id: u32,
-    ) -> std::result::Result<(Texture, usize), Response> {
-        let texture_file = match get_file_by_id(ctx, texture_id as i32).await {
-            Ok(Some(file)) => file,
-            _ => {
-                return Err(
-                    match Response::error(
-                        format!("Failed to get DAT file for texture ID {:X}", texture_id),
-                        400,
-                    ) {
-                        Ok(resp) => resp,
-                        Err(e) => return Err(Response::from_html(format!("Error: {}", e)).unwrap()),
-                    },
+    let mut total_read_count: usize = 0;
+    let (file, buf) =
+        match read_file_by_id(&ctx, &release, clothing_id, &mut total_read_count).await? {
+            Some(val) => val,
+            None => {
+                return Response::error(
+                    format!(
+                        "Clothing table not found with ID {} (0x{:X})",
+                        clothing_id, clothing_id
+                    ),
+                    404,
                 )
             }
         };
 
-        let (texture_object, read_count) = match get_buf_for_file(ctx, &texture_file).await {
-            Ok(data) => data,
-            Err(_) => {
-                return Err(
-                    match Response::error(
-                        format!("Failed to read texture file for ID {:X}", texture_id),
-                        400,
-                    ) {
-                        Ok(resp) => resp,
-                        Err(e) => return Err(Response::from_html(format!("Error: {}", e)).unwrap()),
-                    },
-                )
-            }
-        };
-        let mut buf_reader = Cursor::new(texture_object);
-        let texture_file: DatFile<Texture> = match DatFile::read(&mut buf_reader) {
-            Ok(file) => file,
-            Err(_) => {
-                return Err(
-                    match Response::error("Failed to parse texture file".to_string(), 400) {
-                        Ok(resp) => resp,
-                        Err(e) => return Err(Response::from_html(format!("Error: {}", e)).unwrap()),
-                    },
-                )
-            }
-        };
-        Ok((texture_file.inner, read_count))
+    let file_type = file.resolved_file_type();
+    if file_type != DatFileType::ClothingTable {
+        return Response::error(
+            format!(
+                "File 0x{:08X} is a {}, not a ClothingTable",
+                clothing_id, file_type
+            ),
+            400,
+        );
     }
 
-    let mut total_read_count: usize = 0;
+    // The file is already known to be a ClothingTable, so failing to read it
+    // is a problem with the DAT rather than the request
+    let table = clothing::parse_clothing_table(&buf).map_err(|err| {
+        worker::Error::RustError(format!(
+            "Failed to parse ClothingTable 0x{:08X}: {}",
+            clothing_id, err
+        ))
+    })?;
 
-    // Load background texture - can be ID or ItemType name
-    let maybe_background = if let Some(bg_str) = param_background {
-        // Try parsing as ID first, then as ItemType name
-        let bg_texture_id = if let Ok(id) = parse_decimal_or_hex_string(&bg_str) {
-            id as u32
-        } else {
-            // Parse as ItemType name
-            match acprotocol::dat::icon::parse_item_type(&bg_str) {
-                Ok(item_type_value) => {
-                    acprotocol::dat::icon::get_background_from_item_type(item_type_value)
-                }
-                Err(e) => return Response::error(format!("Error parsing background: {}", e), 400),
-            }
+    let (setup_id, parts) = match table.parts_for(setup_id) {
+        Ok(val) => val,
+        Err(err) => return Response::error(err, 400),
+    };
+
+    let mut icon = None;
+    let mut palettes = Vec::new();
+    if let Some(palette_template) = palette_template {
+        let effect = match table.sub_pal_effect(palette_template) {
+            Ok(val) => val,
+            Err(err) => return Response::error(err, 400),
         };
-        match load_texture_by_id(&ctx, bg_texture_id).await {
-            Ok((texture, count)) => {
-                total_read_count += count;
-                Some(texture)
-            }
-            Err(response) => return Ok(response),
+
+        // Pick a Palette from each sub palette's PaletteSet
+        let mut choices: Vec<Option<PaletteChoice>> = effect
+            .sub_palettes
+            .iter()
+            .map(|sub_palette| {
+                Some(PaletteChoice::PaletteSet {
+                    id: sub_palette.palette_set,
+                    shade,
+                })
+            })
+            .collect();
+        if let Err(err) =
+            resolve_palette_sets(&ctx, &release, choices.iter_mut(), &mut total_read_count).await?
+        {
+            return Response::error(err, 400);
         }
-    } else {
-        None
+
+        icon = Some(effect.icon);
+        for (sub_palette, choice) in effect.sub_palettes.iter().zip(choices) {
+            palettes.push(clothing::ResolvedSubPalette {
+                palette_set: sub_palette.palette_set,
+                palette_id: choice.map(|choice| choice.file_id()).unwrap_or_default(),
+                ranges: sub_palette.ranges.clone(),
+            });
+        }
+    }
+
+    let resolved = clothing::ResolvedClothing {
+        clothing_id,
+        setup_id,
+        palette_template,
+        shade,
+        icon,
+        parts: parts.to_vec(),
+        palettes,
     };
 
-    // Load underlay if specified (ID only)
-    let maybe_underlay = if let Some(underlay_id) = param_underlay {
-        match load_texture_by_id(&ctx, underlay_id as u32).await {
-            Ok((texture, count)) => {
-                total_read_count += count;
-                Some(texture)
-            }
-            Err(response) => return Ok(response),
+    let json = serde_json::to_string_pretty(&resolved)?;
+    let mut response = Response::from_body(worker::ResponseBody::Body(json.into()))?;
+    response
+        .headers_mut()
+        .set("Content-Type", "application/json")?;
+    response
+        .headers_mut()
+        .set("X-R2-Read-Count", &total_read_count.to_string())?;
+    Ok(with_cors_headers(response))
+}
+
+pub async fn strings_get(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let url = req.url()?;
+    let query_params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+
+    // :table_id
+    let table_id = match ctx.param("table_id").map(|value| parse_file_id(value)) {
+        Some(Ok(val)) => val,
+        Some(Err(err)) => return Response::error(format!("Invalid table ID: {}", err), 400),
+        None => return Response::error("Must specify table ID.", 400),
+    };
+
+    // :string_id
+    let string_id = match ctx.param("string_id").map(|value| parse_file_id(value)) {
+        Some(Ok(val)) => val,
+        Some(Err(err)) => return Response::error(format!("Invalid string ID: {}", err), 400),
+        None => return Response::error("Must specify string ID.", 400),
+    };
+
+    // release - defaults to the latest
+    let release_name = release_param(&ctx, &query_params);
+    let release = match get_release(&ctx, release_name).await? {
+        Some(val) => val,
+        None => return release_not_found(release_name),
+    };
+
+    // StringTables live in the language DAT and the mappers in the portal, so
+    // the table is looked up across databases like /files/:id
+    let file = match find_file(&ctx, &release, table_id, &query_params).await? {
+        Ok(val) => val,
+        Err((message, status)) => return Response::error(message, status),
+    };
+    let (buf, total_read_count) = get_buf_for_file(&ctx, &release, &file).await?;
+
+    let file_type = file.resolved_file_type();
+    let lookup = match file_type {
+        DatFileType::StringTable => {
+            strings::parse_string_table(&buf).map(|table| table.lookup(string_id))
+        }
+        DatFileType::EnumMapper => {
+            strings::parse_enum_mapper(&buf).map(|mapper| mapper.lookup(string_id))
+        }
+        DatFileType::DualDidMapper => {
+            strings::parse_dual_did_mapper(&buf).map(|mapper| mapper.lookup(string_id))
+        }
+        _ => {
+            return Response::error(
+                format!(
+                    "File 0x{:08X} is a {}, not a StringTable, EnumMapper or DualDidMapper",
+                    table_id, file_type
+                ),
+                400,
+            )
         }
-    } else {
-        None
     };
 
-    // Load overlay if specified (ID only)
-    let maybe_overlay = if let Some(overlay_id) = param_overlay {
-        match load_texture_by_id(&ctx, overlay_id as u32).await {
-            Ok((texture, count)) => {
-                total_read_count += count;
-                Some(texture)
-            }
-            Err(response) => return Ok(response),
+    let lookup = match lookup {
+        Ok(Some(val)) => val,
+        Ok(None) => {
+            return Response::error(
+                format!(
+                    "String not found with ID {} (0x{:X}) in table 0x{:08X}",
+                    string_id, string_id, table_id
+                ),
+                404,
+            )
         }
-    } else {
-        None
+        Err(err) => return Response::error(err, 400),
     };
 
-    // Load UI effect - can be ID or UiEffects name, defaults to transparent
-    let ui_effect = if let Some(effect_str) = param_ui_effect {
-        // Try parsing as ID first, then as UiEffects name
-        let effect_texture_id = if let Ok(id) = parse_decimal_or_hex_string(&effect_str) {
-            id as u32
-        } else {
-            // Parse as UiEffects name
-            match acprotocol::dat::icon::parse_ui_effect(&effect_str) {
-                Ok(ui_effect_flags) => {
-                    acprotocol::dat::icon::get_ui_effect_texture_id(ui_effect_flags)
-                }
-                Err(e) => return Response::error(format!("Error parsing ui_effect: {}", e), 400),
-            }
-        };
-        match load_texture_by_id(&ctx, effect_texture_id).await {
-            Ok((texture, count)) => {
-                total_read_count += count;
-                texture
-            }
-            Err(response) => return Ok(response),
+    let json = serde_json::to_string_pretty(&lookup)?;
+    let mut response = Response::from_body(worker::ResponseBody::Body(json.into()))?;
+    response
+        .headers_mut()
+        .set("Content-Type", "application/json")?;
+    response
+        .headers_mut()
+        .set("X-R2-Read-Count", &total_read_count.to_string())?;
+    Ok(with_cors_headers(response))
+}
+
+/// A Link header pointing at the page after `after`, keeping every other
+/// query parameter
+fn next_page_link(url: &Url, after: &str) -> String {
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .into_owned()
+        .filter(|(key, _)| key != "after")
+        .collect();
+
+    let mut next = url.clone();
+    next.query_pairs_mut()
+        .clear()
+        .extend_pairs(pairs)
+        .append_pair("after", after);
+
+    format!("<{}>; rel=\"next\"", next)
+}
+
+/// The path of a route, pinned to a release when one was asked for. Each
+/// segment is percent-encoded so a release name with /, ? or spaces in it
+/// still gives a working URL.
+fn release_path(url: &Url, release_name: Option<&str>, segments: &[&str]) -> String {
+    let mut path = url.clone();
+    if let Ok(mut path_segments) = path.path_segments_mut() {
+        path_segments.clear();
+        if let Some(name) = release_name {
+            path_segments.extend(["v", name]);
         }
-    } else {
-        // Default effect (transparent)
-        match load_texture_by_id(&ctx, 0x060011C5).await {
-            Ok((texture, count)) => {
-                total_read_count += count;
-                texture
-            }
-            Err(response) => return Ok(response),
+        path_segments.extend(segments);
+    }
+
+    path.path().to_string()
+}
+
+/// Read and parse one of the singleton tables in the portal DAT. The inner
+/// error is a message about the table rather than a failure to talk to D1
+/// or R2.
+async fn read_table<T>(
+    ctx: &RouteContext<Context>,
+    release: &DatRelease,
+    table_id: u32,
+    parse: fn(&[u8]) -> std::result::Result<T, String>,
+    read_count: &mut usize,
+) -> Result<std::result::Result<T, String>> {
+    match read_file_by_id(ctx, release, table_id, read_count).await? {
+        Some((_, buf)) => Ok(parse(&buf)),
+        None => Ok(Err(format!(
+            "Table 0x{:08X} not found in release {}",
+            table_id, release.release.name
+        ))),
+    }
+}
+
+pub async fn spells_index(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let url = req.url()?;
+    let query_params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+
+    let filter = match spells::SpellFilter::from_params(&query_params) {
+        Ok(val) => val,
+        Err(err) => return Response::error(err, 400),
+    };
+
+    let limit = match page_limit(&query_params) {
+        Ok(val) => val,
+        Err(err) => return Response::error(err, 400),
+    };
+
+    // after - the last spell ID of the previous page
+    let after = match query_params.get("after").map(|value| parse_file_id(value)) {
+        None => None,
+        Some(Ok(val)) => Some(val),
+        Some(Err(err)) => {
+            return Response::error(
+                format!("Failed to parse query parameter: after. Error: {}", err),
+                400,
+            )
         }
     };
 
-    // Look up Icon by ID against D1 Database
-    let base_file = match get_file_by_id(&ctx, param_id_num).await? {
+    // release - defaults to the latest
+    let release_name = release_param(&ctx, &query_params);
+    let release = match get_release(&ctx, release_name).await? {
+        Some(val) => val,
+        None => return release_not_found(release_name),
+    };
+
+    let mut total_read_count: usize = 0;
+    let table = match read_table(
+        &ctx,
+        &release,
+        spells::SPELL_TABLE_ID,
+        spells::parse_spell_table,
+        &mut total_read_count,
+    )
+    .await?
+    {
+        Ok(val) => val,
+        Err(err) => return Response::error(err, 500),
+    };
+
+    let start = after.map_or(0, |id| id.saturating_add(1));
+    let mut page: Vec<&spells::Spell> = table
+        .range(start..)
+        .map(|(_, spell)| spell)
+        .filter(|spell| filter.matches(spell))
+        .take(limit + 1)
+        .collect();
+
+    let has_more = page.len() > limit;
+    page.truncate(limit);
+
+    let mut spell_lines = Vec::new();
+    for spell in &page {
+        spell_lines.push(serde_json::to_string(&spell.summary())?);
+    }
+
+    let mut response = Response::ok(spell_lines.join("\n"))?;
+    response
+        .headers_mut()
+        .set("X-R2-Read-Count", &total_read_count.to_string())?;
+    if let (true, Some(last)) = (has_more, page.last()) {
+        response
+            .headers_mut()
+            .set("Link", &next_page_link(&url, &last.id.to_string()))?;
+    }
+    Ok(with_cors_headers(response))
+}
+
+pub async fn spells_get(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let url = req.url()?;
+    let query_params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+
+    // :id
+    let spell_id = match ctx.param("id").map(|value| parse_file_id(value)) {
+        Some(Ok(val)) => val,
+        Some(Err(err)) => return Response::error(format!("Invalid spell ID: {}", err), 400),
+        None => return Response::error("Must specify spell ID.", 400),
+    };
+
+    // release - defaults to the latest
+    let release_name = release_param(&ctx, &query_params);
+    let release = match get_release(&ctx, release_name).await? {
+        Some(val) => val,
+        None => return release_not_found(release_name),
+    };
+
+    let mut total_read_count: usize = 0;
+    let table = match read_table(
+        &ctx,
+        &release,
+        spells::SPELL_TABLE_ID,
+        spells::parse_spell_table,
+        &mut total_read_count,
+    )
+    .await?
+    {
+        Ok(val) => val,
+        Err(err) => return Response::error(err, 500),
+    };
+
+    let spell = match table.get(&spell_id) {
         Some(val) => val,
         None => {
             return Response::error(
-                format!("Failed to get DAT file for file with ID {:?}", param_id_num),
-                400,
+                format!("Spell not found with ID {} (0x{:X})", spell_id, spell_id),
+                404,
             )
         }
     };
 
-    // Create icon
-    let (base_object, base_count) = get_buf_for_file(&ctx, &base_file).await?;
-    total_read_count += base_count;
-    let mut buf_reader = Cursor::new(base_object);
-    let outer_file: DatFile<Texture> = DatFile::read(&mut buf_reader)?;
-    let icon_texture = outer_file.inner;
-
-    let icon: Icon = Icon {
-        width: 32,
-        height: 32,
-        scale: param_scale,
-        background: maybe_background,
-        underlay: maybe_underlay,
-        icon: icon_texture,
-        overlay: maybe_overlay,
-        effect: Some(ui_effect),
+    let components = match read_table(
+        &ctx,
+        &release,
+        spells::SPELL_COMPONENT_TABLE_ID,
+        spells::parse_spell_component_table,
+        &mut total_read_count,
+    )
+    .await?
+    {
+        Ok(val) => val,
+        Err(err) => return Response::error(err, 500),
     };
 
-    // Generate the image or error
-    let mut response = generate_icon(&icon).await?;
+    // Keep the icon on the same release as the spell
+    let icon_url = release_path(
+        &url,
+        release_name,
+        &["icons", &format!("0x{:08X}", spell.icon)],
+    );
+
+    let json = serde_json::to_string_pretty(&spell.detail(&components, icon_url))?;
+    let mut response = Response::from_body(worker::ResponseBody::Body(json.into()))?;
+    response
+        .headers_mut()
+        .set("Content-Type", "application/json")?;
     response
         .headers_mut()
         .set("X-R2-Read-Count", &total_read_count.to_string())?;
     Ok(with_cors_headers(response))
 }
+
+/// A singleton table in a release and the name it's exported under
+#[derive(serde::Serialize)]
+struct TableResponse {
+    #[serde(flatten)]
+    file: crate::db::FileResponse,
+    name: Option<&'static str>,
+}
+
+pub async fn tables_index(req: Request, ctx: RouteContext<Context>) -> Result<Response> {
+    let url = req.url()?;
+    let query_params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+    let release_name = release_param(&ctx, &query_params);
+    let release = match get_release(&ctx, release_name).await? {
+        Some(val) => val,
+        None => return release_not_found(release_name),
+    };
+
+    // The cell DAT's landblock 0x0E00 falls in the same ID range
+    let db = ctx.d1("DATS_DB")?;
+    let statement = db.prepare(
+        "SELECT * FROM files WHERE release_id = ?1 AND database_type = ?2 AND id BETWEEN ?3 AND ?4 ORDER BY id",
+    );
+    let query = statement.bind(&[
+        (release.release.id as f64).into(),
+        (DatDatabaseType::Portal.as_u32() as f64).into(),
+        (tables::FIRST_TABLE_ID as f64).into(),
+        (tables::LAST_TABLE_ID as f64).into(),
+    ])?;
+
+    let results = query.all().await?;
+    let mut table_lines = Vec::new();
+
+    for result in results.results::<crate::db::File>()? {
+        let response = TableResponse {
+            file: (&result).into(),
+            name: exporters::find(&result.resolved_file_type(), result.id as u32)
+                .map(|exporter| exporter.name),
+        };
+        let json = serde_json::to_string(&response)?;
+        table_lines.push(json);
+    }
+
+    let response_text = table_lines.join("\n");
+    let response = Response::ok(response_text)?;
+    Ok(with_cors_headers(response))
+}
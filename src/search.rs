@@ -0,0 +1,53 @@
+/// Turn a `?q=` value into an FTS5 MATCH expression. Every word is quoted so
+/// user input can't be read as FTS5 syntax, and a trailing `*` is kept as a
+/// prefix search, e.g. `strength oth*` becomes `"strength" "oth"*`. Returns
+/// None when there are no words to search for.
+pub fn match_query(q: &str) -> Option<String> {
+    let terms: Vec<String> = q
+        .split_whitespace()
+        .filter_map(|word| {
+            let (word, prefix) = match word.strip_suffix('*') {
+                Some(stripped) => (stripped.trim_end_matches('*'), true),
+                None => (word, false),
+            };
+            if word.is_empty() {
+                return None;
+            }
+
+            let quoted = format!("\"{}\"", word.replace('"', "\"\""));
+            Some(if prefix { quoted + "*" } else { quoted })
+        })
+        .collect();
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_query() {
+        assert_eq!(match_query("Strength"), Some("\"Strength\"".to_string()));
+        assert_eq!(
+            match_query("  strength   oth* "),
+            Some("\"strength\" \"oth\"*".to_string())
+        );
+        assert_eq!(
+            match_query("say \"hi\" OR NOT"),
+            Some("\"say\" \"\"\"hi\"\"\" \"OR\" \"NOT\"".to_string())
+        );
+        assert_eq!(match_query("icon**"), Some("\"icon\"*".to_string()));
+    }
+
+    #[test]
+    fn test_match_query_empty() {
+        assert_eq!(match_query(""), None);
+        assert_eq!(match_query("   "), None);
+        assert_eq!(match_query("* **"), None);
+    }
+}